@@ -232,6 +232,7 @@ contract MatchManagerInstantiator is MatchManagerInterface, Decorated {
     function getState(uint256 _index, address _user) public view returns
         ( uint256[9] memory _uintValues,
           address[3] memory _addressValues,
+          uint256[4] memory _lastMatchScores,
           bool registered,
           bytes32 currentState
         ) {
@@ -259,6 +260,7 @@ contract MatchManagerInstantiator is MatchManagerInterface, Decorated {
             return (
                 uintValues,
                 addressValues,
+                getLastMatchScores(_index, _user),
                 instance[_index].registered[_user][i.currentEpoch],
                 getCurrentState(_index)
             );
@@ -271,7 +273,7 @@ contract MatchManagerInstantiator is MatchManagerInterface, Decorated {
             address[] memory a;
             uint256[] memory i;
 
-            if (instance[_index].currentEpoch == 0 && (instance[_index].unmatchedPlayer == _user || !instance[_index].registered[_user][0])) {
+            if (!hasMatch(_index, _user)) {
                 a = new address[](0);
                 i = new uint256[](0);
                 return (a, i);
@@ -285,6 +287,34 @@ contract MatchManagerInstantiator is MatchManagerInterface, Decorated {
 
         }
 
+        /// @notice Scores and commit times of the players of the last match of _user
+        /// @return claimer score, claimer commit time, challenger score and
+        /// challenger commit time, all zero if _user hasn't played a match yet
+        function getLastMatchScores(uint256 _index, address _user)
+            internal view returns (uint256[4] memory)
+        {
+            uint256[4] memory scores;
+            if (!hasMatch(_index, _user)) {
+                return scores;
+            }
+
+            // the match addresses are [challenger, claimer, machine]
+            (address[3] memory matchAddresses,,,) = mi.getState(instance[_index].lastMatchIndex[_user], _user);
+            RevealInterface parent = RevealInterface(instance[_index].parentAddress);
+            uint256 parentInstance = instance[_index].parentInstance;
+
+            scores[0] = parent.getScore(parentInstance, matchAddresses[1]);
+            scores[1] = parent.getCommitTime(parentInstance, matchAddresses[1]);
+            scores[2] = parent.getScore(parentInstance, matchAddresses[0]);
+            scores[3] = parent.getCommitTime(parentInstance, matchAddresses[0]);
+            return scores;
+        }
+
+        /// @notice Whether _user has been matched at least once
+        function hasMatch(uint256 _index, address _user) internal view returns (bool) {
+            return !(instance[_index].currentEpoch == 0 && (instance[_index].unmatchedPlayer == _user || !instance[_index].registered[_user][0]));
+        }
+
         function getCurrentState(uint256 _index) public view
             onlyInstantiated(_index)
             returns (bytes32)
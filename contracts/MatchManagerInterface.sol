@@ -54,6 +54,7 @@ contract MatchManagerInterface is Instantiator {
     function getState(uint256 _index, address) public view returns
         ( uint256[9] memory _uintValues,
           address[3] memory _addressValues,
+          uint256[4] memory _lastMatchScores,
           bool registered,
           bytes32 currentState
         ); 
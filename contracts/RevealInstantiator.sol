@@ -56,11 +56,13 @@ contract RevealInstantiator is RevealInterface, Decorated {
         bytes32 initialHash;
         bytes32 finalHash;
         bytes32 commitHash;
+        bytes32 logHash;
+        uint256 commitTime; // time of the last commit, for tie breaks
     }
 
 
     event logCommited(uint256 index, address player, bytes32 commitHash);
-    event logRevealed(uint256 index, address player, bytes32 commitHash);
+    event logRevealed(uint256 index, address player, bytes32 logHash);
 
     mapping(uint256 => RevealCtx) internal instance;
 
@@ -106,7 +108,7 @@ contract RevealInstantiator is RevealInterface, Decorated {
 
     /// @notice Submits a commit.
     /// @param _index index of reveal that is being interacted with.
-    /// @param _commitHash hash of log + the users address to be revealed later.
+    /// @param _commitHash keccak256 of the log hash, the users address and a secret salt, to be revealed later.
     function commit(uint256 _index, bytes32 _commitHash) public {
         require(instance[_index].currentState == state.CommitPhase, "State has to be commit phase");
         // if commit deadline is over, change the state to reveal
@@ -123,17 +125,22 @@ contract RevealInstantiator is RevealInterface, Decorated {
         }
 
         instance[_index].players[msg.sender].commitHash = _commitHash;
+        instance[_index].players[msg.sender].commitTime = now;
 
         emit logCommited(_index, msg.sender, _commitHash);
     }
 
     /// @notice reveals the log and extracts its information.
     /// @param _index index of reveal that is being interacted with.
+    /// @param _logHash hash of the log that was committed
+    /// @param _salt salt used when committing
     /// @param _score that should be contained in the log
     /// @param _finalHash final hash of the machine after that log has been proccessed.
     /// @param _logDriveSiblings siblings for the log drive
     /// @param _scoreDriveSiblings siblings for the log drive
     function reveal(uint256 _index,
+                    bytes32 _logHash,
+                    bytes32 _salt,
                     uint64 _score,
                     bytes32 _finalHash,
                     bytes32[] memory _logDriveSiblings,
@@ -147,10 +154,14 @@ contract RevealInstantiator is RevealInterface, Decorated {
 
         require(instance[_index].currentState == state.RevealPhase, "State has to be reveal phase");
         require(!instance[_index].players[msg.sender].hasRevealed, "Player can only reveal one commit");
+        require(
+            keccak256(abi.encodePacked(_logHash, msg.sender, _salt)) == instance[_index].players[msg.sender].commitHash,
+            "Log hash and salt do not open the commitment"
+        );
 
         require(
             li.isLogAvailable(
-                instance[_index].players[msg.sender].commitHash,
+                _logHash,
                 uint64(instance[_index].logDriveLogSize)),
             "Hash of the log must be available at Logger-dlib"
         );
@@ -174,13 +185,14 @@ contract RevealInstantiator is RevealInterface, Decorated {
         require(Merkle.getRootWithDrive(uint64_values[2], uint64_values[3], scoreWordHash, _scoreDriveSiblings) == _finalHash, "Score is not contained in the final hash");
 
         // Update pristine hash with flash drive containing logs
-        instance[_index].players[msg.sender].initialHash = Merkle.getRootWithDrive(uint64_values[0], uint64_values[1], instance[_index].players[msg.sender].commitHash, _logDriveSiblings);
+        instance[_index].players[msg.sender].initialHash = Merkle.getRootWithDrive(uint64_values[0], uint64_values[1], _logHash, _logDriveSiblings);
 
+        instance[_index].players[msg.sender].logHash = _logHash;
         instance[_index].players[msg.sender].score = _score;
         instance[_index].players[msg.sender].finalHash = _finalHash;
         instance[_index].players[msg.sender].hasRevealed = true;
 
-        emit logRevealed(_index, msg.sender, instance[_index].players[msg.sender].commitHash);
+        emit logRevealed(_index, msg.sender, _logHash);
     }
 
     /// @notice Change state for final, if the deadlines were met.
@@ -203,6 +215,11 @@ contract RevealInstantiator is RevealInterface, Decorated {
         return instance[_index].players[_playerAddr].score;
     }
 
+    function getCommitTime(uint256 _index, address _playerAddr) public view returns (uint256) {
+        require(playerExist(_index, _playerAddr), "Player has to exist");
+        return instance[_index].players[_playerAddr].commitTime;
+    }
+
     function getFinalHash(uint256 _index, address _playerAddr) public view returns (bytes32) {
         require(playerExist(_index, _playerAddr), "Player has to exist");
         return instance[_index].players[_playerAddr].finalHash;
@@ -210,7 +227,7 @@ contract RevealInstantiator is RevealInterface, Decorated {
 
     function getLogHash(uint256 _index, address _playerAddr) public view returns (bytes32) {
         require(playerExist(_index, _playerAddr), "Player has to exist");
-        return instance[_index].players[_playerAddr].commitHash;
+        return instance[_index].players[_playerAddr].logHash;
     }
 
     function getInitialHash(uint256 _index, address _playerAddr) public view returns (bytes32) {
@@ -234,7 +251,10 @@ contract RevealInstantiator is RevealInterface, Decorated {
     function getState(uint256 _index, address _user)
     public view returns (
             uint256[6] memory _uintValues,
+            uint256 scoreDriveLogSize,
+            bytes32 templateHash,
             bytes32 commitHash,
+            bytes32 logHash,
 
             bool revealed,
             bool logAvailable,
@@ -254,9 +274,15 @@ contract RevealInstantiator is RevealInterface, Decorated {
 
         return (
             uintValues,
+            i.scoreDriveLogSize,
+            i.templateHash,
             instance[_index].players[_user].commitHash,
+            // the log hash is only known once the commitment is opened,
+            // so is the availability of the log
+            instance[_index].players[_user].logHash,
             instance[_index].players[_user].hasRevealed,
-            li.isLogAvailable(instance[_index].players[_user].commitHash, uint64(i.logDriveLogSize)),
+            instance[_index].players[_user].hasRevealed &&
+                li.isLogAvailable(instance[_index].players[_user].logHash, uint64(i.logDriveLogSize)),
 
             getCurrentState(_index)
         );
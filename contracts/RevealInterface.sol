@@ -46,6 +46,8 @@ contract RevealInterface is Instantiator {
 
     function getScore(uint256 _index, address _playerAddr) public view returns (uint256);
 
+    function getCommitTime(uint256 _index, address _playerAddr) public view returns (uint256);
+
     function getLogHash(uint256 _index, address _playerAddr) public view returns (bytes32);
 
     function getInitialHash(uint256 _index, address _playerAddr) public view returns (bytes32);
@@ -63,6 +65,8 @@ contract RevealInterface is Instantiator {
     function getState(uint256 _index, address _user)
     public view returns (
         uint256[6] memory _uintValues,
+        uint256 scoreDriveLogSize,
+        bytes32 templateHash,
         bytes32 logHash,
 
         bool revealed,
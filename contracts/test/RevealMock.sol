@@ -53,6 +53,7 @@ contract RevealMock is Decorated, RevealMockInterface {
         bytes32 initialHash;
         bytes32 finalHash;
         bytes32 logHash;
+        uint256 commitTime;
     }
 
     event logCommited(uint256 index, address player, bytes32 logHash, uint256 block);
@@ -72,6 +73,7 @@ contract RevealMock is Decorated, RevealMockInterface {
             newPlayer.finalHash = _finalHashes[i];
             newPlayer.initialHash = _initialHashes[i];
             newPlayer.logHash = _logHashes[i];
+            newPlayer.commitTime = now;
             newPlayer.hasRevealed = true;
 
             instance[_index].players[_playerAddresses[i]] = newPlayer;
@@ -147,6 +149,10 @@ contract RevealMock is Decorated, RevealMockInterface {
         return instance[_index].players[_playerAddr].score;
     }
 
+    function getCommitTime(uint256 _index, address _playerAddr) public returns (uint256) {
+        return instance[_index].players[_playerAddr].commitTime;
+    }
+
     function getLogHash(uint256 _index, address _playerAddr) public returns (bytes32) {
         require(playerExist(_index, _playerAddr), "Player has to exist");
         return instance[_index].players[_playerAddr].logHash;
@@ -50,6 +50,7 @@ contract RevealMockInterface is Instantiator {
     function getInitialHash(uint256 _index, address _playerAddr) public returns (bytes32);
 
     function getScore(uint256 _index, address _playerAddr) public returns (uint256);
+    function getCommitTime(uint256 _index, address _playerAddr) public returns (uint256);
     function getFinalHash(uint256 _index, address _playerAddr) public returns (bytes32);
     function playerExist(uint256 _index, address _playerAddr) public returns (bool);
     function getCurrentState(uint256 _index, address concernedAddress) public view returns (bytes32);
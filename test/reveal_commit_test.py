@@ -56,6 +56,10 @@ templateHash = proof_data["proofs"]["level_after_write"]["proof"]["root_hash"]
 logHash = proof_data["proofs"]["log_after_write"]["proof"]["target_hash"]
 
 initialHash = proof_data["proofs"]["log_after_write"]["proof"]["root_hash"]
+
+salt = Web3.toHex(os.urandom(32))
+commitHash = Web3.solidityKeccak(['bytes32', 'address', 'bytes32'], [logHash, w3.eth.coinbase, salt])
+
 finalHash = proof_data["proofs"]["score_at_completion"]["proof"]["root_hash"]
 
 score = proof_data["proofs"]["score_at_completion"]["score"]
@@ -84,7 +88,7 @@ print(tx_receipt)
 # # # # # # # # # # # # # # # # #
 # COMMIT PHASE
 # # # # # # # # # # # # # # # # #
-commit_tx_hash = ri.functions.commit(0, logHash).transact({'from': w3.eth.coinbase, 'gas': 6283185})
+commit_tx_hash = ri.functions.commit(0, commitHash).transact({'from': w3.eth.coinbase, 'gas': 6283185})
 
 print(commit_tx_hash)
 
@@ -94,9 +98,9 @@ print(commit_tx_receipt)
 
 ri_filter = ri.events.logCommited.createFilter(fromBlock='latest')
 ri_index = ri_filter.get_all_entries()[0]['args']['index']
-ri_logHash = ri_filter.get_all_entries()[0]['args']['logHash']
+ri_commitHash = ri_filter.get_all_entries()[0]['args']['commitHash']
 print(ri_index)
-print(ri_logHash)
+print(ri_commitHash)
 
 # # # # # # # # # # # # # # # # #
 # REVEAL PHASE
@@ -116,7 +120,7 @@ print("LOGGER MOCK RECEIPT")
 print(logger_mock_tx_receipt)
 
 # REVEAL LOG
-reveal_tx_hash = ri.functions.reveal(0, score, finalHash, logDriveSiblings, scoreDriveSiblings).transact({'from': w3.eth.coinbase, 'gas': 6283185})
+reveal_tx_hash = ri.functions.reveal(0, logHash, salt, score, finalHash, logDriveSiblings, scoreDriveSiblings).transact({'from': w3.eth.coinbase, 'gas': 6283185})
 
 reveal_tx_receipt = w3.eth.waitForTransactionReceipt(reveal_tx_hash )
 print("REVEAL RECEIPT")
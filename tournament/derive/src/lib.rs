@@ -0,0 +1,319 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Derive for the DApp contexts decoded from a contract's `getState`.
+//!
+//! The context struct declares the `getState` return layout once, in the
+//! order the contract returns it, and tags every field with the return
+//! value it comes from:
+//!
+//! ```ignore
+//! #[derive(Serialize, Debug, DAppContext)]
+//! #[get_state(
+//!     _uintValues(U256Array3),
+//!     currentState(String32Field)
+//! )]
+//! pub struct FooCtx {
+//!     #[slot(_uintValues)]
+//!     pub first: U256,
+//!     #[slot(_uintValues)]
+//!     pub second: U256,
+//!     #[slot(_uintValues)]
+//!     pub third: U256,
+//!     #[slot(currentState)]
+//!     pub current_state: String,
+//! }
+//! ```
+//!
+//! Fields sharing an array slot take its positions in declaration order, so
+//! they must be declared in the same order as the contract's array literal.
+//! The derive generates the positional `FooCtxParsed` tuple struct,
+//! `From<FooCtxParsed> for FooCtx` and `FooCtx::to_json_data`, which
+//! serializes the context back into the `json_data` layout it is parsed
+//! from. A slot with the wrong number of fields is a compile error.
+
+#![recursion_limit = "128"]
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use syn::spanned::Spanned;
+use syn::{Data, DeriveInput, Fields, Ident, Lit, Meta, NestedMeta};
+
+struct Slot {
+    name: Ident,
+    field_type: Ident,
+    solidity_type: String,
+    // None for scalar fields, the array length otherwise
+    length: Option<usize>,
+    fields: Vec<Ident>,
+}
+
+#[proc_macro_derive(DAppContext, attributes(get_state, slot))]
+pub fn derive_dapp_context(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let vis = &input.vis;
+    let parsed_name = Ident::new(&format!("{}Parsed", name), name.span());
+
+    let mut slots = parse_layout(input)?;
+
+    let fields = match input.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    name.span(),
+                    "DAppContext can only be derived for structs with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                name.span(),
+                "DAppContext can only be derived for structs",
+            ));
+        }
+    };
+
+    for field in fields {
+        let field_name = field.ident.clone().expect("named field");
+        let slot_name = parse_field_slot(field)?;
+        let slot = slots
+            .iter_mut()
+            .find(|s| s.name == slot_name)
+            .ok_or_else(|| {
+                syn::Error::new(
+                    slot_name.span(),
+                    format!("`{}` is not declared in #[get_state(...)]", slot_name),
+                )
+            })?;
+        slot.fields.push(field_name);
+    }
+
+    for slot in &slots {
+        let expected = slot.length.unwrap_or(1);
+        if slot.fields.len() != expected {
+            return Err(syn::Error::new(
+                slot.name.span(),
+                format!(
+                    "`{}` is a {} and needs {} field(s), but {} are mapped to it",
+                    slot.name,
+                    slot.field_type,
+                    expected,
+                    slot.fields.len()
+                ),
+            ));
+        }
+    }
+
+    let parsed_types: Vec<&Ident> = slots.iter().map(|s| &s.field_type).collect();
+
+    let mut assignments = vec![];
+    let mut json_values = vec![];
+    for (i, slot) in slots.iter().enumerate() {
+        let index = syn::Index::from(i);
+        let slot_name = slot.name.to_string();
+        let solidity_type = &slot.solidity_type;
+        let value = match slot.length {
+            Some(_) => {
+                let mut elements = vec![];
+                for (position, field) in slot.fields.iter().enumerate() {
+                    assignments.push(quote! { #field: parsed.#index.value[#position] });
+                    elements.push(quote! { serde_json::to_value(&self.#field).unwrap() });
+                }
+                quote! { serde_json::Value::Array(vec![#(#elements),*]) }
+            }
+            None => {
+                let field = &slot.fields[0];
+                assignments.push(quote! { #field: parsed.#index.value });
+                if slot.field_type == "String32Field" {
+                    // the contract returns it as a zero-padded bytes32
+                    quote! {{
+                        let mut bytes = self.#field.as_bytes().to_vec();
+                        bytes.resize(32, 0);
+                        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                        serde_json::Value::String(format!("0x{}", hex))
+                    }}
+                } else {
+                    quote! { serde_json::to_value(&self.#field).unwrap() }
+                }
+            }
+        };
+        json_values.push(quote! {{
+            let mut entry = serde_json::Map::new();
+            entry.insert("name".to_string(), serde_json::Value::String(#slot_name.to_string()));
+            entry.insert("type".to_string(), serde_json::Value::String(#solidity_type.to_string()));
+            entry.insert("value".to_string(), #value);
+            serde_json::Value::Object(entry)
+        }});
+    }
+
+    Ok(quote! {
+        #[derive(Serialize, Deserialize)]
+        #vis struct #parsed_name(#(#vis #parsed_types),*);
+
+        impl From<#parsed_name> for #name {
+            fn from(parsed: #parsed_name) -> #name {
+                #name {
+                    #(#assignments,)*
+                }
+            }
+        }
+
+        impl #name {
+            /// Serializes the context in the `json_data` layout of the
+            /// contract's `getState`, the inverse of parsing it.
+            #vis fn to_json_data(&self) -> String {
+                serde_json::Value::Array(vec![#(#json_values),*]).to_string()
+            }
+        }
+    })
+}
+
+fn parse_layout(input: &DeriveInput) -> syn::Result<Vec<Slot>> {
+    let attr = input
+        .attrs
+        .iter()
+        .find(|a| a.path.is_ident("get_state"))
+        .ok_or_else(|| {
+            syn::Error::new(
+                input.ident.span(),
+                "DAppContext needs a #[get_state(...)] attribute describing the layout",
+            )
+        })?;
+
+    let list = match attr.parse_meta()? {
+        Meta::List(list) => list,
+        meta => {
+            return Err(syn::Error::new(
+                meta.span(),
+                "expected #[get_state(name(FieldType), ...)]",
+            ));
+        }
+    };
+
+    let mut slots = vec![];
+    for nested in list.nested.iter() {
+        let slot = match nested {
+            NestedMeta::Meta(Meta::List(slot)) if slot.nested.len() == 1 => slot,
+            _ => {
+                return Err(syn::Error::new(
+                    nested.span(),
+                    "expected name(FieldType), e.g. currentState(String32Field)",
+                ));
+            }
+        };
+        let name = single_ident(&slot.path)?;
+        let field_type = match slot.nested.first() {
+            Some(NestedMeta::Meta(Meta::Path(path))) => single_ident(path)?,
+            _ => {
+                return Err(syn::Error::new(
+                    slot.nested.span(),
+                    "expected a dispatcher field type, e.g. U256Array9",
+                ));
+            }
+        };
+        let (solidity_type, length) = solidity_type(&field_type)?;
+        slots.push(Slot {
+            name,
+            field_type,
+            solidity_type,
+            length,
+            fields: vec![],
+        });
+    }
+
+    Ok(slots)
+}
+
+fn parse_field_slot(field: &syn::Field) -> syn::Result<Ident> {
+    let attr = field
+        .attrs
+        .iter()
+        .find(|a| a.path.is_ident("slot"))
+        .ok_or_else(|| {
+            syn::Error::new(
+                field.span(),
+                "every field needs a #[slot(...)] naming its getState return value",
+            )
+        })?;
+
+    match attr.parse_meta()? {
+        Meta::List(ref list) if list.nested.len() == 1 => match list.nested.first() {
+            Some(NestedMeta::Meta(Meta::Path(path))) => single_ident(path),
+            Some(NestedMeta::Lit(Lit::Str(s))) => Ok(Ident::new(&s.value(), s.span())),
+            _ => Err(syn::Error::new(list.span(), "expected #[slot(name)]")),
+        },
+        meta => Err(syn::Error::new(meta.span(), "expected #[slot(name)]")),
+    }
+}
+
+fn single_ident(path: &syn::Path) -> syn::Result<Ident> {
+    path.get_ident()
+        .cloned()
+        .ok_or_else(|| syn::Error::new(path.span(), "expected a single identifier"))
+}
+
+// maps the dispatcher field wrappers to the solidity type they decode
+fn solidity_type(field_type: &Ident) -> syn::Result<(String, Option<usize>)> {
+    let name = field_type.to_string();
+    let scalars = [
+        ("U256Field", "uint256"),
+        ("AddressField", "address"),
+        ("Bytes32Field", "bytes32"),
+        ("BoolField", "bool"),
+        ("String32Field", "bytes32"),
+    ];
+    let arrays = [
+        ("U256Array", "uint256"),
+        ("AddressArray", "address"),
+        ("Bytes32Array", "bytes32"),
+    ];
+
+    if let Some(&(_, solidity)) = scalars.iter().find(|&&(n, _)| n == name) {
+        return Ok((solidity.to_string(), None));
+    }
+
+    // arrays are the wrapper name followed by their length, e.g. U256Array9
+    let prefix = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    if let Some(&(_, solidity)) = arrays.iter().find(|&&(n, _)| n == prefix) {
+        if let Ok(length) = name[prefix.len()..].parse::<usize>() {
+            return Ok((format!("{}[{}]", solidity, length), Some(length)));
+        }
+    }
+
+    Err(syn::Error::new(
+        field_type.span(),
+        format!("unsupported dispatcher field type `{}`", field_type),
+    ))
+}
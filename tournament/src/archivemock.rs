@@ -0,0 +1,206 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Scripted emulator and logger responses, to drive `react` without the
+//! services.
+//!
+//! The responses are keyed exactly as the DApps look them up in the
+//! `Archive`, so a script states what each service answers and
+//! `ArchiveMock::react` keeps reacting, answering every request from the
+//! script, the way the dispatcher would after a round trip to the service.
+//! Every request is recorded in order, and the ones the script had no
+//! answer for are reported as missing.
+
+use super::dispatcher::{Archive, Reaction};
+use super::error::*;
+use super::ethereum_types::H256;
+use super::simulator::{ServiceCall, ServiceOracle, ServiceResponse};
+use super::{
+    build_session_proof_key, build_session_read_key, build_session_run_key, EMULATOR_METHOD_NEW,
+    EMULATOR_METHOD_PROOF, EMULATOR_METHOD_READ, EMULATOR_METHOD_RUN, EMULATOR_SERVICE_NAME,
+    LOGGER_METHOD_DOWNLOAD, LOGGER_METHOD_SUBMIT, LOGGER_SERVICE_NAME,
+};
+use std::collections::HashMap;
+
+pub struct ArchiveMock {
+    archive: Archive,
+    script: HashMap<(String, String, String), ServiceResponse>,
+    calls: Vec<ServiceCall>,
+    missing: Vec<ServiceCall>,
+}
+
+impl ArchiveMock {
+    pub fn new() -> Result<ArchiveMock> {
+        Ok(ArchiveMock {
+            archive: Archive::new()?,
+            script: HashMap::new(),
+            calls: vec![],
+            missing: vec![],
+        })
+    }
+
+    /// Scripts the response of any service request
+    pub fn script(
+        &mut self,
+        service: &str,
+        key: &str,
+        method: &str,
+        response: ServiceResponse,
+    ) -> &mut ArchiveMock {
+        self.script.insert(
+            (service.to_string(), key.to_string(), method.to_string()),
+            response,
+        );
+        self
+    }
+
+    pub fn on_new_session(&mut self, id: &str, response: ServiceResponse) -> &mut ArchiveMock {
+        self.script(EMULATOR_SERVICE_NAME, id, EMULATOR_METHOD_NEW, response)
+    }
+
+    pub fn on_run(&mut self, id: &str, times: Vec<u64>, response: ServiceResponse) -> &mut ArchiveMock {
+        let key = build_session_run_key(id.to_string(), times);
+        self.script(EMULATOR_SERVICE_NAME, &key, EMULATOR_METHOD_RUN, response)
+    }
+
+    pub fn on_proof(
+        &mut self,
+        id: &str,
+        time: u64,
+        address: u64,
+        log2_size: u64,
+        response: ServiceResponse,
+    ) -> &mut ArchiveMock {
+        let key = build_session_proof_key(id.to_string(), time, address, log2_size);
+        self.script(EMULATOR_SERVICE_NAME, &key, EMULATOR_METHOD_PROOF, response)
+    }
+
+    pub fn on_read(
+        &mut self,
+        id: &str,
+        time: u64,
+        address: u64,
+        length: u64,
+        response: ServiceResponse,
+    ) -> &mut ArchiveMock {
+        let key = build_session_read_key(id.to_string(), time, address, length);
+        self.script(EMULATOR_SERVICE_NAME, &key, EMULATOR_METHOD_READ, response)
+    }
+
+    /// Submission of the log at `path`, keyed by the path
+    pub fn on_submit(&mut self, path: &str, response: ServiceResponse) -> &mut ArchiveMock {
+        self.script(LOGGER_SERVICE_NAME, path, LOGGER_METHOD_SUBMIT, response)
+    }
+
+    /// Download of the log with the given root, keyed by the root
+    pub fn on_download(&mut self, root: &H256, response: ServiceResponse) -> &mut ArchiveMock {
+        let key = format!("{:x}", root);
+        self.script(LOGGER_SERVICE_NAME, &key, LOGGER_METHOD_DOWNLOAD, response)
+    }
+
+    /// Calls `react` until it no longer misses a response the script has.
+    /// Returns the last result, which is the miss error if the script had
+    /// no answer for the request.
+    pub fn react<F>(&mut self, mut react: F) -> Result<Reaction>
+    where
+        F: FnMut(&Archive) -> Result<Reaction>,
+    {
+        loop {
+            let result = react(&self.archive);
+            let call = match result {
+                Err(ref e) => match *e.kind() {
+                    ErrorKind::ResponseMissError(ref service, ref key, ref method, ref request) => {
+                        ServiceCall {
+                            service: service.clone(),
+                            key: key.clone(),
+                            method: method.clone(),
+                            request: request.clone(),
+                        }
+                    }
+                    _ => return result,
+                },
+                _ => return result,
+            };
+
+            // an answered request missed again would loop forever
+            if self.calls.contains(&call) {
+                return result;
+            }
+
+            match self.answer(&call) {
+                Some(response) => self.archive.insert_response(
+                    call.service.clone(),
+                    call.key.clone(),
+                    call.method.clone(),
+                    response,
+                ),
+                None => return result,
+            }
+        }
+    }
+
+    /// Every request made so far, in order, answered or not
+    pub fn calls(&self) -> &[ServiceCall] {
+        &self.calls
+    }
+
+    /// The requests the script had no answer for
+    pub fn missing(&self) -> &[ServiceCall] {
+        &self.missing
+    }
+
+    /// `service method(key)` of every request made so far, for asserting
+    /// the sequence of service calls
+    pub fn call_sequence(&self) -> Vec<String> {
+        self.calls.iter().map(|c| c.to_string()).collect()
+    }
+
+    fn answer(&mut self, call: &ServiceCall) -> Option<ServiceResponse> {
+        self.calls.push(call.clone());
+        let response = self
+            .script
+            .get(&(call.service.clone(), call.key.clone(), call.method.clone()))
+            .cloned();
+        if response.is_none() {
+            warn!("No scripted response for {}", call);
+            self.missing.push(call.clone());
+        }
+        response
+    }
+}
+
+/// Lets the simulator answer the players' requests from a script
+impl ServiceOracle for ArchiveMock {
+    fn respond(
+        &mut self,
+        service: &str,
+        key: &str,
+        method: &str,
+        request: &[u8],
+    ) -> Option<ServiceResponse> {
+        self.answer(&ServiceCall {
+            service: service.to_string(),
+            key: key.to_string(),
+            method: method.to_string(),
+            request: request.to_vec(),
+        })
+    }
+}
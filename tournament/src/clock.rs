@@ -0,0 +1,316 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Sources of the current time for the deadline logic.
+//!
+//! The contracts compare deadlines with `now`, the timestamp of the block
+//! the transaction lands in, so that is the time the dispatcher should
+//! use too. The local clock is only right as long as the node is in sync.
+//! The block clock polls the Ethereum node the dispatcher talks to for its
+//! latest block header, in the background.
+
+use super::config::tournament_config;
+use super::error::*;
+use super::http;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub trait Clock: Send + Sync {
+    /// Seconds since `UNIX_EPOCH`
+    fn now(&self) -> Result<u64>;
+}
+
+/// The local system time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Result<u64> {
+        Ok(SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .chain_err(|| "System time before UNIX_EPOCH")?
+            .as_secs())
+    }
+}
+
+/// Where the block clock reads the latest block header, the `block_clock`
+/// entry of the tournament configuration
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockClockConfig {
+    /// JSON-RPC url of the Ethereum node
+    #[serde(default = "default_node_url")]
+    pub url: String,
+    /// Seconds between two polls of the latest header
+    #[serde(default = "default_poll_seconds")]
+    pub poll_seconds: u64,
+}
+
+fn default_node_url() -> String {
+    "http://localhost:8545".to_string()
+}
+
+fn default_poll_seconds() -> u64 {
+    5
+}
+
+impl Default for BlockClockConfig {
+    fn default() -> BlockClockConfig {
+        BlockClockConfig {
+            url: default_node_url(),
+            poll_seconds: default_poll_seconds(),
+        }
+    }
+}
+
+/// Time of the latest block seen, advanced by the local time elapsed since
+/// it was seen, so the clock keeps moving between blocks
+#[derive(Default)]
+pub struct BlockClock {
+    latest: Mutex<Option<(u64, Instant)>>,
+}
+
+impl BlockClock {
+    /// A clock only fed through `update`
+    pub fn new() -> BlockClock {
+        BlockClock::default()
+    }
+
+    /// A clock fed by a background thread polling `node` for its latest
+    /// block header, so reading it never waits for the node
+    pub fn polling(node: BlockClockConfig) -> Arc<BlockClock> {
+        let clock = Arc::new(BlockClock::new());
+        let polled = Arc::downgrade(&clock);
+        thread::spawn(move || {
+            // until the clock is dropped
+            while let Some(clock) = polled.upgrade() {
+                match latest_block_timestamp(&node) {
+                    Ok(timestamp) => clock.update(timestamp),
+                    Err(e) => warn!("Could not poll the latest block: {}", e),
+                }
+                drop(clock);
+                thread::sleep(Duration::from_secs(node.poll_seconds.max(1)));
+            }
+        });
+        clock
+    }
+
+    /// Feeds the timestamp of a new block header
+    pub fn update(&self, block_timestamp: u64) {
+        let mut latest = self.latest.lock().unwrap();
+        match *latest {
+            // ignore reorgs and late headers, time never goes back
+            Some((timestamp, _)) if timestamp >= block_timestamp => {}
+            _ => *latest = Some((block_timestamp, Instant::now())),
+        }
+    }
+}
+
+impl Clock for BlockClock {
+    fn now(&self) -> Result<u64> {
+        match *self.latest.lock().unwrap() {
+            Some((timestamp, seen_at)) => Ok(timestamp + seen_at.elapsed().as_secs()),
+            None => Err("Block clock has not seen any block header yet".into()),
+        }
+    }
+}
+
+fn latest_block_timestamp(node: &BlockClockConfig) -> Result<u64> {
+    let block = http::json_rpc(
+        &node.url,
+        "eth_getBlockByNumber",
+        serde_json::json!(["latest", false]),
+        Duration::from_secs(5),
+    )?;
+    block["timestamp"]
+        .as_str()
+        .and_then(|t| u64::from_str_radix(t.trim_start_matches("0x"), 16).ok())
+        .ok_or(Error::from(format!(
+            "No block timestamp in the latest block of {}: {}",
+            node.url, block
+        )))
+}
+
+/// A clock that only moves when told to, for tests and simulations
+pub struct ManualClock {
+    now: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(now: u64) -> ManualClock {
+        ManualClock {
+            now: AtomicU64::new(now),
+        }
+    }
+
+    pub fn get(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+
+    pub fn set(&self, now: u64) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.now.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Result<u64> {
+        Ok(self.get())
+    }
+}
+
+/// Clock as written in the tournament configuration, `"system"` or `"block"`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockConfig {
+    System,
+    Block,
+}
+
+impl Default for ClockConfig {
+    fn default() -> ClockConfig {
+        ClockConfig::System
+    }
+}
+
+thread_local! {
+    static CLOCK_OVERRIDE: RefCell<Option<Arc<dyn Clock>>> = RefCell::new(None);
+}
+
+/// Runs `f` with `clock` as the default clock of the current thread, so the
+/// templates built deep inside the reaction tree use it too
+pub fn with_clock<R, F: FnOnce() -> R>(clock: Arc<dyn Clock>, f: F) -> R {
+    let previous = CLOCK_OVERRIDE.with(|c| c.replace(Some(clock)));
+    let result = f();
+    CLOCK_OVERRIDE.with(|c| *c.borrow_mut() = previous);
+    result
+}
+
+/// Clock of a new `MachineTemplate`: the one set by `with_clock`, or else
+/// the configured one
+pub fn default_clock() -> Arc<dyn Clock> {
+    if let Some(clock) = CLOCK_OVERRIDE.with(|c| c.borrow().clone()) {
+        return clock;
+    }
+    // a broken configuration is reported by whoever reads it next,
+    // the system clock is the one every node has
+    tournament_config()
+        .map(|config| config.clock.build())
+        .unwrap_or_else(|_| Arc::new(SystemClock))
+}
+
+lazy_static! {
+    static ref BLOCK_CLOCK: Arc<BlockClock> = BlockClock::polling(
+        tournament_config()
+            .map(|config| config.block_clock.clone())
+            .unwrap_or_default()
+    );
+}
+
+/// The process-wide block clock, polling the configured node
+pub fn block_clock() -> Arc<BlockClock> {
+    BLOCK_CLOCK.clone()
+}
+
+impl ClockConfig {
+    pub fn build(&self) -> Arc<dyn Clock> {
+        match *self {
+            ClockConfig::System => Arc::new(SystemClock),
+            ClockConfig::Block => block_clock(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(timestamp: u64, delay: Duration) -> BlockClockConfig {
+        let url = http::serve(move |_, _| {
+            thread::sleep(delay);
+            let body = json_block(timestamp);
+            (200, body.into_bytes())
+        });
+        BlockClockConfig {
+            url,
+            poll_seconds: 60,
+        }
+    }
+
+    fn json_block(timestamp: u64) -> String {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": { "number": "0x10", "timestamp": format!("0x{:x}", timestamp) }
+        })
+        .to_string()
+    }
+
+    // the time once the clock saw a block, waiting for the poller
+    fn first_block(clock: &BlockClock) -> u64 {
+        for _ in 0..100 {
+            if let Ok(now) = clock.now() {
+                return now;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("block clock saw no block");
+    }
+
+    #[test]
+    fn block_clock_reads_the_latest_header() {
+        let clock = BlockClock::polling(node(1_600_000_000, Duration::from_millis(0)));
+        let now = first_block(&clock);
+        assert!((1_600_000_000..=1_600_000_001).contains(&now));
+    }
+
+    #[test]
+    fn block_clock_does_not_wait_for_the_node() {
+        let clock = BlockClock::polling(node(1_600_000_000, Duration::from_secs(2)));
+        let started = Instant::now();
+        assert!(clock.now().is_err());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(first_block(&clock) >= 1_600_000_000);
+    }
+
+    #[test]
+    fn block_clock_never_goes_back() {
+        let clock = BlockClock::new();
+        clock.update(200);
+        clock.update(100);
+        assert!(clock.now().unwrap() >= 200);
+    }
+
+    #[test]
+    fn block_clock_keeps_the_last_block_when_the_node_is_down() {
+        let clock = BlockClock::polling(BlockClockConfig {
+            url: "http://127.0.0.1:1".to_string(),
+            poll_seconds: 1,
+        });
+        assert!(clock.now().is_err());
+        clock.update(300);
+        assert!(clock.now().unwrap() >= 300);
+    }
+}
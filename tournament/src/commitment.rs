@@ -0,0 +1,172 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Salted commitments for `RevealInstantiator.commit`.
+//!
+//! A player commits to `keccak256(logHash, playerAddress, salt)`, so the
+//! commitment can neither be copied by another player nor opened by anyone
+//! who doesn't know the salt. The salt is kept in the tournament storage
+//! until `complete_reveal_phase` opens the commitment.
+
+use super::configuration::Concern;
+use super::dryrun::is_dry_run;
+use super::error::*;
+use super::ethereum_types::{Address, H256, U256};
+use super::merkle::keccak;
+use super::rand::{thread_rng, Rng};
+use super::storage::storage_subdir;
+use std::fs;
+use std::path::PathBuf;
+
+const COMMITMENTS_DIR: &str = "commitments";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Commitment {
+    pub log_hash: H256,
+    pub salt: H256,
+    /// Log file committed to, as named to the logger service
+    #[serde(default)]
+    pub log_path: Option<String>,
+}
+
+impl Commitment {
+    /// New commitment to `log_hash` with a fresh random salt
+    pub fn new(log_hash: H256, log_path: Option<String>) -> Commitment {
+        let salt: [u8; 32] = thread_rng().gen();
+        Commitment {
+            log_hash,
+            salt: H256::from(salt),
+            log_path,
+        }
+    }
+
+    /// The hash sent on-chain, as checked by `RevealInstantiator.reveal`
+    pub fn hash(&self, player: &Address) -> H256 {
+        keccak(&[&self.log_hash[..], &player[..], &self.salt[..]])
+    }
+
+    /// Commitment stored for the player of `concern` in reveal `index`
+    pub fn load(concern: &Concern, index: U256) -> Result<Option<Commitment>> {
+        let path = commitment_path(concern, index)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&path)
+            .chain_err(|| format!("Could not read commitment {}", path.display()))?;
+        let commitment = serde_json::from_str(&contents)
+            .chain_err(|| format!("Could not parse commitment {}", path.display()))?;
+        Ok(Some(commitment))
+    }
+
+    /// Stores the commitment, replacing any previous one of the same reveal
+    pub fn save(&self, concern: &Concern, index: U256) -> Result<()> {
+        if is_dry_run() {
+            return Ok(());
+        }
+        let path = commitment_path(concern, index)?;
+        let contents = serde_json::to_string(self).unwrap();
+        // write and rename, so a crash never leaves a truncated salt behind
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, contents)
+            .chain_err(|| format!("Could not write commitment {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path)
+            .chain_err(|| format!("Could not write commitment {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Commitment to `log_hash` for reveal `index`, reusing the stored salt
+    /// if the player already committed to the same log
+    pub fn load_or_create(
+        concern: &Concern,
+        index: U256,
+        log_hash: H256,
+        log_path: Option<String>,
+    ) -> Result<Commitment> {
+        let commitment = match Commitment::load(concern, index)? {
+            Some(ref stored) if stored.log_hash == log_hash => Commitment {
+                log_path: log_path.or(stored.log_path.clone()),
+                ..stored.clone()
+            },
+            _ => Commitment::new(log_hash, log_path),
+        };
+        commitment.save(concern, index)?;
+        Ok(commitment)
+    }
+}
+
+fn commitment_path(concern: &Concern, index: U256) -> Result<PathBuf> {
+    Ok(storage_subdir(COMMITMENTS_DIR)?.join(format!(
+        "{:x}_{}_{:x}.json",
+        concern.contract_address, index, concern.user_address
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::use_test_dirs;
+
+    fn concern(contract: u8) -> Concern {
+        Concern {
+            contract_address: Address::from([contract; 20]),
+            user_address: Address::from([0x22; 20]),
+        }
+    }
+
+    #[test]
+    fn hash_is_keccak_of_the_packed_log_hash_player_and_salt() {
+        let commitment = Commitment {
+            log_hash: H256::from([0x11; 32]),
+            salt: H256::from([0x33; 32]),
+            log_path: None,
+        };
+
+        // keccak256(abi.encodePacked(bytes32(0x11..), address(0x22..), bytes32(0x33..)))
+        let expected: H256 = "478c047f1f2d93b75ef814921369667d5635827bab46dab9ad6eddb079e935cf"
+            .parse()
+            .unwrap();
+        assert_eq!(commitment.hash(&Address::from([0x22; 20])), expected);
+        assert_ne!(commitment.hash(&Address::from([0x23; 20])), expected);
+    }
+
+    #[test]
+    fn stored_salt_is_reused_for_the_same_log() {
+        use_test_dirs();
+        let concern = concern(0x51);
+        let index = U256::from(5);
+        let log_hash = H256::from([0x11; 32]);
+
+        let first =
+            Commitment::load_or_create(&concern, index, log_hash, Some("5.json.br.cpio".into()))
+                .unwrap();
+        let second = Commitment::load_or_create(&concern, index, log_hash, None).unwrap();
+        assert_eq!(second, first);
+        assert_eq!(
+            Commitment::load(&concern, index).unwrap(),
+            Some(first.clone())
+        );
+
+        let other =
+            Commitment::load_or_create(&concern, index, H256::from([0x12; 32]), None).unwrap();
+        assert_ne!(other.salt, first.salt);
+        assert_eq!(Commitment::load(&concern, index).unwrap(), Some(other));
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Tournament settings that don't belong to the dispatcher itself, read
+//! once from the JSON file at `TOURNAMENT_CONFIG_PATH`:
+//!
+//! ```json
+//! {
+//!     "chain_id": 1337,
+//!     "clock": "block",
+//!     "block_clock": { "url": "http://ganache:8545", "poll_seconds": 5 },
+//!     "challenge_policy": { "policy": "always_verify" },
+//!     "accounts": {
+//!         "0x2ad38f50f38abc5cbcf175e1962293eecc7936de": {
+//!             "challenge_policy": { "policy": "gas_threshold", "max_gas": 3000000 }
+//!         }
+//!     },
+//!     "settlement": {
+//!         "prize_function": "claimPrize",
+//!         "claimed_function": "isPrizeClaimed",
+//!         "rpc_url": "http://ganache:8545"
+//!     },
+//!     "download": {
+//!         "sources": [
+//!             { "source": "directory", "path": "/var/cache/tournament/logs" },
+//!             { "source": "http", "url": "http://mirror.example:8080/logs" }
+//!         ]
+//!     },
+//!     "log_cache": { "max_bytes": 10000000000 },
+//!     "dry_run": { "enabled": false, "record_path": "dry-run.jsonl" },
+//!     "score_ordering": "highest_wins"
+//! }
+//! ```
+//!
+//! Without the variable every setting takes its default.
+
+use super::clock::{BlockClockConfig, ClockConfig};
+use super::download::DownloadConfig;
+use super::dryrun::DryRunConfig;
+use super::error::*;
+use super::ethereum_types::Address;
+use super::logcache::LogCacheConfig;
+use super::policy::PolicyConfig;
+use super::score::ScoreOrdering;
+use super::settlement::SettlementConfig;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+/// Environment variable pointing to the tournament configuration file
+pub const CONFIG_PATH_VAR: &str = "TOURNAMENT_CONFIG_PATH";
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TournamentConfig {
+    /// Chain the dispatcher sends its transactions to, part of the
+    /// machine ids
+    #[serde(default)]
+    pub chain_id: u64,
+    /// Clock used for the deadlines
+    #[serde(default)]
+    pub clock: ClockConfig,
+    /// Node the block clock reads the time from
+    #[serde(default)]
+    pub block_clock: BlockClockConfig,
+    /// Policy of the accounts without one of their own
+    #[serde(default)]
+    pub challenge_policy: PolicyConfig,
+    /// Settings of specific player accounts
+    #[serde(default)]
+    pub accounts: HashMap<Address, AccountConfig>,
+    /// How the winner claims its prize
+    #[serde(default)]
+    pub settlement: SettlementConfig,
+    /// How long to wait for logs and where else to find them
+    #[serde(default)]
+    pub download: DownloadConfig,
+    /// Where logs are kept and for how long
+    #[serde(default)]
+    pub log_cache: LogCacheConfig,
+    /// Whether the dispatcher only records what it would send
+    #[serde(default)]
+    pub dry_run: DryRunConfig,
+    /// Which player of a match is expected to be the claimer, matches
+    /// created otherwise are warned about and played
+    #[serde(default)]
+    pub score_ordering: ScoreOrdering,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct AccountConfig {
+    #[serde(default)]
+    pub challenge_policy: Option<PolicyConfig>,
+}
+
+impl TournamentConfig {
+    /// Reads the configuration from `TOURNAMENT_CONFIG_PATH`,
+    /// or the default one if the variable is not set
+    pub fn from_env() -> Result<TournamentConfig> {
+        match env::var(CONFIG_PATH_VAR) {
+            Ok(path) => {
+                let contents = fs::read_to_string(&path)
+                    .chain_err(|| format!("Could not read tournament config {}", path))?;
+                TournamentConfig::from_json(&contents)
+                    .chain_err(|| format!("Invalid tournament config {}", path))
+            }
+            Err(_) => Ok(TournamentConfig::default()),
+        }
+    }
+
+    /// Parses a configuration
+    pub fn from_json(contents: &str) -> Result<TournamentConfig> {
+        serde_json::from_str(contents).chain_err(|| "Could not parse tournament config")
+    }
+
+    /// Challenge policy of the player `account`
+    pub fn challenge_policy(&self, account: &Address) -> &PolicyConfig {
+        self.accounts
+            .get(account)
+            .and_then(|a| a.challenge_policy.as_ref())
+            .unwrap_or(&self.challenge_policy)
+    }
+}
+
+lazy_static! {
+    static ref CONFIG: ::std::result::Result<TournamentConfig, String> =
+        TournamentConfig::from_env().map_err(|e| e.to_string());
+}
+
+/// The process-wide configuration, loaded on first use
+pub fn tournament_config() -> Result<&'static TournamentConfig> {
+    CONFIG
+        .as_ref()
+        .map_err(|e| Error::from(ErrorKind::Msg(e.clone())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn challenge_policy_is_looked_up_per_account() {
+        let config = TournamentConfig::from_json(
+            r#"{
+                "challenge_policy": { "policy": "dry_run" },
+                "accounts": {
+                    "0x2ad38f50f38abc5cbcf175e1962293eecc7936de": {
+                        "challenge_policy": { "policy": "gas_threshold", "max_gas": 3000000 }
+                    },
+                    "0x0101010101010101010101010101010101010101": {}
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let account: Address = "2ad38f50f38abc5cbcf175e1962293eecc7936de".parse().unwrap();
+        assert_eq!(
+            config.challenge_policy(&account),
+            &PolicyConfig::GasThreshold { max_gas: 3_000_000 }
+        );
+        // accounts without a policy of their own take the default one
+        assert_eq!(
+            config.challenge_policy(&Address::from([1; 20])),
+            &PolicyConfig::DryRun
+        );
+        assert_eq!(
+            config.challenge_policy(&Address::from([2; 20])),
+            &PolicyConfig::DryRun
+        );
+    }
+
+    #[test]
+    fn missing_settings_take_their_defaults() {
+        let config = TournamentConfig::from_json("{}").unwrap();
+        assert_eq!(
+            config.challenge_policy(&Address::from([1; 20])),
+            &PolicyConfig::AlwaysVerify
+        );
+        assert!(TournamentConfig::from_json(r#"{ "challenge_policy": "never" }"#).is_err());
+    }
+}
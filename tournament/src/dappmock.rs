@@ -25,34 +25,27 @@ use super::error::Result;
 use super::error::*;
 use super::ethabi::Token;
 use super::ethereum_types::U256;
-use super::revealmock::{RevealMock, RevealMockCtx, RevealMockCtxParsed};
+use super::revealmock::{RevealMock, RevealMockCtx, RevealMockCtxParsed, RevealMockState};
 use super::transaction;
 use super::transaction::TransactionRequest;
 
 pub struct DAppMock();
 
-// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-// these two structs and the From trait below shuld be
-// obtained from a simple derive
-// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-#[derive(Serialize, Deserialize)]
-struct DAppMockCtxParsed(
-    U256Field,     // revealIndex
-    String32Field, // currentState
-);
-
-#[derive(Serialize, Debug)]
-struct DAppMockCtx {
-    reveal_index: U256,
-    current_state: String,
+#[derive(Serialize, Debug, DAppContext)]
+#[get_state(revealIndex(U256Field), currentState(String32Field))]
+pub struct DAppMockCtx {
+    #[slot(revealIndex)]
+    pub reveal_index: U256,
+    #[slot(currentState)]
+    pub current_state: String,
 }
 
-impl From<DAppMockCtxParsed> for DAppMockCtx {
-    fn from(parsed: DAppMockCtxParsed) -> DAppMockCtx {
-        DAppMockCtx {
-            reveal_index: parsed.0.value,
-            current_state: parsed.1.value,
-        }
+contract_state! {
+    /// States of `DAppMock.sol`
+    pub enum DAppMockState {
+        Idle,
+        DAppRunning,
+        DAppFinished,
     }
 }
 
@@ -78,12 +71,14 @@ impl DApp<()> for DAppMock {
 
         // these states should not occur as they indicate an innactive instance,
         // but it is possible that the blockchain state changed between queries
-        match ctx.current_state.as_ref() {
-            "DAppFinished" => {
+        let state: DAppMockState = ctx.current_state.parse()?;
+
+        match state {
+            DAppMockState::DAppFinished => {
                 return Ok(Reaction::Idle);
             }
 
-            "Idle" => {
+            DAppMockState::Idle => {
                 println!("STATE is IDLE");
                 let request = TransactionRequest {
                     concern: instance.concern.clone(),
@@ -97,7 +92,7 @@ impl DApp<()> for DAppMock {
                 return Ok(Reaction::Transaction(request));
             }
 
-            "DAppRunning" => {
+            DAppMockState::DAppRunning => {
                 // we inspect the reveal contract
                 let revealmock_instance = instance.sub_instances.get(0).ok_or(Error::from(
                     ErrorKind::InvalidContractState(format!(
@@ -114,9 +109,10 @@ impl DApp<()> for DAppMock {
                         )
                     })?;
                 let revealmock_ctx: RevealMockCtx = revealmock_parsed.into();
+                let revealmock_state: RevealMockState = revealmock_ctx.current_state.parse()?;
 
-                match revealmock_ctx.current_state.as_ref() {
-                    "TournamentOver" => {
+                match revealmock_state {
+                    RevealMockState::TournamentOver => {
                         // claim Finished in dappmock test contract
                         let request = TransactionRequest {
                             concern: instance.concern.clone(),
@@ -128,16 +124,15 @@ impl DApp<()> for DAppMock {
                         };
                         return Ok(Reaction::Transaction(request));
                     }
-                    _ => {
+                    RevealMockState::CommitPhase
+                    | RevealMockState::RevealPhase
+                    | RevealMockState::MatchManagerPhase => {
                         // revealMock is still active,
                         // pass control to the appropriate dapp
                         return RevealMock::react(revealmock_instance, archive, post_payload, &());
                     }
                 }
             }
-            _ => {
-                return Ok(Reaction::Idle);
-            }
         }
     }
 
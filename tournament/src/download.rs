@@ -0,0 +1,608 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Logger requests bounded by the time left to act on them.
+//!
+//! The logger answers a pending submit or download with a non-zero status,
+//! which `get_logger_response` turns into a `ServiceNeedsRetry`, and the
+//! dispatcher retries it for as long as it takes. The contracts only budget
+//! `TIME_TO_DOWNLOAD_LOG` for a download, so past a deadline retrying is
+//! pointless. A download can also be served by the fallback sources of the
+//! configuration, as a file named after the log root: a local directory or
+//! an HTTP mirror, `GET {url}/{root}` over plain HTTP/1.0, TLS not being
+//! supported. The sources are fetched from on a thread of their own, so a
+//! slow mirror never holds up `react`: the fetch stores the log it finds in
+//! the cache, where a later call picks it up, and is started again while
+//! the log is still missing. A dry run fetches from none of them.
+//! Downloads end up in the log cache, which checks them against their root.
+
+use super::clock::Clock;
+use super::config::tournament_config;
+use super::dispatcher::Archive;
+use super::dryrun::is_dry_run;
+use super::error::*;
+use super::ethereum_types::H256;
+use super::http;
+use super::logcache::LogCache;
+use super::merkle::get_root_of_data;
+use super::r#match::MachineTemplate;
+use super::storage::log_file_path;
+use super::LOGGER_METHOD_DOWNLOAD;
+use super::{get_logger_response, DownloadFileRequest, DownloadFileResponse, LOGGER_SERVICE_NAME};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+/// Time the contracts allow for downloading the opponent's log
+pub const TIME_TO_DOWNLOAD_LOG: u64 = 2400;
+/// Time the contracts allow for building a machine
+pub const TIME_TO_START_MACHINE: u64 = 40;
+/// Time the contracts allow for running an instruction
+pub const PICOSECONDS_TO_RUN_INSN: u64 = 500;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DownloadConfig {
+    /// Where to look for a log the logger has not delivered yet, in order
+    #[serde(default)]
+    pub sources: Vec<DownloadSource>,
+    /// Time kept back from a deadline to act once the log is available
+    #[serde(default = "default_reserve_seconds")]
+    pub reserve_seconds: u64,
+    /// Timeout of each request to an HTTP source
+    #[serde(default = "default_http_timeout_seconds")]
+    pub http_timeout_seconds: u64,
+}
+
+fn default_reserve_seconds() -> u64 {
+    120
+}
+
+fn default_http_timeout_seconds() -> u64 {
+    10
+}
+
+impl Default for DownloadConfig {
+    fn default() -> DownloadConfig {
+        DownloadConfig {
+            sources: vec![],
+            reserve_seconds: default_reserve_seconds(),
+            http_timeout_seconds: default_http_timeout_seconds(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum DownloadSource {
+    /// Directory holding logs named after their root
+    Directory { path: PathBuf },
+    /// HTTP server holding logs named after their root, over plain
+    /// HTTP/1.0, `http://` only
+    Http { url: String },
+}
+
+impl DownloadSource {
+    fn fetch(&self, root: &H256, config: &DownloadConfig) -> Result<Vec<u8>> {
+        match *self {
+            DownloadSource::Directory { ref path } => {
+                let file = path.join(format!("{:x}", root));
+                fs::read(&file).chain_err(|| format!("Could not read {}", file.display()))
+            }
+            DownloadSource::Http { ref url } => http::get(
+                &format!("{}/{:x}", url.trim_end_matches('/'), root),
+                Duration::from_secs(config.http_timeout_seconds),
+            ),
+        }
+    }
+}
+
+/// Last time by which a retried logger request is still worth waiting for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryBudget {
+    pub deadline: u64,
+}
+
+impl RetryBudget {
+    pub fn new(deadline: u64) -> RetryBudget {
+        RetryBudget { deadline }
+    }
+
+    /// Budget of the challenger's download in a match expiring at
+    /// `match_deadline`, leaving time to build and run the opponent's
+    /// machine up to `final_time`, and then `reserve_seconds` to challenge
+    pub fn for_challenge(
+        match_deadline: u64,
+        final_time: u64,
+        reserve_seconds: u64,
+    ) -> RetryBudget {
+        let run_time =
+            (final_time as u128 * PICOSECONDS_TO_RUN_INSN as u128 / 1_000_000_000_000) as u64;
+        RetryBudget::new(
+            match_deadline
+                .saturating_sub(TIME_TO_START_MACHINE)
+                .saturating_sub(run_time)
+                .saturating_sub(reserve_seconds),
+        )
+    }
+
+    /// Seconds left, `None` once the deadline passed
+    pub fn remaining(&self, clock: &dyn Clock) -> Result<Option<u64>> {
+        let now = clock.now()?;
+        Ok(if now > self.deadline {
+            None
+        } else {
+            Some(self.deadline - now)
+        })
+    }
+}
+
+/// Last progress the logger reported for a pending request
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LoggerProgress {
+    pub method: String,
+    pub key: String,
+    pub status: u32,
+    pub progress: u32,
+    pub description: String,
+    pub reported_at: u64,
+}
+
+lazy_static! {
+    static ref PROGRESS: Mutex<HashMap<String, LoggerProgress>> = Mutex::new(HashMap::new());
+}
+
+/// Logger requests still pending, with their last reported progress
+pub fn pending_requests() -> Vec<LoggerProgress> {
+    let progress = PROGRESS.lock().unwrap();
+    let mut pending: Vec<LoggerProgress> = progress.values().cloned().collect();
+    pending.sort_by(|a, b| a.key.cmp(&b.key));
+    pending
+}
+
+fn report_progress(update: LoggerProgress) {
+    let mut progress = PROGRESS.lock().unwrap();
+    let changed = match progress.get(&update.key) {
+        Some(last) => last.progress != update.progress || last.status != update.status,
+        None => true,
+    };
+    if changed {
+        info!(
+            "Logger request {} ({}) at {}%, status {}: {}",
+            update.key, update.method, update.progress, update.status, update.description
+        );
+    }
+    progress.insert(update.key.clone(), update);
+}
+
+fn clear_progress(key: &str) {
+    PROGRESS.lock().unwrap().remove(key);
+}
+
+/// Calls the logger through `get_logger_response`, reporting the progress
+/// of a pending request. Returns `None` instead of asking for a retry once
+/// the `budget` is exhausted.
+pub fn logger_request(
+    archive: &Archive,
+    contract: &str,
+    key: String,
+    method: &str,
+    request: Vec<u8>,
+    budget: &RetryBudget,
+    clock: &dyn Clock,
+) -> Result<Option<Vec<u8>>> {
+    let result = get_logger_response(
+        archive,
+        contract.to_string(),
+        LOGGER_SERVICE_NAME.to_string(),
+        key.clone(),
+        method.to_string(),
+        request,
+    );
+    let e = match result {
+        Ok(response) => {
+            clear_progress(&key);
+            return Ok(Some(response));
+        }
+        Err(e) => e,
+    };
+
+    match *e.kind() {
+        ErrorKind::ServiceNeedsRetry(_, _, _, _, _, status, progress, ref description) => {
+            report_progress(LoggerProgress {
+                method: method.to_string(),
+                key: key.clone(),
+                status,
+                progress,
+                description: description.clone(),
+                reported_at: clock.now()?,
+            });
+        }
+        ErrorKind::ResponseMissError(..) => {}
+        _ => return Err(e),
+    }
+
+    match budget.remaining(clock)? {
+        Some(_) => Err(e),
+        None => {
+            warn!(
+                "Giving up on logger request {} ({}), its deadline {} has passed",
+                key, method, budget.deadline
+            );
+            clear_progress(&key);
+            Ok(None)
+        }
+    }
+}
+
+/// Outcome of a bounded log download
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadOutcome {
+    /// The log is available at `path`, as named to the logger
+    Ready { path: String },
+    /// Neither the logger nor a fallback source delivered the log in time
+    OutOfTime { deadline: u64 },
+}
+
+/// Downloads the log of `root` into the log cache, from the logger or,
+/// while it is pending, from the configured fallback sources
+pub fn download_log(
+    archive: &Archive,
+    contract: &str,
+    root: H256,
+    machine_template: &MachineTemplate,
+    budget: &RetryBudget,
+) -> Result<DownloadOutcome> {
+    let config = tournament_config()?;
+    download_log_with(
+        archive,
+        contract,
+        root,
+        machine_template,
+        budget,
+        &LogCache::new(config.log_cache.clone()),
+        &config.download,
+    )
+}
+
+fn download_log_with(
+    archive: &Archive,
+    contract: &str,
+    root: H256,
+    machine_template: &MachineTemplate,
+    budget: &RetryBudget,
+    cache: &LogCache,
+    config: &DownloadConfig,
+) -> Result<DownloadOutcome> {
+    let log2_size = machine_template.page_log2_size + machine_template.tree_log2_size;
+    if let Some(path) = cache.get(&root, log2_size)? {
+        trace!("Log {:x} found in the cache", root);
+        return Ok(DownloadOutcome::Ready { path });
+    }
+
+    cache.create_dir()?;
+    let request = DownloadFileRequest {
+        root,
+        path: cache.logger_path(&root),
+        page_log2_size: machine_template.page_log2_size,
+        tree_log2_size: machine_template.tree_log2_size,
+    };
+    let key = format!("{:x}", root);
+    let result = logger_request(
+        archive,
+        contract,
+        key.clone(),
+        LOGGER_METHOD_DOWNLOAD,
+        request.into(),
+        budget,
+        &*machine_template.clock,
+    );
+    match result {
+        Ok(Some(response)) => {
+            let response: DownloadFileResponse = response.into();
+            let (stored, path) = cache.insert_file(&log_file_path(&response.path), log2_size)?;
+            if stored != root {
+                return Err(format!(
+                    "Logger downloaded {} for log {:x}, but its root is {:x}",
+                    response.path, root, stored
+                )
+                .into());
+            }
+            return Ok(DownloadOutcome::Ready { path });
+        }
+        Ok(None) => {}
+        Err(e) => match *e.kind() {
+            ErrorKind::ServiceNeedsRetry(..) | ErrorKind::ResponseMissError(..) => {
+                if let Some(path) = poll_sources(cache, &root, log2_size, config)? {
+                    clear_progress(&key);
+                    return Ok(DownloadOutcome::Ready { path });
+                }
+                return Err(e);
+            }
+            _ => return Err(e),
+        },
+    }
+
+    // a fetch still running may deliver the log on a later call
+    if let Some(path) = poll_sources(cache, &root, log2_size, config)? {
+        return Ok(DownloadOutcome::Ready { path });
+    }
+    Ok(DownloadOutcome::OutOfTime {
+        deadline: budget.deadline,
+    })
+}
+
+lazy_static! {
+    // roots being fetched from the fallback sources, each on a thread of
+    // its own, until the fetch ends
+    static ref SOURCE_FETCHES: Mutex<HashSet<H256>> = Mutex::new(HashSet::new());
+}
+
+/// Path of the log a fetch from the fallback sources stored in the cache,
+/// starting a fetch unless one is running
+fn poll_sources(
+    cache: &LogCache,
+    root: &H256,
+    log2_size: u64,
+    config: &DownloadConfig,
+) -> Result<Option<String>> {
+    if config.sources.is_empty() {
+        return Ok(None);
+    }
+    if is_dry_run() {
+        trace!(
+            "Dry run, not fetching log {:x} from the fallback sources",
+            root
+        );
+        return Ok(None);
+    }
+    if let Some(path) = cache.get(root, log2_size)? {
+        return Ok(Some(path));
+    }
+
+    if SOURCE_FETCHES.lock().unwrap().insert(*root) {
+        let root = *root;
+        let cache = cache.clone();
+        let config = config.clone();
+        thread::spawn(move || {
+            if let Some((source, data)) = fetch_from_sources(&root, log2_size, &config) {
+                match cache.insert(&root, &data, log2_size) {
+                    Ok(_) => info!("Log {:x} downloaded from {:?}", root, source),
+                    Err(e) => warn!("Could not cache log {:x} from {:?}: {}", root, source, e),
+                }
+            }
+            SOURCE_FETCHES.lock().unwrap().remove(&root);
+        });
+    }
+    Ok(None)
+}
+
+/// The log from the first fallback source holding it, if any
+fn fetch_from_sources(
+    root: &H256,
+    log2_size: u64,
+    config: &DownloadConfig,
+) -> Option<(DownloadSource, Vec<u8>)> {
+    for source in &config.sources {
+        let data = match source.fetch(root, config) {
+            Ok(data) => data,
+            Err(e) => {
+                trace!("Log {:x} not available from {:?}: {}", root, source, e);
+                continue;
+            }
+        };
+        match get_root_of_data(&data, log2_size) {
+            Ok(ref actual) if actual == root => return Some((source.clone(), data)),
+            Ok(actual) => warn!(
+                "Discarding log {:x} from {:?}, its root is {:x}",
+                root, source, actual
+            ),
+            Err(e) => warn!("Discarding log {:x} from {:?}: {}", root, source, e),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::ManualClock;
+    use dryrun::with_dry_run;
+    use logcache::LogCacheConfig;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+    use storage::use_test_dirs;
+
+    // 2^10 bytes of log
+    fn template(now: u64) -> MachineTemplate {
+        MachineTemplate {
+            page_log2_size: 3,
+            tree_log2_size: 7,
+            clock: Arc::new(ManualClock::new(now)),
+            ..Default::default()
+        }
+    }
+
+    fn log(seed: u8) -> (H256, Vec<u8>) {
+        let data = vec![seed; 1024];
+        (get_root_of_data(&data, 10).unwrap(), data)
+    }
+
+    // mirror serving `data`, or not found without it, counting requests
+    fn mirror(data: Option<Vec<u8>>, requests: Arc<AtomicUsize>) -> DownloadConfig {
+        let url = http::serve(move |_, _| {
+            requests.fetch_add(1, Ordering::SeqCst);
+            match data {
+                Some(ref data) => (200, data.clone()),
+                None => (404, vec![]),
+            }
+        });
+        DownloadConfig {
+            sources: vec![DownloadSource::Http { url }],
+            http_timeout_seconds: 1,
+            ..Default::default()
+        }
+    }
+
+    fn cache(dir: &str) -> LogCache {
+        LogCache::new(LogCacheConfig {
+            dir: dir.to_string(),
+            ..Default::default()
+        })
+    }
+
+    // calls `download_log` until it no longer asks for a retry
+    fn download(
+        root: H256,
+        template: &MachineTemplate,
+        budget: &RetryBudget,
+        cache: &LogCache,
+        config: &DownloadConfig,
+    ) -> DownloadOutcome {
+        let archive = Archive::new().unwrap();
+        let started = Instant::now();
+        loop {
+            match download_log_with(&archive, "Match", root, template, budget, cache, config) {
+                Ok(outcome) => return outcome,
+                Err(e) => match *e.kind() {
+                    ErrorKind::ResponseMissError(..) => {}
+                    _ => panic!("{}", e),
+                },
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "download never ended"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // waits for the fetch of `root` to end and forget the root
+    fn wait_for_fetch(root: &H256) {
+        let started = Instant::now();
+        while SOURCE_FETCHES.lock().unwrap().contains(root) {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "fetch never ended"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn log_from_a_mirror_is_cached() {
+        use_test_dirs();
+        let (root, data) = log(1);
+        let config = mirror(Some(data.clone()), Arc::new(AtomicUsize::new(0)));
+        let cache = cache("download-mirror");
+
+        let outcome = download(
+            root,
+            &template(1_000),
+            &RetryBudget::new(5_000),
+            &cache,
+            &config,
+        );
+        assert_eq!(
+            outcome,
+            DownloadOutcome::Ready {
+                path: cache.logger_path(&root)
+            }
+        );
+        assert_eq!(fs::read(cache.local_path(&root)).unwrap(), data);
+        wait_for_fetch(&root);
+    }
+
+    #[test]
+    fn mirror_without_the_log_is_given_up_on_past_the_budget() {
+        use_test_dirs();
+        let (root, _) = log(2);
+        let requests = Arc::new(AtomicUsize::new(0));
+        let config = mirror(None, requests.clone());
+        let cache = cache("download-exhausted");
+        let template = template(1_000);
+        let budget = RetryBudget::new(500);
+
+        // the fetch runs aside, the budget being already exhausted
+        assert_eq!(
+            download(root, &template, &budget, &cache, &config),
+            DownloadOutcome::OutOfTime { deadline: 500 }
+        );
+        wait_for_fetch(&root);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            download(root, &template, &budget, &cache, &config),
+            DownloadOutcome::OutOfTime { deadline: 500 }
+        );
+        assert!(cache.get(&root, 10).unwrap().is_none());
+    }
+
+    #[test]
+    fn dry_run_does_not_fetch_from_mirrors() {
+        use_test_dirs();
+        let (root, data) = log(4);
+        let requests = Arc::new(AtomicUsize::new(0));
+        let config = mirror(Some(data), requests.clone());
+        let cache = cache("download-dry-run");
+
+        let outcome = with_dry_run(|| {
+            download(
+                root,
+                &template(1_000),
+                &RetryBudget::new(500),
+                &cache,
+                &config,
+            )
+        });
+        assert_eq!(outcome, DownloadOutcome::OutOfTime { deadline: 500 });
+        assert!(!SOURCE_FETCHES.lock().unwrap().contains(&root));
+        assert_eq!(requests.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn log_a_mirror_delivers_late_is_still_used() {
+        use_test_dirs();
+        let (root, data) = log(3);
+        let config = mirror(Some(data), Arc::new(AtomicUsize::new(0)));
+        let cache = cache("download-late");
+        let template = template(1_000);
+        let budget = RetryBudget::new(500);
+
+        let started = Instant::now();
+        loop {
+            match download(root, &template, &budget, &cache, &config) {
+                DownloadOutcome::Ready { path } => {
+                    assert_eq!(path, cache.logger_path(&root));
+                    break;
+                }
+                DownloadOutcome::OutOfTime { .. } => {}
+            }
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "log never delivered"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
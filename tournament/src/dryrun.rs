@@ -0,0 +1,504 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Reacting without side effects.
+//!
+//! Inside `with_dry_run` the DApps still decide their reaction, but the
+//! files the tournament keeps between dispatcher runs are left untouched,
+//! so looking at what a node would do never changes what it will do.
+//!
+//! `DryRun` reacts over a whole instance tree this way, recording the
+//! transaction it would send instead of sending it. Service requests are
+//! answered from responses cached beforehand, and only the read-only ones
+//! reach live services. `DryRunDApp` does the same inside the dispatcher,
+//! to run a new version against a live tournament before switching over,
+//! recording each distinct transaction once. Logs are not fetched from the
+//! fallback sources either, the node is only read from.
+
+use super::config::tournament_config;
+use super::dispatcher::{Archive, DApp, Reaction};
+use super::error::*;
+use super::ethabi::Token;
+use super::ethereum_types::{Address, U256};
+use super::hex;
+use super::simulator::{ServiceCall, ServiceOracle, ServiceResponse};
+use super::transaction::TransactionRequest;
+use super::{EMULATOR_METHOD_PROOF, EMULATOR_METHOD_READ};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+thread_local! {
+    static DRY_RUN: Cell<bool> = Cell::new(false);
+}
+
+/// Runs `f` in dry-run mode on the current thread
+pub fn with_dry_run<R, F: FnOnce() -> R>(f: F) -> R {
+    let previous = DRY_RUN.with(|d| d.replace(true));
+    let result = f();
+    DRY_RUN.with(|d| d.set(previous));
+    result
+}
+
+/// Whether the current thread is in dry-run mode, where nothing is stored
+pub fn is_dry_run() -> bool {
+    DRY_RUN.with(|d| d.get())
+}
+
+/// Human-readable value of a transaction argument
+pub fn describe_token(token: &Token) -> String {
+    match *token {
+        Token::Address(ref address) => format!("{:?}", address),
+        Token::FixedBytes(ref bytes) | Token::Bytes(ref bytes) => {
+            format!("0x{}", hex::encode(bytes))
+        }
+        Token::Int(ref value) | Token::Uint(ref value) => value.to_string(),
+        Token::Bool(value) => value.to_string(),
+        Token::String(ref value) => format!("{:?}", value),
+        Token::FixedArray(ref tokens) | Token::Array(ref tokens) => format!(
+            "[{}]",
+            tokens
+                .iter()
+                .map(describe_token)
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Whether a service request only reads, and may reach a live service in a
+/// dry run. Creating sessions, running them and storing or fetching logs
+/// all change what the services hold, and the fallback log sources of
+/// `download` are not fetched from either.
+pub fn is_read_only(method: &str) -> bool {
+    method == EMULATOR_METHOD_READ || method == EMULATOR_METHOD_PROOF
+}
+
+/// What a contract function does when a DApp sends it
+pub fn explain(function: &str) -> String {
+    match function {
+        "commit" => "commits to the salted hash of our log, hidden until the reveal phase".into(),
+        "reveal" => "reveals our log and its score, with the proofs of both".into(),
+        "endCommitAndReveal" => {
+            "ends the commit and reveal phases, their deadline has passed".into()
+        }
+        "playNextEpoch" => "registers our score to be matched in the next epoch".into(),
+        "advanceEpoch" => "moves the tournament to the next epoch, the current one is over".into(),
+        "challengeHighestScore" => {
+            "challenges the opponent's score, starting a verification game".into()
+        }
+        "winByVG" => "claims the match, won in the verification game".into(),
+        "claimVictoryByTime" => "claims the match, the opponent missed its deadline".into(),
+        "claimWin" => "claims the tournament, no opponent is left".into(),
+        "claimDAppRunning" => "starts the DApp".into(),
+        "claimFinished" => "finishes the DApp, the tournament is over".into(),
+        _ => format!("calls {}", function),
+    }
+}
+
+/// A transaction a dry run decided to send, and did not
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TransactionRecord {
+    pub contract: Address,
+    /// `name #index` of the instance at `contract`, if found in the tree
+    pub instance: Option<String>,
+    pub account: Address,
+    pub function: String,
+    pub arguments: Vec<String>,
+    pub value: U256,
+    /// None lets the dispatcher estimate it
+    pub gas: Option<U256>,
+    pub explanation: String,
+}
+
+impl TransactionRecord {
+    /// Records `request`, naming its instance from the `tree` it was
+    /// decided on
+    pub fn new(request: &TransactionRequest, tree: &state::Instance) -> TransactionRecord {
+        TransactionRecord {
+            contract: request.concern.contract_address,
+            instance: find_instance(tree, &request.concern.contract_address)
+                .map(|i| format!("{} #{}", i.name, i.index)),
+            account: request.concern.user_address,
+            function: request.function.clone(),
+            arguments: request.data.iter().map(describe_token).collect(),
+            value: request.value,
+            gas: request.gas,
+            explanation: explain(&request.function),
+        }
+    }
+}
+
+impl fmt::Display for TransactionRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "send {}({}) to {:?}",
+            self.function,
+            self.arguments.join(", "),
+            self.contract
+        )?;
+        if let Some(ref instance) = self.instance {
+            write!(f, " ({})", instance)?;
+        }
+        if !self.value.is_zero() {
+            write!(f, " with value {}", self.value)?;
+        }
+        match self.gas {
+            Some(gas) => write!(f, " with gas {}", gas)?,
+            None => write!(f, " with estimated gas")?,
+        }
+        write!(f, ": {}", self.explanation)
+    }
+}
+
+// the shallowest instance of the tree at `contract`
+fn find_instance<'a>(tree: &'a state::Instance, contract: &Address) -> Option<&'a state::Instance> {
+    if tree.concern.contract_address == *contract {
+        return Some(tree);
+    }
+    tree.sub_instances
+        .iter()
+        .filter_map(|sub| find_instance(sub, contract))
+        .next()
+}
+
+/// A service response saved beforehand, for `DryRun::cache`. The response
+/// is hex encoded, and `error` replaces it for a service that failed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedResponse {
+    pub service: String,
+    pub key: String,
+    pub method: String,
+    #[serde(default)]
+    pub response: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl CachedResponse {
+    /// Reads a JSON list of responses
+    pub fn load(path: &str) -> Result<Vec<CachedResponse>> {
+        let contents = fs::read_to_string(path)
+            .chain_err(|| format!("Could not read cached responses {}", path))?;
+        serde_json::from_str(&contents)
+            .chain_err(|| format!("Could not parse cached responses {}", path))
+    }
+
+    fn to_response(&self) -> Result<ServiceResponse> {
+        match (&self.response, &self.error) {
+            (_, Some(error)) => Ok(Err(error.clone())),
+            (Some(response), &None) => Ok(Ok(hex::decode(response.trim_start_matches("0x"))
+                .chain_err(|| {
+                    format!(
+                        "Invalid cached response of {} {}({})",
+                        self.service, self.method, self.key
+                    )
+                })?)),
+            (&None, &None) => Err(format!(
+                "Cached response of {} {}({}) has neither response nor error",
+                self.service, self.method, self.key
+            )
+            .into()),
+        }
+    }
+}
+
+/// How a service request was handled in a dry run
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceOutcome {
+    /// Answered from the cached responses
+    Cached,
+    /// Answered by a live service, the request being read-only
+    Live,
+    /// Not sent, the request would change the service
+    Blocked,
+    /// Read-only, but no live service answered it
+    Unanswered,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ServiceRecord {
+    /// `service method(key)`
+    pub call: String,
+    pub outcome: ServiceOutcome,
+}
+
+/// Reacts in dry-run mode, recording transactions instead of sending them
+/// and keeping the services from being changed
+pub struct DryRun {
+    archive: Archive,
+    cache: HashMap<(String, String, String), ServiceResponse>,
+    live: Option<Box<dyn ServiceOracle>>,
+    calls: Vec<ServiceCall>,
+    services: Vec<ServiceRecord>,
+    transactions: Vec<TransactionRecord>,
+}
+
+impl DryRun {
+    pub fn new() -> Result<DryRun> {
+        Ok(DryRun {
+            archive: Archive::new()?,
+            cache: HashMap::new(),
+            live: None,
+            calls: vec![],
+            services: vec![],
+            transactions: vec![],
+        })
+    }
+
+    /// Answers the requests matching `responses` with them
+    pub fn cache(&mut self, responses: &[CachedResponse]) -> Result<&mut DryRun> {
+        for r in responses {
+            self.cache.insert(
+                (r.service.clone(), r.key.clone(), r.method.clone()),
+                r.to_response()?,
+            );
+        }
+        Ok(self)
+    }
+
+    /// Sends the read-only requests the cache has no answer for to `live`
+    pub fn live_services(&mut self, live: Box<dyn ServiceOracle>) -> &mut DryRun {
+        self.live = Some(live);
+        self
+    }
+
+    /// Runs `react` on the `tree` in dry-run mode until it no longer misses
+    /// a response it can be given, and records the transaction it decides
+    /// on. Returns the last result, which is the miss error of a request
+    /// that was blocked or left unanswered.
+    pub fn react<F>(&mut self, tree: &state::Instance, mut react: F) -> Result<Reaction>
+    where
+        F: FnMut(&state::Instance, &Archive) -> Result<Reaction>,
+    {
+        loop {
+            let result = with_dry_run(|| react(tree, &self.archive));
+            let call = match result {
+                Ok(Reaction::Transaction(ref request)) => {
+                    let record = TransactionRecord::new(request, tree);
+                    info!("Dry run, not sending: {}", record);
+                    self.transactions.push(record);
+                    return result;
+                }
+                Err(ref e) => match missed_call(e) {
+                    Some(call) => call,
+                    None => return result,
+                },
+                _ => return result,
+            };
+
+            // an answered request missed again would loop forever
+            if self.calls.contains(&call) {
+                return result;
+            }
+
+            let (outcome, response) = self.answer(&call);
+            self.services.push(ServiceRecord {
+                call: call.to_string(),
+                outcome,
+            });
+            match response {
+                Some(response) => {
+                    self.archive.insert_response(
+                        call.service.clone(),
+                        call.key.clone(),
+                        call.method.clone(),
+                        response,
+                    );
+                    self.calls.push(call);
+                }
+                None => return result,
+            }
+        }
+    }
+
+    /// The transactions decided so far, none of them sent
+    pub fn transactions(&self) -> &[TransactionRecord] {
+        &self.transactions
+    }
+
+    /// Every service request made so far, in order, and how it was handled
+    pub fn services(&self) -> &[ServiceRecord] {
+        &self.services
+    }
+
+    fn answer(&mut self, call: &ServiceCall) -> (ServiceOutcome, Option<ServiceResponse>) {
+        let key = (call.service.clone(), call.key.clone(), call.method.clone());
+        if let Some(response) = self.cache.get(&key) {
+            return (ServiceOutcome::Cached, Some(response.clone()));
+        }
+        if !is_read_only(&call.method) {
+            info!("Dry run, not calling {}", call);
+            return (ServiceOutcome::Blocked, None);
+        }
+        let response = self
+            .live
+            .as_mut()
+            .and_then(|live| live.respond(&call.service, &call.key, &call.method, &call.request));
+        match response {
+            Some(response) => (ServiceOutcome::Live, Some(response)),
+            None => (ServiceOutcome::Unanswered, None),
+        }
+    }
+}
+
+fn missed_call(e: &Error) -> Option<ServiceCall> {
+    match *e.kind() {
+        ErrorKind::ResponseMissError(ref service, ref key, ref method, ref request) => {
+            Some(ServiceCall {
+                service: service.clone(),
+                key: key.clone(),
+                method: method.clone(),
+                request: request.clone(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Dry-run settings of the dispatcher, the `dry_run` entry of the
+/// tournament configuration
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DryRunConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// File the transactions not sent are appended to, as JSON lines
+    #[serde(default)]
+    pub record_path: Option<String>,
+}
+
+/// Runs `D` in the dispatcher in dry-run mode: transactions are logged and
+/// recorded instead of sent, and requests changing a service are not made.
+/// Read-only requests still go to the services through the dispatcher.
+pub struct DryRunDApp<D>(PhantomData<D>);
+
+impl<T, D: DApp<T>> DApp<T> for DryRunDApp<D> {
+    fn react(
+        instance: &state::Instance,
+        archive: &Archive,
+        post_payload: &Option<String>,
+        param: &T,
+    ) -> Result<Reaction> {
+        let result = with_dry_run(|| D::react(instance, archive, post_payload, param));
+        match result {
+            Ok(Reaction::Transaction(ref request)) => {
+                let record = TransactionRecord::new(request, instance);
+                let recorded = match tournament_config()?.dry_run.record_path {
+                    Some(ref path) => save_record(&record, path)?,
+                    None => remember(&record),
+                };
+                if recorded {
+                    info!("Dry run, not sending: {}", record);
+                } else {
+                    trace!("Dry run, still not sending: {}", record);
+                }
+                Ok(Reaction::Idle)
+            }
+            Err(ref e) => match missed_call(e) {
+                Some(ref call) if !is_read_only(&call.method) => {
+                    info!("Dry run, not calling {}", call);
+                    Ok(Reaction::Idle)
+                }
+                _ => result,
+            },
+            _ => result,
+        }
+    }
+
+    fn get_pretty_instance(
+        instance: &state::Instance,
+        archive: &Archive,
+        param: &T,
+    ) -> Result<state::Instance> {
+        D::get_pretty_instance(instance, archive, param)
+    }
+}
+
+lazy_static! {
+    // (contract, function, arguments) of the transactions recorded so far
+    static ref RECORDED: Mutex<HashSet<(Address, String, Vec<String>)>> =
+        Mutex::new(HashSet::new());
+}
+
+// whether `record` was not recorded before, a DApp deciding the same
+// transaction on every tick until its state changes
+fn remember(record: &TransactionRecord) -> bool {
+    RECORDED.lock().unwrap().insert((
+        record.contract,
+        record.function.clone(),
+        record.arguments.clone(),
+    ))
+}
+
+// appends `record` to the record file at `path` unless it was already
+fn save_record(record: &TransactionRecord, path: &str) -> Result<bool> {
+    if !remember(record) {
+        return Ok(false);
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .chain_err(|| format!("Could not open dry-run record {}", path))?;
+    writeln!(file, "{}", serde_json::to_string(record).unwrap())
+        .chain_err(|| format!("Could not write dry-run record {}", path))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::use_test_dirs;
+
+    fn record(contract: u8, arguments: Vec<&str>) -> TransactionRecord {
+        TransactionRecord {
+            contract: Address::from([contract; 20]),
+            instance: None,
+            account: Address::from([1; 20]),
+            function: "claimVictoryByTime".to_string(),
+            arguments: arguments.into_iter().map(|a| a.to_string()).collect(),
+            value: U256::from(0),
+            gas: None,
+            explanation: explain("claimVictoryByTime"),
+        }
+    }
+
+    #[test]
+    fn repeated_transactions_are_recorded_once() {
+        let path = use_test_dirs().join("dry-run-repeated.jsonl");
+        let path = path.to_str().unwrap();
+
+        assert!(save_record(&record(20, vec!["0"]), path).unwrap());
+        assert!(!save_record(&record(20, vec!["0"]), path).unwrap());
+        assert!(save_record(&record(20, vec!["1"]), path).unwrap());
+        assert!(save_record(&record(21, vec!["0"]), path).unwrap());
+        assert!(!save_record(&record(20, vec!["1"]), path).unwrap());
+
+        let contents = fs::read_to_string(path).unwrap();
+        assert_eq!(contents.lines().count(), 3);
+    }
+}
@@ -0,0 +1,196 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Minimal HTTP/1.0 client, enough to fetch a log from a mirror or query
+//! an Ethereum node. Plain HTTP/1.0 is the only transport: `http://` urls
+//! only, no TLS, no redirects and no chunked encoding. Calls block for up
+//! to their timeout on each of connecting, sending and every read, so
+//! keep them off `react` when the server may be slow.
+
+use super::error::*;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Body of a `GET url`
+pub fn get(url: &str, timeout: Duration) -> Result<Vec<u8>> {
+    request("GET", url, None, timeout)
+}
+
+/// Body of the answer to posting the JSON `body` to `url`
+pub fn post_json(url: &str, body: &str, timeout: Duration) -> Result<Vec<u8>> {
+    request("POST", url, Some(body.as_bytes()), timeout)
+}
+
+/// `result` of the JSON-RPC call of `method` on the Ethereum node at `url`
+pub fn json_rpc(
+    url: &str,
+    method: &str,
+    params: serde_json::Value,
+    timeout: Duration,
+) -> Result<serde_json::Value> {
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params
+    });
+    let response = post_json(url, &request.to_string(), timeout)?;
+    let mut response: serde_json::Value = serde_json::from_slice(&response)
+        .chain_err(|| format!("Could not parse the answer of {} to {}", url, method))?;
+    if let Some(error) = response.get("error") {
+        return Err(format!("{} on {} failed: {}", method, url, error).into());
+    }
+    match response["result"].take() {
+        serde_json::Value::Null => Err(format!("{} on {} returned no result", method, url).into()),
+        result => Ok(result),
+    }
+}
+
+fn request(method: &str, url: &str, body: Option<&[u8]>, timeout: Duration) -> Result<Vec<u8>> {
+    let rest = if url.starts_with("http://") {
+        &url["http://".len()..]
+    } else {
+        return Err(format!("Unsupported url {}, only http:// is", url).into());
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    let socket_address = address
+        .to_socket_addrs()
+        .chain_err(|| format!("Could not resolve {}", address))?
+        .next()
+        .ok_or(Error::from(format!("Could not resolve {}", address)))?;
+
+    let mut stream = TcpStream::connect_timeout(&socket_address, timeout)
+        .chain_err(|| format!("Could not connect to {}", address))?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .chain_err(|| "Could not set read timeout")?;
+    stream
+        .set_write_timeout(Some(timeout))
+        .chain_err(|| "Could not set write timeout")?;
+
+    let mut request = format!("{} {} HTTP/1.0\r\nHost: {}\r\n", method, path, authority);
+    if let Some(body) = body {
+        request.push_str(&format!(
+            "Content-Type: application/json\r\nContent-Length: {}\r\n",
+            body.len()
+        ));
+    }
+    request.push_str("Connection: close\r\n\r\n");
+    let mut request = request.into_bytes();
+    if let Some(body) = body {
+        request.extend_from_slice(body);
+    }
+    stream
+        .write_all(&request)
+        .chain_err(|| format!("Could not send request to {}", url))?;
+
+    let mut response = vec![];
+    stream
+        .read_to_end(&mut response)
+        .chain_err(|| format!("Could not read response from {}", url))?;
+
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or(Error::from(format!("Malformed response from {}", url)))?;
+    let header = String::from_utf8_lossy(&response[..header_end]);
+    let status = header.lines().next().unwrap_or("");
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("{} answered {}", url, status).into());
+    }
+
+    Ok(response[header_end + 4..].to_vec())
+}
+
+/// Serves `respond(request line, body)` on a local port until the process
+/// exits, returning its base url. For tests standing in for mirrors and
+/// Ethereum nodes.
+#[cfg(test)]
+pub fn serve<F>(respond: F) -> String
+where
+    F: Fn(&str, &[u8]) -> (u16, Vec<u8>) + Send + 'static,
+{
+    use std::net::TcpListener;
+    use std::thread;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let mut request = vec![];
+            let mut buffer = [0; 4096];
+            let header_end = loop {
+                match stream.read(&mut buffer) {
+                    Ok(0) | Err(_) => break None,
+                    Ok(n) => request.extend_from_slice(&buffer[..n]),
+                }
+                if let Some(i) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break Some(i + 4);
+                }
+            };
+            let header_end = match header_end {
+                Some(header_end) => header_end,
+                None => continue,
+            };
+            let header = String::from_utf8_lossy(&request[..header_end]).to_string();
+            let length = header
+                .lines()
+                .filter_map(|l| {
+                    l.splitn(2, ':')
+                        .nth(1)
+                        .filter(|_| l.to_lowercase().starts_with("content-length"))
+                })
+                .filter_map(|v| v.trim().parse::<usize>().ok())
+                .next()
+                .unwrap_or(0);
+            while request.len() < header_end + length {
+                match stream.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buffer[..n]),
+                }
+            }
+            let (status, body) =
+                respond(header.lines().next().unwrap_or(""), &request[header_end..]);
+            let mut response = format!(
+                "HTTP/1.0 {} -\r\nContent-Length: {}\r\n\r\n",
+                status,
+                body.len()
+            )
+            .into_bytes();
+            response.extend_from_slice(&body);
+            let _ = stream.write_all(&response);
+        }
+    });
+    url
+}
@@ -0,0 +1,144 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Prints the decoded instance tree of a tournament DApp:
+//!
+//! ```text
+//! inspect --root dappmock|reveal_commit|matchmanager --instance instance.json
+//!     [--account 0x..] [--now <seconds>] [--responses responses.json]
+//!     [--events logs.json] [--format table|json] [--no-color]
+//! ```
+//!
+//! The instance file is a `state::Instance` as fetched by the dispatcher.
+//! The next action is the reaction of `--account`, the instance's user by
+//! default, at `--now`, the current time by default. It is found by a dry
+//! run, which answers service requests from the `--responses` file, a JSON
+//! list of `dryrun::CachedResponse`. The `--events` file, the `eth_getLogs`
+//! result of the tournament's matches on `MatchInstantiator`, completes the
+//! standings.
+
+// error-chain recursion
+#![recursion_limit = "1024"]
+#![warn(unused_extern_crates)]
+#![allow(clippy::result_large_err)]
+
+extern crate error;
+extern crate ethereum_types;
+extern crate tournament;
+extern crate utils;
+
+use error::*;
+use ethereum_types::Address;
+use std::env;
+use std::fs;
+use std::process;
+use tournament::clock::{Clock, SystemClock};
+use tournament::dryrun::CachedResponse;
+use tournament::inspector::{inspect, set_account, RootDApp};
+use tournament::view::EventLog;
+use utils::print_error;
+
+const USAGE: &str = "usage: inspect --root <dappmock|reveal_commit|matchmanager> \
+                     --instance <file> [--account <address>] [--now <seconds>] \
+                     [--responses <file>] [--events <file>] [--format <table|json>] \
+                     [--no-color]";
+
+fn main() {
+    env_logger::init();
+
+    if let Err(ref e) = run() {
+        print_error(e);
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let mut root = None;
+    let mut instance_path = None;
+    let mut account = None;
+    let mut now = None;
+    let mut responses = vec![];
+    let mut events = vec![];
+    let mut json = false;
+    let mut colour = env::var_os("NO_COLOR").is_none();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--no-color" {
+            colour = false;
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or(Error::from(format!("Missing value of {}\n{}", arg, USAGE)))?;
+        match arg.as_ref() {
+            "--root" => root = Some(value.parse::<RootDApp>()?),
+            "--instance" => instance_path = Some(value),
+            "--account" => {
+                account = Some(
+                    value
+                        .trim_start_matches("0x")
+                        .parse::<Address>()
+                        .map_err(|_| Error::from(format!("Invalid address {}", value)))?,
+                )
+            }
+            "--now" => {
+                now = Some(
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| Error::from(format!("Invalid time {}", value)))?,
+                )
+            }
+            "--responses" => responses = CachedResponse::load(&value)?,
+            "--events" => events = EventLog::load(&value)?,
+            "--format" => match value.as_ref() {
+                "table" => json = false,
+                "json" => json = true,
+                _ => return Err(format!("Unknown format {}\n{}", value, USAGE).into()),
+            },
+            _ => return Err(format!("Unexpected argument {}\n{}", arg, USAGE).into()),
+        }
+    }
+
+    let root = root.ok_or(Error::from(format!("Missing --root\n{}", USAGE)))?;
+    let instance_path =
+        instance_path.ok_or(Error::from(format!("Missing --instance\n{}", USAGE)))?;
+    let mut instance: state::Instance = serde_json::from_str(
+        &fs::read_to_string(&instance_path)
+            .chain_err(|| format!("Could not read instance {}", instance_path))?,
+    )
+    .chain_err(|| format!("Could not parse instance {}", instance_path))?;
+    if let Some(account) = account {
+        set_account(&mut instance, account);
+    }
+    let now = match now {
+        Some(now) => now,
+        None => SystemClock.now()?,
+    };
+
+    let inspection = inspect(root, &instance, now, &responses, &events)?;
+    if json {
+        println!("{}", inspection.to_json());
+    } else {
+        print!("{}", inspection.to_table(colour));
+    }
+    Ok(())
+}
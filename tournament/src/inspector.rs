@@ -0,0 +1,974 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Decoded view of a tournament instance tree, for the `inspect` binary.
+//!
+//! The tree is the `get_pretty_instance` of the root DApp, annotated with
+//! the phase deadlines, whose turn it is and the reaction our account
+//! would send next, found by a `DryRun` of `react` at the inspected time.
+//! Once there is a match manager, the standings of the players are rebuilt
+//! by a `TournamentView` of the tree and the given `MatchInstantiator` logs.
+
+use super::clock::{with_clock, Clock, ManualClock};
+use super::dappmock::DAppMock;
+use super::dispatcher::{Archive, DApp, Reaction};
+use super::dryrun::{CachedResponse, DryRun, ServiceOutcome, ServiceRecord, TransactionRecord};
+use super::error::*;
+use super::ethereum_types::{Address, H256, U256};
+use super::matchmanager::MatchManager;
+use super::r#match::MachineTemplate;
+use super::reveal_commit::RevealCommit;
+use super::view::{EventLog, PlayerStatus, Standings, TournamentView};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// DApps an instance tree can be inspected from
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RootDApp {
+    DAppMock,
+    RevealCommit,
+    MatchManager,
+}
+
+impl FromStr for RootDApp {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RootDApp> {
+        match s.to_lowercase().replace('_', "").as_ref() {
+            "dappmock" => Ok(RootDApp::DAppMock),
+            "revealcommit" => Ok(RootDApp::RevealCommit),
+            "matchmanager" => Ok(RootDApp::MatchManager),
+            _ => Err(format!(
+                "Unknown root {}, expected dappmock, reveal_commit or matchmanager",
+                s
+            )
+            .into()),
+        }
+    }
+}
+
+impl RootDApp {
+    fn react(
+        &self,
+        instance: &state::Instance,
+        archive: &Archive,
+        machine_template: &MachineTemplate,
+    ) -> Result<Reaction> {
+        match *self {
+            RootDApp::DAppMock => DAppMock::react(instance, archive, &None, &()),
+            RootDApp::RevealCommit => {
+                RevealCommit::react(instance, archive, &None, machine_template)
+            }
+            RootDApp::MatchManager => {
+                MatchManager::react(instance, archive, &None, machine_template)
+            }
+        }
+    }
+
+    fn get_pretty_instance(
+        &self,
+        instance: &state::Instance,
+        archive: &Archive,
+        machine_template: &MachineTemplate,
+    ) -> Result<state::Instance> {
+        match *self {
+            RootDApp::DAppMock => DAppMock::get_pretty_instance(instance, archive, &()),
+            RootDApp::RevealCommit => {
+                RevealCommit::get_pretty_instance(instance, archive, machine_template)
+            }
+            RootDApp::MatchManager => {
+                MatchManager::get_pretty_instance(instance, archive, machine_template)
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Deadline {
+    pub label: String,
+    /// Seconds since `UNIX_EPOCH`
+    pub at: u64,
+    /// Seconds left at the inspected time, negative once passed
+    pub remaining: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct InspectedNode {
+    pub name: String,
+    pub index: U256,
+    pub contract: Address,
+    pub state: Option<String>,
+    pub deadlines: Vec<Deadline>,
+    /// Who is expected to act next in this instance
+    pub turn: Option<String>,
+    /// Progress of a pending service request
+    pub service: Option<String>,
+    /// The decoded context, as in `get_pretty_instance`
+    pub context: serde_json::Value,
+    pub children: Vec<InspectedNode>,
+}
+
+/// The reaction of a dry run of the root's `react`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum NextAction {
+    Idle,
+    Terminate,
+    Transaction {
+        transaction: TransactionRecord,
+    },
+    /// Needs an answer from a service before deciding
+    WaitingForService {
+        call: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl ::std::fmt::Display for NextAction {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            NextAction::Idle => write!(f, "idle"),
+            NextAction::Terminate => write!(f, "terminate"),
+            NextAction::Transaction { ref transaction } => write!(f, "{}", transaction),
+            NextAction::WaitingForService { ref call } => write!(f, "call {}", call),
+            NextAction::Error { ref message } => write!(f, "fail: {}", message),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Inspection {
+    pub root: RootDApp,
+    /// Inspected time, seconds since `UNIX_EPOCH`
+    pub now: u64,
+    pub account: Address,
+    pub tree: InspectedNode,
+    pub next_action: NextAction,
+    /// Service requests of the dry run, in order
+    pub services: Vec<ServiceRecord>,
+    /// Bracket as far as the tree and the logs tell
+    pub standings: Option<Standings>,
+}
+
+/// Sets `account` as the user of every instance of the tree
+pub fn set_account(instance: &mut state::Instance, account: Address) {
+    instance.concern.user_address = account;
+    for sub in instance.sub_instances.iter_mut() {
+        set_account(sub, account);
+    }
+}
+
+/// Inspects the tree of the `root` DApp `instance` at time `now`, answering
+/// service requests from the cached `responses`. `events` are the logs of
+/// the tournament's matches.
+pub fn inspect(
+    root: RootDApp,
+    instance: &state::Instance,
+    now: u64,
+    responses: &[CachedResponse],
+    events: &[EventLog],
+) -> Result<Inspection> {
+    let clock = Arc::new(ManualClock::new(now));
+    with_clock(clock.clone(), || {
+        let machine_template = MachineTemplate::default();
+        let account = instance.concern.user_address;
+
+        let pretty = root.get_pretty_instance(instance, &Archive::new()?, &machine_template)?;
+        let tree = inspect_node(&pretty, &account, now);
+
+        let mut dry_run = DryRun::new()?;
+        dry_run.cache(responses)?;
+        let result = dry_run.react(instance, |i, a| root.react(i, a, &machine_template));
+        let waiting = dry_run
+            .services()
+            .iter()
+            .find(|s| {
+                s.outcome == ServiceOutcome::Blocked || s.outcome == ServiceOutcome::Unanswered
+            })
+            .cloned();
+        let next_action = match result {
+            Ok(Reaction::Idle) => NextAction::Idle,
+            Ok(Reaction::Terminate) => NextAction::Terminate,
+            Ok(Reaction::Transaction(_)) => NextAction::Transaction {
+                transaction: dry_run.transactions()[0].clone(),
+            },
+            Err(e) => match waiting {
+                Some(service) => NextAction::WaitingForService { call: service.call },
+                None => NextAction::Error {
+                    message: e.to_string(),
+                },
+            },
+        };
+
+        Ok(Inspection {
+            root,
+            now: clock.now()?,
+            account,
+            tree,
+            next_action,
+            services: dry_run.services().to_vec(),
+            standings: standings(root, instance, events)?,
+        })
+    })
+}
+
+fn standings(
+    root: RootDApp,
+    instance: &state::Instance,
+    events: &[EventLog],
+) -> Result<Option<Standings>> {
+    let match_manager = match root {
+        RootDApp::MatchManager => Some(instance),
+        // DAppMock > RevealMock > MatchManager
+        RootDApp::DAppMock => instance
+            .sub_instances
+            .first()
+            .and_then(|reveal| reveal.sub_instances.first())
+            .map(|m| &**m),
+        RootDApp::RevealCommit => None,
+    };
+    if match_manager.is_none() && events.is_empty() {
+        return Ok(None);
+    }
+
+    let mut view = TournamentView::new();
+    if let Some(match_manager) = match_manager {
+        view.add_match_manager(match_manager)?;
+    }
+    for event in events {
+        view.add_log(event)?;
+    }
+    Ok(Some(view.standings()))
+}
+
+fn inspect_node(instance: &state::Instance, account: &Address, now: u64) -> InspectedNode {
+    let context: serde_json::Value =
+        serde_json::from_str(&instance.json_data).unwrap_or(serde_json::Value::Null);
+    let state = context["current_state"].as_str().map(|s| s.to_string());
+    let field = |name: &str| -> Option<U256> { serde_json::from_value(context[name].clone()).ok() };
+    let address =
+        |name: &str| -> Option<Address> { serde_json::from_value(context[name].clone()).ok() };
+    let ours = |player: Option<Address>| -> String {
+        match player {
+            Some(ref player) if player == account => format!("{:?} (us)", player),
+            Some(ref player) => format!("{:?}", player),
+            None => "unknown".to_string(),
+        }
+    };
+    let deadline = |label: String, at: u64| Deadline {
+        label,
+        at,
+        remaining: at as i64 - now as i64,
+    };
+
+    let mut deadlines = vec![];
+    let mut turn = None;
+    match (instance.name.as_ref(), state.as_ref().map(|s| s.as_str())) {
+        ("RevealCommit", Some(state)) => {
+            let commit_end = field("instantiated_at").unwrap_or_default()
+                + field("commit_duration").unwrap_or_default();
+            let reveal_end = commit_end + field("reveal_duration").unwrap_or_default();
+            deadlines.push(deadline("commit phase ends".into(), commit_end.as_u64()));
+            deadlines.push(deadline("reveal phase ends".into(), reveal_end.as_u64()));
+            let committed = serde_json::from_value::<H256>(context["commit_hash"].clone())
+                .map(|h| !h.is_zero())
+                .unwrap_or(false);
+            let revealed = context["has_revealed"].as_bool().unwrap_or(false);
+            turn = match state {
+                "CommitPhase" if committed => Some("players commit, we committed".into()),
+                "CommitPhase" => Some("players commit, we have not committed".into()),
+                "RevealPhase" if revealed => Some("players reveal, we revealed".into()),
+                "RevealPhase" if committed => Some("players reveal, we have not revealed".into()),
+                "RevealPhase" => Some("players reveal, we did not commit".into()),
+                _ => None,
+            };
+        }
+        ("MatchManager", Some(state)) => {
+            let epoch = field("current_epoch").unwrap_or_default();
+            let epoch_end = field("last_epoch_start_time").unwrap_or_default()
+                + field("epoch_duration").unwrap_or_default();
+            let unmatched = address("unmatched_player");
+            turn = match state {
+                "WaitingMatches" if unmatched.as_ref() == Some(account) => {
+                    Some("we are unmatched, we advance the epoch once it ends".into())
+                }
+                "WaitingMatches" if epoch.is_zero() && context["registered"] == false => {
+                    Some("we register for the first epoch".into())
+                }
+                "WaitingMatches" => Some("players play their matches".into()),
+                "MatchesOver" => Some(format!("winner {} claims the prize", ours(unmatched))),
+                _ => None,
+            };
+            if state == "WaitingMatches" {
+                deadlines.push(deadline(
+                    format!("epoch {} ends", epoch),
+                    epoch_end.as_u64(),
+                ));
+            }
+        }
+        ("Match", Some(state)) => {
+            if let Some(at) = field("deadline") {
+                deadlines.push(deadline(format!("{} deadline", state), at.as_u64()));
+            }
+            turn = match state {
+                "WaitingChallenge" => Some(format!(
+                    "challenger {} challenges or lets the claim of {} stand",
+                    ours(address("challenger")),
+                    ours(address("claimer"))
+                )),
+                "ChallengeStarted" => Some("the verification game is being played".into()),
+                _ => None,
+            };
+        }
+        _ => {
+            if let Some(at) = field("deadline") {
+                deadlines.push(deadline("deadline".into(), at.as_u64()));
+            }
+        }
+    }
+
+    InspectedNode {
+        name: instance.name.clone(),
+        index: instance.index,
+        contract: instance.concern.contract_address,
+        state,
+        deadlines,
+        turn,
+        service: instance.service_status.as_ref().map(|s| {
+            format!(
+                "{} {}: {}% {} (status {})",
+                s.service_name, s.service_method, s.progress, s.description, s.status
+            )
+        }),
+        context: context.clone(),
+        children: instance
+            .sub_instances
+            .iter()
+            .map(|sub| inspect_node(sub, account, now))
+            .collect(),
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+
+impl Inspection {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Table of the tree, one row per instance and deadline, coloured with
+    /// ANSI escapes if `colour`
+    pub fn to_table(&self, colour: bool) -> String {
+        // (cell, colour) per column
+        let mut rows: Vec<Vec<(String, &str)>> = vec![vec![
+            ("INSTANCE".to_string(), BOLD),
+            ("STATE".to_string(), BOLD),
+            ("DEADLINE".to_string(), BOLD),
+            ("AT".to_string(), BOLD),
+            ("COUNTDOWN".to_string(), BOLD),
+            ("TURN".to_string(), BOLD),
+        ]];
+        table_rows(&self.tree, 0, &mut rows);
+
+        let columns = rows[0].len();
+        let widths: Vec<usize> = (0..columns)
+            .map(|c| {
+                rows.iter()
+                    .map(|r| r[c].0.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let mut table = String::new();
+        for row in &rows {
+            let mut line = String::new();
+            for (c, &(ref cell, style)) in row.iter().enumerate() {
+                // padded outside the escapes, so trailing blanks are trimmed
+                line.push_str(&paint(cell, style, colour));
+                if c + 1 < columns {
+                    let padding = widths[c] - cell.chars().count() + 2;
+                    line.push_str(&" ".repeat(padding));
+                }
+            }
+            table.push_str(line.trim_end());
+            table.push('\n');
+        }
+
+        table.push_str(&format!(
+            "\n{} {:?}{}\n{} {}\n",
+            paint("account:", BOLD, colour),
+            self.account,
+            if self.account.is_zero() {
+                " (none)"
+            } else {
+                ""
+            },
+            paint("inspected at:", BOLD, colour),
+            format_time(self.now)
+        ));
+        let action_style = match self.next_action {
+            NextAction::Transaction { .. } => YELLOW,
+            NextAction::Error { .. } => RED,
+            _ => "",
+        };
+        table.push_str(&format!(
+            "{} {}\n",
+            paint("next action:", BOLD, colour),
+            paint(&self.next_action.to_string(), action_style, colour)
+        ));
+        for service in &self.services {
+            let (outcome, style) = match service.outcome {
+                ServiceOutcome::Cached => ("cached", ""),
+                ServiceOutcome::Live => ("live", ""),
+                ServiceOutcome::Blocked => ("blocked", RED),
+                ServiceOutcome::Unanswered => ("unanswered", YELLOW),
+            };
+            table.push_str(&format!(
+                "{} {} ({})\n",
+                paint("service:", BOLD, colour),
+                service.call,
+                paint(outcome, style, colour)
+            ));
+        }
+        if let Some(ref standings) = self.standings {
+            table.push_str(&standings_rows(standings, &self.account, colour));
+        }
+        table
+    }
+}
+
+fn standings_rows(standings: &Standings, account: &Address, colour: bool) -> String {
+    let mut rows = format!(
+        "{} epoch {}, {} matches known\n",
+        paint("standings:", BOLD, colour),
+        standings
+            .current_epoch
+            .map_or("unknown".to_string(), |e| e.to_string()),
+        standings
+            .epochs
+            .iter()
+            .map(|e| e.matches.len())
+            .sum::<usize>()
+    );
+    for player in &standings.players {
+        let (status, style) = match player.status {
+            PlayerStatus::Registered => ("registered", ""),
+            PlayerStatus::Playing => ("playing", YELLOW),
+            PlayerStatus::Advancing => ("advancing", GREEN),
+            PlayerStatus::WaitingOpponent => ("waiting for an opponent", ""),
+            PlayerStatus::Eliminated => ("eliminated", RED),
+            PlayerStatus::Winner => ("winner", GREEN),
+        };
+        rows.push_str(&format!(
+            "  {:?}{} {}, {} won",
+            player.player,
+            if player.player == *account {
+                " (us)"
+            } else {
+                ""
+            },
+            paint(status, style, colour),
+            player.matches_won
+        ));
+        if let (Some(m), Some(epoch)) = (player.last_match, player.last_epoch) {
+            rows.push_str(&format!(", last match #{} of epoch {}", m, epoch));
+        }
+        rows.push('\n');
+    }
+    rows
+}
+
+fn table_rows(node: &InspectedNode, depth: usize, rows: &mut Vec<Vec<(String, &str)>>) {
+    let name = format!("{}{} #{}", "  ".repeat(depth), node.name, node.index);
+    let state = node.state.clone().unwrap_or_default();
+    let turn = node.turn.clone().unwrap_or_default();
+    let turn_style = if turn.contains("(us)") || turn.contains("we ") {
+        YELLOW
+    } else {
+        ""
+    };
+
+    if node.deadlines.is_empty() {
+        rows.push(vec![
+            (name.clone(), ""),
+            (state.clone(), CYAN),
+            (String::new(), ""),
+            (String::new(), ""),
+            (String::new(), ""),
+            (turn.clone(), turn_style),
+        ]);
+    }
+    for (i, deadline) in node.deadlines.iter().enumerate() {
+        let first = i == 0;
+        rows.push(vec![
+            (if first { name.clone() } else { String::new() }, ""),
+            (if first { state.clone() } else { String::new() }, CYAN),
+            (deadline.label.clone(), ""),
+            (format_time(deadline.at), ""),
+            (
+                format_countdown(deadline.remaining),
+                if deadline.remaining < 0 { RED } else { GREEN },
+            ),
+            (if first { turn.clone() } else { String::new() }, turn_style),
+        ]);
+    }
+    if let Some(ref service) = node.service {
+        rows.push(vec![
+            (String::new(), ""),
+            (String::new(), ""),
+            ("service".to_string(), ""),
+            (String::new(), ""),
+            (String::new(), ""),
+            (service.clone(), ""),
+        ]);
+    }
+
+    for child in &node.children {
+        table_rows(child, depth + 1, rows);
+    }
+}
+
+fn paint(text: &str, style: &str, colour: bool) -> String {
+    if colour && !style.is_empty() && !text.is_empty() {
+        format!("{}{}{}", style, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// `seconds` since `UNIX_EPOCH` as a UTC date and time
+pub fn format_time(seconds: u64) -> String {
+    // civil date from days since the epoch, after Howard Hinnant
+    let days = (seconds / 86400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let time = seconds % 86400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Time left until a deadline, or since it passed if negative
+pub fn format_countdown(remaining: i64) -> String {
+    let seconds = remaining.abs();
+    let span = if seconds >= 86400 {
+        format!(
+            "{}d {:02}h {:02}m",
+            seconds / 86400,
+            seconds % 86400 / 3600,
+            seconds % 3600 / 60
+        )
+    } else if seconds >= 3600 {
+        format!(
+            "{}h {:02}m {:02}s",
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60
+        )
+    } else if seconds >= 60 {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    };
+    if remaining < 0 {
+        format!("passed {} ago", span)
+    } else {
+        format!("in {}", span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use configuration::Concern;
+    use dappmock::DAppMockCtx;
+    use matchmanager::MatchManagerCtx;
+    use r#match::MatchCtx;
+    use reveal_commit::RevealCommitCtx;
+    use revealmock::RevealMockCtx;
+    use storage::use_test_dirs;
+
+    const PLAYER: [u8; 20] = [1; 20];
+    const NOW: u64 = 1_600_000_000;
+
+    fn concern(contract: u8) -> Concern {
+        Concern {
+            contract_address: Address::from([contract; 20]),
+            user_address: Address::from(PLAYER),
+        }
+    }
+
+    fn instance(
+        name: &str,
+        contract: u8,
+        json_data: String,
+        sub_instances: Vec<state::Instance>,
+    ) -> state::Instance {
+        state::Instance {
+            name: name.to_string(),
+            concern: concern(contract),
+            index: U256::from(0),
+            service_status: None,
+            json_data,
+            sub_instances: sub_instances.into_iter().map(Box::new).collect(),
+        }
+    }
+
+    // the player claims a match of epoch 1, against the winner of the
+    // other match of epoch 0
+    fn match_manager() -> state::Instance {
+        let ctx = MatchManagerCtx {
+            epoch_duration: U256::from(3_600),
+            round_duration: U256::from(60),
+            current_epoch: U256::from(1),
+            final_time: U256::from(100),
+            last_epoch_start_time: U256::from(NOW - 600),
+            number_of_matches_on_last_epoch: U256::from(2),
+            last_match_index: U256::from(2),
+            parent_instance: U256::from(0),
+            last_match_epoch: U256::from(1),
+            unmatched_player: Address::zero(),
+            machine: Address::from([8; 20]),
+            parent_address: Address::from([7; 20]),
+            last_match_claimer_score: U256::from(9),
+            last_match_claimer_commit_time: U256::from(10),
+            last_match_challenger_score: U256::from(5),
+            last_match_challenger_commit_time: U256::from(20),
+            registered: true,
+            current_state: "WaitingMatches".to_string(),
+        };
+        let match_ctx = MatchCtx {
+            challenger: Address::from([4; 20]),
+            claimer: Address::from(PLAYER),
+            machine: Address::from([8; 20]),
+            epoch_number: U256::from(1),
+            deadline: U256::from(NOW - 60),
+            final_time: U256::from(100),
+            log_hash: H256::from([5; 32]),
+            initial_hash: H256::from([6; 32]),
+            claimed_final_hash: H256::from([7; 32]),
+            current_state: "WaitingChallenge".to_string(),
+        };
+        instance(
+            "MatchManager",
+            10,
+            ctx.to_json_data(),
+            vec![instance("Match", 11, match_ctx.to_json_data(), vec![])],
+        )
+    }
+
+    // DAppMock > RevealMock > the match manager
+    fn dapp_mock() -> state::Instance {
+        let dapp_ctx = DAppMockCtx {
+            reveal_index: U256::from(0),
+            current_state: "DAppRunning".to_string(),
+        };
+        let reveal_ctx = RevealMockCtx {
+            commit_duration: U256::from(600),
+            reveal_duration: U256::from(600),
+            match_manager_epoch_duration: U256::from(3_600),
+            match_manager_match_duration: U256::from(60),
+            final_time: U256::from(100),
+            initial_hash: H256::from([6; 32]),
+            machine_address: Address::from([8; 20]),
+            current_state: "MatchManagerPhase".to_string(),
+        };
+        instance(
+            "DAppMock",
+            13,
+            dapp_ctx.to_json_data(),
+            vec![instance(
+                "RevealMock",
+                14,
+                reveal_ctx.to_json_data(),
+                vec![match_manager()],
+            )],
+        )
+    }
+
+    // the commit phase ends in 5 minutes, the player has not committed
+    fn reveal_commit() -> state::Instance {
+        let ctx = RevealCommitCtx {
+            instantiated_at: U256::from(NOW - 300),
+            commit_duration: U256::from(600),
+            reveal_duration: U256::from(600),
+            score_word_position: U256::from(0),
+            log_drive_position: U256::from(0),
+            log_drive_log_size: U256::from(10),
+            score_drive_log_size: U256::from(5),
+            template_hash: H256::from([6; 32]),
+            commit_hash: H256::zero(),
+            log_hash: H256::zero(),
+            has_revealed: false,
+            log_available: false,
+            current_state: "CommitPhase".to_string(),
+        };
+        instance("RevealCommit", 15, ctx.to_json_data(), vec![])
+    }
+
+    fn strip_colours(table: &str) -> String {
+        let mut plain = String::new();
+        let mut escape = false;
+        for c in table.chars() {
+            match c {
+                '\x1b' => escape = true,
+                'm' if escape => escape = false,
+                _ if escape => {}
+                _ => plain.push(c),
+            }
+        }
+        plain
+    }
+
+    fn event(signature: &str, words: Vec<String>) -> EventLog {
+        EventLog {
+            address: Address::from([12; 20]),
+            topics: vec![::merkle::keccak(&[signature.as_bytes()])],
+            data: format!("0x{}", words.concat()),
+        }
+    }
+
+    fn word(value: u64) -> String {
+        format!("{:064x}", value)
+    }
+
+    fn address_word(n: u8) -> String {
+        format!("{:0>64}", format!("{:x}", Address::from([n; 20])))
+    }
+
+    fn epoch_zero_events() -> Vec<EventLog> {
+        let created = |index: u64, challenger: u8, claimer: u8| {
+            let mut words = vec![
+                word(index),
+                address_word(challenger),
+                address_word(claimer),
+                word(0),
+            ];
+            words.extend((0..7).map(word));
+            event(
+                "MatchCreated(uint256,address,address,uint256,uint256,address,\
+                 bytes32,bytes32,bytes32,uint256,uint256)",
+                words,
+            )
+        };
+        let finished = |index: u64, state: u64| {
+            event(
+                "MatchFinished(uint256,uint8)",
+                vec![word(index), word(state)],
+            )
+        };
+        vec![
+            created(0, 2, 1),
+            created(1, 4, 3),
+            finished(0, 3),
+            finished(1, 2),
+        ]
+    }
+
+    #[test]
+    fn standings_follow_the_match_manager_and_the_logs() {
+        use_test_dirs();
+        let inspection = inspect(
+            RootDApp::MatchManager,
+            &match_manager(),
+            NOW,
+            &[],
+            &epoch_zero_events(),
+        )
+        .unwrap();
+
+        let standings = inspection.standings.clone().unwrap();
+        assert_eq!(standings.current_epoch, Some(U256::from(1)));
+        assert_eq!(standings.epochs.len(), 2);
+        let status = |n: u8| {
+            standings
+                .players
+                .iter()
+                .find(|p| p.player == Address::from([n; 20]))
+                .unwrap()
+                .status
+        };
+        assert_eq!(status(1), PlayerStatus::Playing);
+        assert_eq!(status(2), PlayerStatus::Eliminated);
+        assert_eq!(status(3), PlayerStatus::Eliminated);
+        assert_eq!(status(4), PlayerStatus::Playing);
+
+        let json: serde_json::Value = serde_json::from_str(&inspection.to_json()).unwrap();
+        assert_eq!(json["standings"]["players"].as_array().unwrap().len(), 4);
+        let table = inspection.to_table(false);
+        assert!(table.contains("standings: epoch 1, 3 matches known"));
+        assert!(table.contains(&format!("{:?} (us) playing, 1 won", Address::from(PLAYER))));
+    }
+
+    #[test]
+    fn no_standings_before_the_match_manager() {
+        use_test_dirs();
+        let reveal = instance("RevealCommit", 10, "{}".to_string(), vec![]);
+        let standings = super::standings(RootDApp::RevealCommit, &reveal, &[]).unwrap();
+        assert!(standings.is_none());
+    }
+
+    // the plain table starts with `rows`, and the coloured one only adds
+    // escapes to it
+    fn assert_table(inspection: &Inspection, rows: &[&str]) -> String {
+        let plain = inspection.to_table(false);
+        assert!(!plain.contains('\x1b'));
+        assert_eq!(plain.lines().take(rows.len()).collect::<Vec<_>>(), rows);
+        let coloured = inspection.to_table(true);
+        assert_eq!(strip_colours(&coloured), plain);
+        assert!(coloured.starts_with("\x1b[1mINSTANCE"));
+        coloured
+    }
+
+    fn to_json(inspection: &Inspection) -> serde_json::Value {
+        serde_json::from_str(&inspection.to_json()).unwrap()
+    }
+
+    #[test]
+    fn dapp_mock_tree_is_rendered() {
+        use_test_dirs();
+        let inspection = inspect(RootDApp::DAppMock, &dapp_mock(), NOW, &[], &[]).unwrap();
+
+        let coloured = assert_table(
+            &inspection,
+            &[
+                "INSTANCE             STATE              DEADLINE                   \
+                 AT                       COUNTDOWN          TURN",
+                "DAppMock #0          DAppRunning",
+                "  RevealMock #0      MatchManagerPhase",
+                "    MatchManager #0  WaitingMatches     epoch 1 ends               \
+                 2020-09-13 13:16:40 UTC  in 50m 00s         players play their matches",
+                "      Match #0       WaitingChallenge   WaitingChallenge deadline  \
+                 2020-09-13 12:25:40 UTC  passed 1m 00s ago  challenger \
+                 0x0404040404040404040404040404040404040404 challenges or lets the claim of \
+                 0x0101010101010101010101010101010101010101 (us) stand",
+            ],
+        );
+        assert!(coloured.contains("\x1b[36mDAppRunning"));
+        assert!(coloured.contains("\x1b[32min 50m 00s"));
+        assert!(coloured.contains("\x1b[31mpassed 1m 00s ago"));
+        assert!(coloured.contains("\x1b[33msend claimVictoryByTime(0)"));
+
+        let json = to_json(&inspection);
+        assert_eq!(json["root"], "d_app_mock");
+        let manager = &json["tree"]["children"][0]["children"][0];
+        assert_eq!(manager["name"], "MatchManager");
+        assert_eq!(manager["deadlines"][0]["remaining"], 3_000);
+        assert_eq!(manager["children"][0]["state"], "WaitingChallenge");
+        assert_eq!(json["next_action"]["action"], "transaction");
+        assert_eq!(
+            json["next_action"]["transaction"]["function"],
+            "claimVictoryByTime"
+        );
+        assert_eq!(json["standings"]["players"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reveal_commit_tree_is_rendered() {
+        use_test_dirs();
+        let inspection = inspect(RootDApp::RevealCommit, &reveal_commit(), NOW, &[], &[]).unwrap();
+
+        let coloured = assert_table(
+            &inspection,
+            &[
+                "INSTANCE         STATE        DEADLINE           AT                       \
+                 COUNTDOWN   TURN",
+                "RevealCommit #0  CommitPhase  commit phase ends  2020-09-13 12:31:40 UTC  \
+                 in 5m 00s   players commit, we have not committed",
+                "                              reveal phase ends  2020-09-13 12:41:40 UTC  \
+                 in 15m 00s",
+                "",
+                "account: 0x0101010101010101010101010101010101010101",
+                "inspected at: 2020-09-13 12:26:40 UTC",
+                "next action: idle",
+            ],
+        );
+        assert!(coloured.contains("\x1b[36mCommitPhase"));
+        assert!(coloured.contains("\x1b[33mplayers commit, we have not committed"));
+        assert!(!coloured.contains("standings:"));
+
+        let json = to_json(&inspection);
+        assert_eq!(json["root"], "reveal_commit");
+        assert_eq!(json["tree"]["name"], "RevealCommit");
+        assert_eq!(json["tree"]["deadlines"][0]["remaining"], 300);
+        assert_eq!(json["tree"]["deadlines"][1]["remaining"], 900);
+        assert_eq!(json["next_action"]["action"], "idle");
+        assert!(json["standings"].is_null());
+    }
+
+    #[test]
+    fn match_manager_tree_is_rendered() {
+        use_test_dirs();
+        let inspection = inspect(RootDApp::MatchManager, &match_manager(), NOW, &[], &[]).unwrap();
+
+        let coloured = assert_table(
+            &inspection,
+            &[
+                "INSTANCE         STATE             DEADLINE                   \
+                 AT                       COUNTDOWN          TURN",
+                "MatchManager #0  WaitingMatches    epoch 1 ends               \
+                 2020-09-13 13:16:40 UTC  in 50m 00s         players play their matches",
+                "  Match #0       WaitingChallenge  WaitingChallenge deadline  \
+                 2020-09-13 12:25:40 UTC  passed 1m 00s ago  challenger \
+                 0x0404040404040404040404040404040404040404 challenges or lets the claim of \
+                 0x0101010101010101010101010101010101010101 (us) stand",
+            ],
+        );
+        assert!(coloured.contains("\x1b[1mstandings:\x1b[0m epoch 1, 1 matches known"));
+        assert!(coloured.contains("(us) \x1b[33mplaying\x1b[0m, 0 won"));
+
+        let json = to_json(&inspection);
+        assert_eq!(json["root"], "match_manager");
+        assert_eq!(
+            json["tree"]["children"][0]["deadlines"][0]["remaining"],
+            -60
+        );
+        assert_eq!(json["next_action"]["transaction"]["instance"], "Match #0");
+        assert_eq!(json["standings"]["current_epoch"], "0x1");
+    }
+}
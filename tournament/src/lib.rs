@@ -20,11 +20,37 @@
 // rewritten, the entire component will be released under the Apache v2 license.
 
 #![warn(unused_extern_crates)]
+// every fallible function returns the large error-chain `Error`
+#![allow(clippy::result_large_err)]
+#[macro_use]
+mod macros;
+
+#[cfg(any(test, feature = "mock"))]
+pub mod archivemock;
+pub mod clock;
+pub mod commitment;
+pub mod config;
 pub mod dappmock;
+pub mod download;
+pub mod dryrun;
+pub mod http;
+pub mod inspector;
+pub mod logcache;
+pub mod machine_id;
 pub mod r#match;
 pub mod matchmanager;
+pub mod merkle;
+pub mod parent;
+pub mod policy;
 pub mod reveal_commit;
 pub mod revealmock;
+pub mod score;
+pub mod session;
+pub mod settlement;
+pub mod simulator;
+pub mod storage;
+pub mod verifier;
+pub mod view;
 
 extern crate configuration;
 extern crate error;
@@ -34,15 +60,19 @@ extern crate serde_derive;
 extern crate crypto;
 extern crate hex;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
 extern crate compute;
 extern crate dispatcher;
 extern crate ethabi;
 extern crate ethereum_types;
 extern crate logger_service;
+extern crate protobuf;
+extern crate rand;
 extern crate transaction;
-
-use ethereum_types::{Address, U256};
+#[macro_use]
+extern crate tournament_derive;
 
 pub use compute::Partition;
 pub use compute::MM;
@@ -62,6 +92,8 @@ pub use logger_service::{
 
 pub use dappmock::DAppMock;
 pub use matchmanager::MatchManager;
+pub use machine_id::{MachineId, MachinePurpose};
+pub use parent::{Tournament, TournamentParent};
 pub use r#match::{MachineTemplate, Match};
 pub use reveal_commit::{Params, Payload, RevealCommit};
 pub use revealmock::RevealMock;
@@ -130,16 +162,3 @@ pub fn get_logger_response(
         }
     }
 }
-
-// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-// we need to have a proper way to construct machine ids.
-// but this will only make real sense when we have the scripting
-// language or some other means to construct a machine inside the
-// blockchain.
-// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-
-pub fn build_machine_id(tournament_index: U256, player_address: &Address) -> String {
-    return format!("{:x}:{}", player_address, tournament_index);
-    //return "0000000000000000000000000000000000000000000000008888888888888888"
-    //    .to_string();
-}
@@ -0,0 +1,420 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Logs kept by their root, shared by every match and tournament of the
+//! node.
+//!
+//! A log is stored once as `{dir}/{root}.json.br.cpio` in the directory
+//! shared with the logger, so a log downloaded for one match is not
+//! fetched again for the next one, and two matches never write the same
+//! file. Files are checked against their root the first time they are
+//! used, and the oldest ones are evicted past the configured age or total
+//! size, unless an open session still reads them.
+
+use super::cartesi_base::MachineRequest;
+use super::config::tournament_config;
+use super::dryrun::is_dry_run;
+use super::error::*;
+use super::ethereum_types::H256;
+use super::merkle::get_root_of_data;
+use super::session::pinned_logs;
+use super::storage::log_file_path;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+const LOG_EXTENSION: &str = ".json.br.cpio";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogCacheConfig {
+    /// Directory of the cache, relative to `TOURNAMENT_LOGS_DIR`
+    #[serde(default = "default_dir")]
+    pub dir: String,
+    /// Total size of the cached logs, no limit if missing
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Age after which a cached log is evicted, no limit if missing
+    #[serde(default = "default_max_age_seconds")]
+    pub max_age_seconds: Option<u64>,
+}
+
+fn default_dir() -> String {
+    "log-cache".to_string()
+}
+
+fn default_max_age_seconds() -> Option<u64> {
+    // longer than any tournament the node is expected to play
+    Some(7 * 24 * 3600)
+}
+
+impl Default for LogCacheConfig {
+    fn default() -> LogCacheConfig {
+        LogCacheConfig {
+            dir: default_dir(),
+            max_bytes: None,
+            max_age_seconds: default_max_age_seconds(),
+        }
+    }
+}
+
+/// A log in the cache
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CacheEntry {
+    pub root: H256,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+lazy_static! {
+    // cached logs already checked against their root by this process
+    static ref VERIFIED: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+#[derive(Clone)]
+pub struct LogCache {
+    config: LogCacheConfig,
+}
+
+impl LogCache {
+    pub fn new(config: LogCacheConfig) -> LogCache {
+        LogCache { config }
+    }
+
+    /// The cache of the process-wide configuration
+    pub fn from_config() -> Result<LogCache> {
+        Ok(LogCache::new(tournament_config()?.log_cache.clone()))
+    }
+
+    /// Path of the log of `root`, as named to the logger
+    pub fn logger_path(&self, root: &H256) -> String {
+        format!("{}/{:x}{}", self.config.dir, root, LOG_EXTENSION)
+    }
+
+    /// Local path of the log of `root`
+    pub fn local_path(&self, root: &H256) -> PathBuf {
+        log_file_path(&self.logger_path(root))
+    }
+
+    /// Creates the cache directory, so the logger can store logs in it
+    pub fn create_dir(&self) -> Result<()> {
+        if is_dry_run() {
+            return Ok(());
+        }
+        let dir = log_file_path(&self.config.dir);
+        fs::create_dir_all(&dir)
+            .chain_err(|| format!("Could not create log cache {}", dir.display()))
+    }
+
+    /// Path of the log of `root` as named to the logger, if it is cached.
+    /// A cached file that does not match its root, or is not a log of
+    /// 2^`log2_size` bytes, is removed.
+    pub fn get(&self, root: &H256, log2_size: u64) -> Result<Option<String>> {
+        let path = self.local_path(root);
+        if !path.exists() {
+            return Ok(None);
+        }
+        if VERIFIED.lock().unwrap().contains(&path) {
+            return Ok(Some(self.logger_path(root)));
+        }
+
+        let data =
+            fs::read(&path).chain_err(|| format!("Could not read log {}", path.display()))?;
+        match get_root_of_data(&data, log2_size) {
+            Ok(ref actual) if actual == root => {}
+            Ok(actual) => {
+                warn!(
+                    "Removing cached log {}, its root is {:x}",
+                    path.display(),
+                    actual
+                );
+                self.remove(root)?;
+                return Ok(None);
+            }
+            Err(e) => {
+                warn!("Removing cached log {}: {}", path.display(), e);
+                self.remove(root)?;
+                return Ok(None);
+            }
+        }
+        VERIFIED.lock().unwrap().insert(path);
+        Ok(Some(self.logger_path(root)))
+    }
+
+    /// Caches `data` as the log of `root`, returning its path as named to
+    /// the logger. Fails if `data` does not match `root`.
+    pub fn insert(&self, root: &H256, data: &[u8], log2_size: u64) -> Result<String> {
+        let actual = get_root_of_data(data, log2_size)?;
+        if actual != *root {
+            return Err(
+                format!("Log with root {:x} cannot be cached as {:x}", actual, root).into(),
+            );
+        }
+
+        if is_dry_run() {
+            return Ok(self.logger_path(root));
+        }
+        self.create_dir()?;
+        let path = self.local_path(root);
+        // written aside and renamed, a concurrent reader never sees half a log
+        let partial = path.with_extension(format!("partial.{}", ::std::process::id()));
+        fs::write(&partial, data)
+            .chain_err(|| format!("Could not write log {}", partial.display()))?;
+        fs::rename(&partial, &path)
+            .chain_err(|| format!("Could not move log to {}", path.display()))?;
+        trace!("Cached log {:x} at {}", root, path.display());
+        VERIFIED.lock().unwrap().insert(path);
+
+        self.evict(root)?;
+        Ok(self.logger_path(root))
+    }
+
+    /// Caches the log at the local `path`, returning its root and its path
+    /// in the cache as named to the logger
+    pub fn insert_file(&self, path: &Path, log2_size: u64) -> Result<(H256, String)> {
+        let data = fs::read(path).chain_err(|| format!("Could not read log {}", path.display()))?;
+        let root = get_root_of_data(&data, log2_size)?;
+        if path == self.local_path(&root).as_path() {
+            VERIFIED.lock().unwrap().insert(path.to_path_buf());
+            self.evict(&root)?;
+            return Ok((root, self.logger_path(&root)));
+        }
+        let cached = self.insert(&root, &data, log2_size)?;
+        Ok((root, cached))
+    }
+
+    /// Logs in the cache, oldest first
+    pub fn entries(&self) -> Result<Vec<CacheEntry>> {
+        let dir = log_file_path(&self.config.dir);
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut entries = vec![];
+        for file in fs::read_dir(&dir).chain_err(|| format!("Could not list {}", dir.display()))? {
+            let file = file.chain_err(|| format!("Could not list {}", dir.display()))?;
+            let name = file.file_name().to_string_lossy().into_owned();
+            if !name.ends_with(LOG_EXTENSION) {
+                continue;
+            }
+            let root: H256 = match name[..name.len() - LOG_EXTENSION.len()].parse() {
+                Ok(root) => root,
+                Err(_) => continue,
+            };
+            let metadata = file
+                .metadata()
+                .chain_err(|| format!("Could not stat {}", name))?;
+            entries.push(CacheEntry {
+                root,
+                size: metadata.len(),
+                modified: metadata
+                    .modified()
+                    .chain_err(|| format!("Could not stat {}", name))?,
+            });
+        }
+        entries.sort_by_key(|a| a.modified);
+        Ok(entries)
+    }
+
+    /// Removes the logs past the maximum age, then the oldest ones until
+    /// the cache fits its maximum size, always keeping `keep` and the logs
+    /// read by open sessions
+    pub fn evict(&self, keep: &H256) -> Result<Vec<H256>> {
+        let entries = self.entries()?;
+        let mut pinned = pinned_logs()?;
+        pinned.insert(*keep);
+        let now = SystemTime::now();
+        let mut total: u64 = entries.iter().map(|e| e.size).sum();
+        let mut evicted = vec![];
+
+        for entry in entries.iter().filter(|e| !pinned.contains(&e.root)) {
+            let age = now
+                .duration_since(entry.modified)
+                .unwrap_or(Duration::from_secs(0));
+            let too_old = match self.config.max_age_seconds {
+                Some(max_age) => age > Duration::from_secs(max_age),
+                None => false,
+            };
+            let too_big = match self.config.max_bytes {
+                Some(max_bytes) => total > max_bytes,
+                None => false,
+            };
+            if too_old || too_big {
+                self.remove(&entry.root)?;
+                total -= entry.size;
+                evicted.push(entry.root);
+            }
+        }
+
+        if !evicted.is_empty() {
+            info!("Evicted {} logs from the cache", evicted.len());
+        }
+        Ok(evicted)
+    }
+
+    fn remove(&self, root: &H256) -> Result<()> {
+        if is_dry_run() {
+            return Ok(());
+        }
+        let path = self.local_path(root);
+        VERIFIED.lock().unwrap().remove(&path);
+        fs::remove_file(&path).chain_err(|| format!("Could not remove log {}", path.display()))
+    }
+}
+
+/// `machine` with the flash drive starting at `position` backed by the
+/// log at the local `image`
+pub fn mount_log(machine: &MachineRequest, position: u64, image: &Path) -> Result<MachineRequest> {
+    let mut machine = machine.clone();
+    if !machine.has_config() {
+        return Err("Cannot mount a log in a machine loaded from a directory".into());
+    }
+    let drive = machine
+        .mut_config()
+        .mut_flash_drive()
+        .iter_mut()
+        .find(|d| d.get_start() == position)
+        .ok_or(Error::from(format!(
+            "The machine has no flash drive at {:#x} to mount the log in",
+            position
+        )))?;
+    drive.set_image_filename(image.to_string_lossy().into_owned());
+    Ok(machine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use archivemock::ArchiveMock;
+    use dispatcher::Reaction;
+    use ethereum_types::Address;
+    use machine_id::{MachineId, MachinePurpose};
+    use r#match::MachineTemplate;
+    use session::open_session;
+    use std::thread;
+    use storage::use_test_dirs;
+
+    fn log(seed: u8) -> (H256, Vec<u8>) {
+        let data = vec![seed; 1024];
+        (get_root_of_data(&data, 10).unwrap(), data)
+    }
+
+    fn cache(dir: &str, max_bytes: Option<u64>, max_age_seconds: Option<u64>) -> LogCache {
+        LogCache::new(LogCacheConfig {
+            dir: dir.to_string(),
+            max_bytes,
+            max_age_seconds,
+        })
+    }
+
+    // opens a session of tournament `tournament_index` reading `root`
+    fn pin(tournament_index: u64, root: H256) {
+        let template = MachineTemplate {
+            tournament_index: tournament_index.into(),
+            ..Default::default()
+        };
+        let id = MachineId::new(
+            &template,
+            Address::from([1; 20]),
+            MachinePurpose::Verification,
+        );
+        let mut archive = ArchiveMock::new().unwrap();
+        archive.on_new_session(&id.to_string(), Err("AlreadyExists".to_string()));
+        archive
+            .react(|a| {
+                open_session(a, &id, &Default::default(), Some(root)).map(|_| Reaction::Idle)
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn log_of_another_root_is_removed() {
+        use_test_dirs();
+        let cache = cache("log-cache-mismatch", None, None);
+        let (root, _) = log(1);
+        let (_, other_data) = log(2);
+        cache.create_dir().unwrap();
+        fs::write(cache.local_path(&root), other_data).unwrap();
+
+        assert_eq!(cache.get(&root, 10).unwrap(), None);
+        assert!(!cache.local_path(&root).exists());
+    }
+
+    #[test]
+    fn corrupted_log_is_removed() {
+        use_test_dirs();
+        let cache = cache("log-cache-corrupted", None, None);
+        let (root, _) = log(3);
+        cache.create_dir().unwrap();
+        // larger than a log can be
+        fs::write(cache.local_path(&root), vec![3; 2048]).unwrap();
+
+        assert_eq!(cache.get(&root, 10).unwrap(), None);
+        assert!(!cache.local_path(&root).exists());
+        assert!(cache.insert(&root, &[3; 2048], 10).is_err());
+    }
+
+    #[test]
+    fn logs_past_their_age_are_evicted() {
+        use_test_dirs();
+        let cache = cache("log-cache-age", None, Some(0));
+        let (old, old_data) = log(4);
+        let (pinned, pinned_data) = log(5);
+        let (new, new_data) = log(6);
+
+        cache.insert(&old, &old_data, 10).unwrap();
+        cache.insert(&pinned, &pinned_data, 10).unwrap();
+        pin(22, pinned);
+        thread::sleep(Duration::from_millis(10));
+        cache.insert(&new, &new_data, 10).unwrap();
+
+        assert!(!cache.local_path(&old).exists());
+        assert!(cache.local_path(&pinned).exists());
+        assert!(cache.local_path(&new).exists());
+        assert_eq!(cache.entries().unwrap().len(), 2);
+
+        let young = LogCache::new(LogCacheConfig {
+            dir: "log-cache-age".to_string(),
+            max_bytes: None,
+            max_age_seconds: Some(3600),
+        });
+        assert!(young.evict(&H256::zero()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn logs_of_open_sessions_are_not_evicted() {
+        use_test_dirs();
+        let cache = cache("log-cache-pinned", Some(1024), None);
+        let (pinned, pinned_data) = log(11);
+        let (evicted, evicted_data) = log(12);
+        let (kept, kept_data) = log(13);
+
+        cache.insert(&pinned, &pinned_data, 10).unwrap();
+        pin(21, pinned);
+
+        assert!(cache.insert(&evicted, &evicted_data, 10).is_ok());
+        assert!(cache.insert(&kept, &kept_data, 10).is_ok());
+        assert!(cache.local_path(&pinned).exists());
+        assert!(!cache.local_path(&evicted).exists());
+        assert!(cache.local_path(&kept).exists());
+    }
+}
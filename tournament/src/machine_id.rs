@@ -0,0 +1,222 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Ids of the machines, and emulator sessions, of the tournament.
+//!
+//! An id is unique to the chain, the reveal contract and instance of the
+//! tournament, the player whose log the machine runs and what the machine
+//! is run for, formatted as
+//! `{chain_id}:{reveal_address:x}:{tournament_index}:{player:x}:{purpose}`.
+
+use super::error::*;
+use super::ethereum_types::{Address, U256};
+use super::hex;
+use r#match::MachineTemplate;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MachinePurpose {
+    /// The player's own machine, revealed and defended as claimer
+    Reveal,
+    /// An opponent's machine, run to verify its claim
+    Verification,
+}
+
+impl fmt::Display for MachinePurpose {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MachinePurpose::Reveal => write!(f, "reveal"),
+            MachinePurpose::Verification => write!(f, "verification"),
+        }
+    }
+}
+
+impl FromStr for MachinePurpose {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<MachinePurpose> {
+        match s {
+            "reveal" => Ok(MachinePurpose::Reveal),
+            "verification" => Ok(MachinePurpose::Verification),
+            _ => Err(format!("Unknown machine purpose {}", s).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineId {
+    pub chain_id: u64,
+    pub reveal_address: Address,
+    pub tournament_index: U256,
+    pub player: Address,
+    pub purpose: MachinePurpose,
+}
+
+impl MachineId {
+    /// Machine of `player` in the tournament of `machine_template`
+    pub fn new(
+        machine_template: &MachineTemplate,
+        player: Address,
+        purpose: MachinePurpose,
+    ) -> MachineId {
+        MachineId {
+            chain_id: machine_template.chain_id,
+            reveal_address: machine_template.reveal_address,
+            tournament_index: machine_template.tournament_index,
+            player,
+            purpose,
+        }
+    }
+
+    /// Machine `user` runs in a match against `claimer`: its own if it is
+    /// the claimer, the claimer's to verify otherwise
+    pub fn for_match(
+        machine_template: &MachineTemplate,
+        user: Address,
+        claimer: Address,
+    ) -> MachineId {
+        match user == claimer {
+            true => MachineId::new(machine_template, user, MachinePurpose::Reveal),
+            false => MachineId::new(machine_template, claimer, MachinePurpose::Verification),
+        }
+    }
+
+    /// Whether the machine belongs to the tournament of `machine_template`
+    pub fn in_tournament(&self, machine_template: &MachineTemplate) -> bool {
+        self.chain_id == machine_template.chain_id
+            && self.reveal_address == machine_template.reveal_address
+            && self.tournament_index == machine_template.tournament_index
+    }
+}
+
+impl fmt::Display for MachineId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{:x}:{}:{:x}:{}",
+            self.chain_id, self.reveal_address, self.tournament_index, self.player, self.purpose
+        )
+    }
+}
+
+impl FromStr for MachineId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<MachineId> {
+        let parts: Vec<&str> = s.split(':').collect();
+        if parts.len() != 5 {
+            return Err(format!(
+                "Machine id {} should be chain_id:reveal_address:tournament_index:player:purpose",
+                s
+            )
+            .into());
+        }
+
+        Ok(MachineId {
+            chain_id: parts[0]
+                .parse()
+                .chain_err(|| format!("Invalid chain id in machine id {}", s))?,
+            reveal_address: parse_address(parts[1])
+                .chain_err(|| format!("Invalid reveal address in machine id {}", s))?,
+            tournament_index: U256::from_dec_str(parts[2])
+                .map_err(|e| format!("Invalid tournament index in machine id {}: {:?}", s, e))?,
+            player: parse_address(parts[3])
+                .chain_err(|| format!("Invalid player in machine id {}", s))?,
+            purpose: parts[4].parse()?,
+        })
+    }
+}
+
+fn parse_address(s: &str) -> Result<Address> {
+    let bytes = hex::decode(s.trim_start_matches("0x"))
+        .map_err(|e| format!("{} is not hexadecimal: {}", s, e))?;
+    if bytes.len() != 20 {
+        return Err(format!("{} has {} bytes, addresses have 20", s, bytes.len()).into());
+    }
+    Ok(Address::from_slice(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use configuration::Concern;
+
+    const PLAYER: [u8; 20] = [1; 20];
+
+    fn reveal_instance(contract: u8, index: u64) -> state::Instance {
+        state::Instance {
+            name: "RevealMock".to_string(),
+            concern: Concern {
+                contract_address: Address::from([contract; 20]),
+                user_address: Address::from(PLAYER),
+            },
+            index: U256::from(index),
+            service_status: None,
+            json_data: "{}".to_string(),
+            sub_instances: vec![],
+        }
+    }
+
+    fn id(template: &MachineTemplate) -> MachineId {
+        MachineId::new(template, Address::from(PLAYER), MachinePurpose::Reveal)
+    }
+
+    #[test]
+    fn tournaments_of_two_contracts_give_distinct_ids() {
+        let first = MachineTemplate::from_config()
+            .unwrap()
+            .in_reveal(&reveal_instance(2, 0));
+        let second = MachineTemplate::from_config()
+            .unwrap()
+            .in_reveal(&reveal_instance(3, 0));
+
+        assert_ne!(id(&first), id(&second));
+        assert_ne!(id(&first).to_string(), id(&second).to_string());
+        assert!(id(&first).in_tournament(&first));
+        assert!(!id(&first).in_tournament(&second));
+    }
+
+    #[test]
+    fn tournaments_of_two_chains_give_distinct_ids() {
+        let instance = reveal_instance(2, 0);
+        let mut first = MachineTemplate::from_config().unwrap().in_reveal(&instance);
+        let mut second = MachineTemplate::from_config().unwrap().in_reveal(&instance);
+        first.chain_id = 1;
+        second.chain_id = 1337;
+
+        assert_ne!(id(&first).to_string(), id(&second).to_string());
+    }
+
+    #[test]
+    fn id_round_trips() {
+        let template = MachineTemplate::from_config()
+            .unwrap()
+            .in_reveal(&reveal_instance(2, 7));
+        let id = MachineId::for_match(&template, Address::from(PLAYER), Address::from([4; 20]));
+
+        assert_eq!(id.to_string().parse::<MachineId>().unwrap(), id);
+        assert_eq!(id.purpose, MachinePurpose::Verification);
+        assert_eq!(id.reveal_address, Address::from([2; 20]));
+        assert_eq!(id.tournament_index, U256::from(7));
+    }
+}
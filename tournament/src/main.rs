@@ -29,7 +29,9 @@ extern crate tournament;
 extern crate utils;
 
 use dispatcher::Dispatcher;
+use tournament::config::tournament_config;
 use tournament::dappmock::DAppMock;
+use tournament::dryrun::DryRunDApp;
 use utils::print_error;
 
 fn main() {
@@ -43,5 +45,17 @@ fn main() {
         }
     };
 
-    dispatcher.run::<DAppMock>();
+    let config = match tournament_config() {
+        Ok(c) => c,
+        Err(ref e) => {
+            print_error(e);
+            return;
+        }
+    };
+
+    if config.dry_run.enabled {
+        dispatcher.run::<DryRunDApp<DAppMock>>();
+    } else {
+        dispatcher.run::<DAppMock>();
+    }
 }
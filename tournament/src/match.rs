@@ -19,7 +19,6 @@
 // be used independently under the Apache v2 license. After this component is
 // rewritten, the entire component will be released under the Apache v2 license.
 
-use super::configuration::Concern;
 use super::dispatcher::{AddressArray3, Bytes32Array3, String32Field, U256Array3};
 use super::dispatcher::{Archive, DApp, Reaction};
 use super::error::Result;
@@ -28,51 +27,73 @@ use super::ethabi::Token;
 use super::ethereum_types::{Address, H256, U256};
 use super::transaction;
 use super::transaction::TransactionRequest;
-use super::{build_machine_id, build_session_run_key};
+use super::build_session_run_key;
 use super::{
-    cartesi_base, DownloadFileRequest, DownloadFileResponse, NewSessionRequest, NewSessionResult, Role,
-    SessionRunRequest, SessionRunResult, EMULATOR_METHOD_NEW, EMULATOR_METHOD_RUN,
-    EMULATOR_SERVICE_NAME, LOGGER_METHOD_DOWNLOAD, LOGGER_SERVICE_NAME, VG, get_logger_response
+    cartesi_base, Role, SessionRunRequest, SessionRunResult,
+    EMULATOR_METHOD_RUN, EMULATOR_SERVICE_NAME, VG,
 };
-use super::{VGCtx, VGCtxParsed, win_by_deadline_or_idle};
-
-use std::time::{SystemTime, UNIX_EPOCH};
+use super::{VGCtx, VGCtxParsed};
+use super::clock::{default_clock, Clock};
+use super::config::tournament_config;
+use super::download::{download_log, DownloadOutcome, RetryBudget};
+use super::dryrun::is_dry_run;
+use super::logcache::mount_log;
+use super::storage::log_file_path;
+use super::policy::{ChallengeDecision, ChallengePolicy};
+use super::score::ScoreOrdering;
+use super::machine_id::{MachineId, MachinePurpose};
+use super::session::open_session;
+
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::sync::{Arc, Mutex};
 
 pub struct Match();
 
-// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-// these two structs and the From trait below shuld be
-// obtained from a simple derive
-// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-#[derive(Serialize, Deserialize)]
-pub struct MatchCtxParsed(
-    AddressArray3, // challenger
-    // claimer
-    // machine
-    U256Array3, // epochNumber
-    // deadline
-    // finalTime
-    Bytes32Array3, // logHash
-    // initialHash
-    // finalHash
-    String32Field, // currentState
-);
-
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, DAppContext)]
+#[get_state(
+    _addressValues(AddressArray3),
+    _uintValues(U256Array3),
+    _bytesValues(Bytes32Array3),
+    _currentState(String32Field)
+)]
 pub struct MatchCtx {
+    #[slot(_addressValues)]
     pub challenger: Address,
+    #[slot(_addressValues)]
     pub claimer: Address,
+    #[slot(_addressValues)]
     pub machine: Address,
+
+    #[slot(_uintValues)]
     pub epoch_number: U256,
+    #[slot(_uintValues)]
     pub deadline: U256,
+    #[slot(_uintValues)]
+    pub final_time: U256,
+
+    #[slot(_bytesValues)]
     pub log_hash: H256,
+    #[slot(_bytesValues)]
     pub initial_hash: H256,
+    #[slot(_bytesValues)]
     pub claimed_final_hash: H256,
-    pub final_time: U256,
+
+    #[slot(_currentState)]
     pub current_state: String,
 }
 
-#[derive(Default)]
+contract_state! {
+    /// States of `MatchInterface.sol`
+    pub enum MatchState {
+        WaitingChallenge,
+        ChallengeStarted,
+        ChallengerWon,
+        ClaimerWon,
+    }
+}
+
 pub struct MachineTemplate {
     pub machine: cartesi_base::MachineRequest,
     pub opponent_machine: cartesi_base::MachineRequest,
@@ -80,25 +101,58 @@ pub struct MachineTemplate {
     pub page_log2_size: u64,
     pub tree_log2_size: u64,
     pub final_time: u64,
+    /// Chain the tournament is played on
+    pub chain_id: u64,
+    /// Reveal contract of the tournament
+    pub reveal_address: Address,
+    /// Start of the log drive, where the opponent's log is mounted. Without
+    /// it, the opponent machine of match `{match_index}` reads
+    /// `{tournament_index}_{match_index}_opponent.json.br.cpio`, a link to
+    /// the cached log.
+    pub log_drive_position: Option<u64>,
+    /// Time against which the deadlines are checked
+    pub clock: Arc<dyn Clock>,
+    /// Which player of a match is expected to be the claimer
+    pub score_ordering: ScoreOrdering,
 }
 
-impl From<MatchCtxParsed> for MatchCtx {
-    fn from(parsed: MatchCtxParsed) -> MatchCtx {
-        MatchCtx {
-            challenger: parsed.0.value[0],
-            claimer: parsed.0.value[1],
-            machine: parsed.0.value[2],
-            epoch_number: parsed.1.value[0],
-            deadline: parsed.1.value[1],
-            final_time: parsed.1.value[2],
-            log_hash: parsed.2.value[0],
-            initial_hash: parsed.2.value[1],
-            claimed_final_hash: parsed.2.value[2],
-            current_state: parsed.3.value,
+impl Default for MachineTemplate {
+    fn default() -> MachineTemplate {
+        MachineTemplate {
+            machine: Default::default(),
+            opponent_machine: Default::default(),
+            tournament_index: Default::default(),
+            page_log2_size: Default::default(),
+            tree_log2_size: Default::default(),
+            final_time: Default::default(),
+            chain_id: Default::default(),
+            reveal_address: Default::default(),
+            log_drive_position: None,
+            clock: default_clock(),
+            score_ordering: Default::default(),
         }
     }
 }
 
+impl MachineTemplate {
+    /// Template of a tournament played with the configured settings
+    pub fn from_config() -> Result<MachineTemplate> {
+        let config = tournament_config()?;
+        Ok(MachineTemplate {
+            chain_id: config.chain_id,
+            score_ordering: config.score_ordering,
+            ..Default::default()
+        })
+    }
+
+    /// The template of the tournament of `reveal_instance`
+    pub fn in_reveal(mut self, reveal_instance: &state::Instance) -> MachineTemplate {
+        self.reveal_address = reveal_instance.concern.contract_address;
+        self.tournament_index = reveal_instance.index;
+        self
+    }
+}
+
 impl DApp<MachineTemplate> for Match {
     /// React to the Match contract, submitting solutions, confirming
     /// or challenging them when appropriate
@@ -118,248 +172,142 @@ impl DApp<MachineTemplate> for Match {
         let ctx: MatchCtx = parsed.into();
         trace!("Context for match (index {}) {:?}", instance.index, ctx);
 
-        // these states should not occur as they indicate an innactive instance,
-        // but it is possible that the blockchain state changed between queries
-        match ctx.current_state.as_ref() {
-            "ChallengerWon" | "ClaimerWon" => {
-                return Ok(Reaction::Idle);
-            }
-            _ => {}
-        };
+        let state: MatchState = ctx.current_state.parse()?;
 
-        // if we reach this code, the instance is active, get user's role
-        let role = match instance.concern.user_address {
-            cl if (cl == ctx.claimer) => Role::Claimer,
-            ch if (ch == ctx.challenger) => Role::Challenger,
-            _ => {
-                return Err(Error::from(ErrorKind::InvalidContractState(String::from(
-                    "User is neither claimer nor challenger",
-                ))));
+        match state {
+            // these states should not occur as they indicate an innactive instance,
+            // but it is possible that the blockchain state changed between queries
+            MatchState::ChallengerWon | MatchState::ClaimerWon => {
+                Ok(Reaction::Idle)
             }
-        };
-        trace!("Role played (index {}) is: {:?}", instance.index, role);
-
-        match role {
-            Role::Claimer => match ctx.current_state.as_ref() {
-                "WaitingChallenge" => {
-                    return win_by_deadline_or_idle(
-                        &instance.concern,
-                        instance.index,
-                        ctx.deadline.as_u64(),
-                    );
-                }
 
-                "ChallengeStarted" => {
-                    // we inspect the verification contract
-                    let vg_instance = instance.sub_instances.get(0).ok_or(Error::from(
-                        ErrorKind::InvalidContractState(format!(
-                            "There is no vg instance {}",
-                            ctx.current_state
-                        )),
-                    ))?;
-                    let vg_parsed: VGCtxParsed = serde_json::from_str(&vg_instance.json_data)
-                        .chain_err(|| {
-                            format!(
-                                "Could not parse vg instance json_data: {}",
-                                &vg_instance.json_data
-                            )
-                        })?;
-                    let vg_ctx: VGCtx = vg_parsed.into();
-
-                    match vg_ctx.current_state.as_ref() {
-                        "FinishedClaimerWon" => {
-                            // claim victory in compute contract
-                            info!("Claiming victory by VG (index: {})", instance.index);
-                            let request = TransactionRequest {
-                                concern: instance.concern.clone(),
-                                value: U256::from(0),
-                                function: "winByVG".into(),
-                                data: vec![Token::Uint(instance.index)],
-                                gas: None,
-                                strategy: transaction::Strategy::Simplest,
-                            };
-                            return Ok(Reaction::Transaction(request));
-                        }
-                        "FinishedChallengerWon" => {
-                            error!("we lost a verification game {:?}", vg_ctx);
-                            return Ok(Reaction::Idle);
-                        }
-                        _ => {
-                            // verification game is still active,
-                            // pass control to the appropriate dapp
-                            let id =
-                                build_machine_id(machine_template.tournament_index, &ctx.claimer);
-                            return VG::react(vg_instance, archive, &None, &id);
-                        }
+            MatchState::WaitingChallenge => match get_role(instance, &ctx)? {
+                Role::Claimer => {
+                    // a victory already earned is claimed before anything else
+                    let reaction = claim_victory_by_time_or_idle(
+                        instance,
+                        ctx.deadline.as_u64(),
+                        &*machine_template.clock,
+                    )?;
+                    if let Reaction::Idle = reaction {
+                        audit_claim(archive, instance, &ctx, machine_template)?;
                     }
+                    Ok(reaction)
                 }
-                _ => {
-                    return Err(Error::from(ErrorKind::InvalidContractState(format!(
-                        "Unknown current state {}",
-                        ctx.current_state
-                    ))));
-                }
-            },
-            Role::Challenger => match ctx.current_state.as_ref() {
-                "WaitingChallenge" => {
+                Role::Challenger => {
+                    let policy = tournament_config()?
+                        .challenge_policy(&instance.concern.user_address)
+                        .build();
+                    if !policy.verifies() {
+                        trace!(
+                            "Challenge policy accepts claim {:?} (index {}) without verifying",
+                            ctx.claimed_final_hash,
+                            instance.index
+                        );
+                        return Ok(Reaction::Idle);
+                    }
+
                     // download the log of the opponent with given hash
                     trace!("Download file for hash: {:?}...", ctx.log_hash);
 
-                    let request = DownloadFileRequest {
-                        root: ctx.log_hash.clone(),
-                        path: format!("{}_opponent.json.br.cpio", machine_template.tournament_index),
-                        page_log2_size: machine_template.page_log2_size,
-                        tree_log2_size: machine_template.tree_log2_size,
+                    let budget = RetryBudget::for_challenge(
+                        ctx.deadline.as_u64(),
+                        ctx.final_time.as_u64(),
+                        tournament_config()?.download.reserve_seconds,
+                    );
+                    let path = match download_log(
+                        archive,
+                        "Match",
+                        ctx.log_hash,
+                        machine_template,
+                        &budget,
+                    )? {
+                        DownloadOutcome::Ready { path } => {
+                            trace!("Downloaded! File stored at: {}...", path);
+                            path
+                        }
+                        DownloadOutcome::OutOfTime { deadline } => {
+                            error!(
+                                "Cannot verify opponent in time: log {:?} not downloaded by {} \
+                                 (match {} expires at {}), not challenging",
+                                ctx.log_hash, deadline, instance.index, ctx.deadline
+                            );
+                            return Ok(Reaction::Idle);
+                        }
                     };
-
-                    let processed_response: DownloadFileResponse = get_logger_response(
-                            archive,
-                            "Match".into(),
-                            LOGGER_SERVICE_NAME.to_string(),
-                            format!("{:x}", ctx.log_hash.clone()),
-                            LOGGER_METHOD_DOWNLOAD.to_string(),
-                            request.into(),
-                        )?
-                        .into();
-                    trace!("Downloaded! File stored at: {}...", processed_response.path);
+                    let opponent_machine =
+                        opponent_machine(machine_template, instance.index, &path)?;
 
                     // machine id
-                    let id = build_machine_id(machine_template.tournament_index, &ctx.claimer);
+                    let id =
+                        MachineId::new(machine_template, ctx.claimer, MachinePurpose::Verification);
+
+                    let hashes = run_machine(
+                        archive,
+                        &id,
+                        &opponent_machine,
+                        ctx.final_time.as_u64(),
+                        Some(ctx.log_hash),
+                    )?;
+
+                    react_to_verification(instance, &ctx, &*policy, &id, &hashes)
+                }
+            },
 
-                    let request = NewSessionRequest {
-                        session_id: id.clone(),
-                        machine: machine_template.opponent_machine.clone(),
-                    };
+            MatchState::ChallengeStarted => {
+                let role = get_role(instance, &ctx)?;
 
-                    // send newSession request to the emulator service
-                    let id_clone = id.clone();
-                    let duplicate_session_msg = format!(
-                        "Trying to register a session with a session_id that already exists: {}",
-                        id
-                    );
-                    let _processed_response: NewSessionResult = archive
-                        .get_response(
-                            EMULATOR_SERVICE_NAME.to_string(),
-                            id.clone(),
-                            EMULATOR_METHOD_NEW.to_string(),
-                            request.into(),
-                        )?
-                        .map_err(move |e| {
-                            if e == duplicate_session_msg {
-                                Error::from(ErrorKind::ResponseNeedsDummy(
-                                    EMULATOR_SERVICE_NAME.to_string(),
-                                    id_clone,
-                                    EMULATOR_METHOD_NEW.to_string(),
-                                ))
-                            } else {
-                                Error::from(ErrorKind::ResponseInvalidError(
-                                    EMULATOR_SERVICE_NAME.to_string(),
-                                    id_clone,
-                                    EMULATOR_METHOD_NEW.to_string(),
-                                ))
-                            }
-                        })?
-                        .into();
-
-                    // here goes the calculation of the final hash
-                    // to check the claim and potentialy raise challenge
-                    let sample_points: Vec<u64> = vec![0, ctx.final_time.as_u64()];
-                    let request = SessionRunRequest {
-                        session_id: id.clone(),
-                        times: sample_points.clone(),
-                    };
-                    let archive_key = build_session_run_key(id.clone(), sample_points.clone());
-
-                    trace!("Calculating final hash of machine {}", id);
-                    // have we sampled the final time?
-                    let processed_response: SessionRunResult = archive
-                        .get_response(
-                            EMULATOR_SERVICE_NAME.to_string(),
-                            archive_key.clone(),
-                            EMULATOR_METHOD_RUN.to_string(),
-                            request.into(),
-                        )?
-                        .map_err(move |_e| {
-                            Error::from(ErrorKind::ResponseInvalidError(
-                                EMULATOR_SERVICE_NAME.to_string(),
-                                archive_key,
-                                EMULATOR_METHOD_RUN.to_string(),
-                            ))
-                        })?
-                        .into();
-
-                    let hash = processed_response.hashes[1];
-                    if hash == ctx.claimed_final_hash {
-                        info!("Confirming final hash {:?} for {}", hash, id);
-                        return Ok(Reaction::Idle);
-                    } else {
-                        info!(
-                            "Disputing final hash {:?} != {} for {}",
-                            hash, ctx.claimed_final_hash, id
-                        );
+                // we inspect the verification contract
+                let vg_instance = instance.sub_instances.first().ok_or(Error::from(
+                    ErrorKind::InvalidContractState(format!(
+                        "There is no vg instance {}",
+                        ctx.current_state
+                    )),
+                ))?;
+                let vg_parsed: VGCtxParsed = serde_json::from_str(&vg_instance.json_data)
+                    .chain_err(|| {
+                        format!(
+                            "Could not parse vg instance json_data: {}",
+                            &vg_instance.json_data
+                        )
+                    })?;
+                let vg_ctx: VGCtx = vg_parsed.into();
+
+                let (victory, defeat) = match role {
+                    Role::Claimer => ("FinishedClaimerWon", "FinishedChallengerWon"),
+                    Role::Challenger => ("FinishedChallengerWon", "FinishedClaimerWon"),
+                };
+
+                match vg_ctx.current_state.as_str() {
+                    s if s == victory => {
+                        // claim victory in compute contract
+                        info!("Claiming victory by VG (index: {})", instance.index);
                         let request = TransactionRequest {
-                            concern: instance.concern.clone(),
+                            concern: instance.concern,
                             value: U256::from(0),
-                            function: "challengeHighestScore".into(),
+                            function: "winByVG".into(),
                             data: vec![Token::Uint(instance.index)],
                             gas: None,
                             strategy: transaction::Strategy::Simplest,
                         };
-
-                        return Ok(Reaction::Transaction(request));
+                        Ok(Reaction::Transaction(request))
                     }
-                }
-                "ChallengeStarted" => {
-                    // we inspect the verification contract
-                    let vg_instance = instance.sub_instances.get(0).ok_or(Error::from(
-                        ErrorKind::InvalidContractState(format!(
-                            "There is no vg instance {}",
-                            ctx.current_state
-                        )),
-                    ))?;
-                    let vg_parsed: VGCtxParsed = serde_json::from_str(&vg_instance.json_data)
-                        .chain_err(|| {
-                            format!(
-                                "Could not parse vg instance json_data: {}",
-                                &vg_instance.json_data
-                            )
-                        })?;
-                    let vg_ctx: VGCtx = vg_parsed.into();
-
-                    match vg_ctx.current_state.as_ref() {
-                        "FinishedChallengerWon" => {
-                            // claim victory in compute contract
-                            info!("Claiming victory by VG (index: {})", instance.index);
-                            let request = TransactionRequest {
-                                concern: instance.concern.clone(),
-                                value: U256::from(0),
-                                function: "winByVG".into(),
-                                data: vec![Token::Uint(instance.index)],
-                                gas: None,
-                                strategy: transaction::Strategy::Simplest,
-                            };
-                            return Ok(Reaction::Transaction(request));
-                        }
-                        "FinishedClaimerWon" => {
-                            error!("we lost a verification game {:?}", vg_ctx);
-                            return Ok(Reaction::Idle);
-                        }
-                        _ => {
-                            // verification game is still active,
-                            // pass control to the appropriate dapp
-                            let id =
-                                build_machine_id(machine_template.tournament_index, &ctx.claimer);
-                            return VG::react(vg_instance, archive, &None, &id);
-                        }
+                    s if s == defeat => {
+                        error!("we lost a verification game {:?}", vg_ctx);
+                        Ok(Reaction::Idle)
+                    }
+                    _ => {
+                        // verification game is still active,
+                        // pass control to the appropriate dapp
+                        let id = MachineId::for_match(
+                            machine_template,
+                            instance.concern.user_address,
+                            ctx.claimer,
+                        );
+                        VG::react(vg_instance, archive, &None, &id.to_string())
                     }
                 }
-                _ => {
-                    return Err(Error::from(ErrorKind::InvalidContractState(format!(
-                        "Unknown current state {}",
-                        ctx.current_state
-                    ))));
-                }
-            },
+            }
         }
     }
 
@@ -400,3 +348,548 @@ impl DApp<MachineTemplate> for Match {
         return Ok(pretty_instance);
     }
 }
+
+fn get_role(instance: &state::Instance, ctx: &MatchCtx) -> Result<Role> {
+    let role = match instance.concern.user_address {
+        cl if (cl == ctx.claimer) => Role::Claimer,
+        ch if (ch == ctx.challenger) => Role::Challenger,
+        _ => {
+            return Err(Error::from(ErrorKind::InvalidContractState(String::from(
+                "User is neither claimer nor challenger",
+            ))));
+        }
+    };
+    trace!("Role played (index {}) is: {:?}", instance.index, role);
+
+    Ok(role)
+}
+
+/// Claims the match once the challenger missed the deadline
+fn claim_victory_by_time_or_idle(
+    instance: &state::Instance,
+    deadline: u64,
+    clock: &dyn Clock,
+) -> Result<Reaction> {
+    if clock.now()? > deadline {
+        info!("Claiming victory by time (index: {})", instance.index);
+        let request = TransactionRequest {
+            concern: instance.concern,
+            value: U256::from(0),
+            function: "claimVictoryByTime".into(),
+            data: vec![Token::Uint(instance.index)],
+            gas: None,
+            strategy: transaction::Strategy::Simplest,
+        };
+        return Ok(Reaction::Transaction(request));
+    }
+    Ok(Reaction::Idle)
+}
+
+lazy_static! {
+    // machines whose claim was already audited by this process
+    static ref AUDITED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Re-runs our own machine, once per `MachineId`, to alert when the claim
+/// we revealed would not survive a challenge. Best-effort: only the service
+/// requests it still waits for are returned as errors, a failed audit is
+/// logged and not retried.
+fn audit_claim(
+    archive: &Archive,
+    instance: &state::Instance,
+    ctx: &MatchCtx,
+    machine_template: &MachineTemplate,
+) -> Result<()> {
+    let id = MachineId::new(
+        machine_template,
+        instance.concern.user_address,
+        MachinePurpose::Reveal,
+    );
+    if AUDITED.lock().unwrap().contains(&id.to_string()) {
+        return Ok(());
+    }
+
+    let verification = run_machine(
+        archive,
+        &id,
+        &machine_template.machine,
+        ctx.final_time.as_u64(),
+        None,
+    )
+    .and_then(|hashes| verify_claim(ctx, &hashes));
+    match verification {
+        Ok(ClaimVerification::Confirmed) => {
+            trace!(
+                "Self-audit confirmed final hash {:?} for {}",
+                ctx.claimed_final_hash,
+                id
+            );
+        }
+        Ok(ClaimVerification::InitialHashMismatch(hash)) => {
+            error!(
+                "Self-audit failed: initial hash {:?} != revealed {:?} for {}, \
+                 a challenge against us would be lost",
+                hash, ctx.initial_hash, id
+            );
+        }
+        Ok(ClaimVerification::FinalHashMismatch(hash)) => {
+            error!(
+                "Self-audit failed: final hash {:?} != revealed {:?} for {}, \
+                 a challenge against us would be lost",
+                hash, ctx.claimed_final_hash, id
+            );
+        }
+        Err(e) => match *e.kind() {
+            ErrorKind::ResponseMissError(..) | ErrorKind::ServiceNeedsRetry(..) => {
+                return Err(e);
+            }
+            _ => warn!("Self-audit of {} could not run, not retrying: {}", id, e),
+        },
+    }
+
+    AUDITED.lock().unwrap().insert(id.to_string());
+    Ok(())
+}
+
+/// Outcome of re-running the claimer's machine
+#[derive(Debug, PartialEq)]
+pub enum ClaimVerification {
+    /// Both the initial and the final hash match the claim
+    Confirmed,
+    /// The machine built from the downloaded log is not the one on-chain,
+    /// carries the hash we computed at time 0
+    InitialHashMismatch(H256),
+    /// The claimed final hash is wrong, carries the hash we computed
+    FinalHashMismatch(H256),
+}
+
+/// Checks the hashes sampled at times `[0, final_time]` against the claim
+pub fn verify_claim(ctx: &MatchCtx, hashes: &[H256]) -> Result<ClaimVerification> {
+    if hashes.len() != 2 {
+        return Err(format!(
+            "Expected the hashes at times 0 and {}, got {} hashes",
+            ctx.final_time,
+            hashes.len()
+        )
+        .into());
+    }
+
+    if hashes[0] != ctx.initial_hash {
+        return Ok(ClaimVerification::InitialHashMismatch(hashes[0]));
+    }
+    if hashes[1] != ctx.claimed_final_hash {
+        return Ok(ClaimVerification::FinalHashMismatch(hashes[1]));
+    }
+    Ok(ClaimVerification::Confirmed)
+}
+
+/// Reacts to the hashes the challenger computed for the claim of match
+/// `instance`, challenging it only if the policy allows
+fn react_to_verification(
+    instance: &state::Instance,
+    ctx: &MatchCtx,
+    policy: &dyn ChallengePolicy,
+    id: &MachineId,
+    hashes: &[H256],
+) -> Result<Reaction> {
+    match verify_claim(ctx, hashes)? {
+        ClaimVerification::Confirmed => {
+            info!("Confirming final hash {:?} for {}", ctx.claimed_final_hash, id);
+            Ok(Reaction::Idle)
+        }
+        ClaimVerification::InitialHashMismatch(hash) => {
+            // the verification game starts from the on-chain initial
+            // hash, a challenge we cannot reproduce is bound to be lost
+            error!(
+                "Opponent log not reproducible: initial hash {:?} != {:?} for {}, \
+                 not challenging",
+                hash, ctx.initial_hash, id
+            );
+            Ok(Reaction::Idle)
+        }
+        ClaimVerification::FinalHashMismatch(hash) => match policy.on_mismatch(ctx) {
+            ChallengeDecision::Challenge => {
+                info!(
+                    "Disputing final hash {:?} != {} for {}",
+                    hash, ctx.claimed_final_hash, id
+                );
+                let request = TransactionRequest {
+                    concern: instance.concern,
+                    value: U256::from(0),
+                    function: "challengeHighestScore".into(),
+                    data: vec![Token::Uint(instance.index)],
+                    gas: None,
+                    strategy: transaction::Strategy::Simplest,
+                };
+
+                Ok(Reaction::Transaction(request))
+            }
+            ChallengeDecision::Pass(reason) => {
+                warn!(
+                    "Not disputing final hash {:?} != {} for {}: {}",
+                    hash, ctx.claimed_final_hash, id, reason
+                );
+                Ok(Reaction::Idle)
+            }
+        },
+    }
+}
+
+/// The opponent machine of match `match_index` reading the log at `path`,
+/// as named to the logger
+fn opponent_machine(
+    machine_template: &MachineTemplate,
+    match_index: U256,
+    path: &str,
+) -> Result<cartesi_base::MachineRequest> {
+    let local_path = log_file_path(path);
+    match machine_template.log_drive_position {
+        Some(position) => mount_log(&machine_template.opponent_machine, position, &local_path),
+        None => {
+            // linked rather than copied, the machine reads the cached log,
+            // and once per match so concurrent matches keep their own link
+            let legacy_path = log_file_path(&format!(
+                "{}_{}_opponent.json.br.cpio",
+                machine_template.tournament_index, match_index
+            ));
+            if !is_dry_run() {
+                if fs::symlink_metadata(&legacy_path).is_ok() {
+                    fs::remove_file(&legacy_path)
+                        .chain_err(|| format!("Could not remove {}", legacy_path.display()))?;
+                }
+                // relative to the logs directory, as `path` is
+                symlink(path, &legacy_path)
+                    .chain_err(|| format!("Could not link log to {}", legacy_path.display()))?;
+            }
+            Ok(machine_template.opponent_machine.clone())
+        }
+    }
+}
+
+/// Opens the session `id` of `machine`, reading the cached log of
+/// `log_root` if any, or recovers it if it exists, and returns its hashes
+/// at times 0 and `final_time`
+fn run_machine(
+    archive: &Archive,
+    id: &MachineId,
+    machine: &cartesi_base::MachineRequest,
+    final_time: u64,
+    log_root: Option<H256>,
+) -> Result<Vec<H256>> {
+    open_session(archive, id, machine, log_root)?;
+
+    let sample_points: Vec<u64> = vec![0, final_time];
+    let request = SessionRunRequest {
+        session_id: id.to_string(),
+        times: sample_points.clone(),
+    };
+    let archive_key = build_session_run_key(id.to_string(), sample_points.clone());
+
+    trace!("Calculating final hash of machine {}", id);
+    // have we sampled the final time?
+    let processed_response: SessionRunResult = archive
+        .get_response(
+            EMULATOR_SERVICE_NAME.to_string(),
+            archive_key.clone(),
+            EMULATOR_METHOD_RUN.to_string(),
+            request.into(),
+        )?
+        .map_err(move |_e| {
+            Error::from(ErrorKind::ResponseInvalidError(
+                EMULATOR_SERVICE_NAME.to_string(),
+                archive_key,
+                EMULATOR_METHOD_RUN.to_string(),
+            ))
+        })?
+        .into();
+
+    Ok(processed_response.hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use archivemock::ArchiveMock;
+    use clock::ManualClock;
+    use configuration::Concern;
+    use logcache::LogCache;
+    use policy::{AlwaysVerify, PolicyConfig};
+    use storage::use_test_dirs;
+    use EMULATOR_METHOD_NEW;
+
+    const CLAIMER: [u8; 20] = [1; 20];
+    const CHALLENGER: [u8; 20] = [2; 20];
+    const DEADLINE: u64 = 1_000;
+
+    fn ctx(log_hash: H256) -> MatchCtx {
+        MatchCtx {
+            challenger: Address::from(CHALLENGER),
+            claimer: Address::from(CLAIMER),
+            machine: Address::from([3; 20]),
+            epoch_number: U256::from(0),
+            deadline: U256::from(DEADLINE),
+            final_time: U256::from(100),
+            log_hash,
+            initial_hash: H256::from([5; 32]),
+            claimed_final_hash: H256::from([6; 32]),
+            current_state: "WaitingChallenge".to_string(),
+        }
+    }
+
+    fn instance(user: [u8; 20], ctx: &MatchCtx) -> state::Instance {
+        state::Instance {
+            name: "Match".to_string(),
+            concern: Concern {
+                contract_address: Address::from([9; 20]),
+                user_address: Address::from(user),
+            },
+            index: U256::from(7),
+            service_status: None,
+            json_data: ctx.to_json_data(),
+            sub_instances: vec![],
+        }
+    }
+
+    fn claimer_instance() -> state::Instance {
+        instance(CLAIMER, &ctx(H256::from([4; 32])))
+    }
+
+    fn template(now: u64, tournament_index: u64) -> MachineTemplate {
+        MachineTemplate {
+            tournament_index: U256::from(tournament_index),
+            clock: Arc::new(ManualClock::new(now)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn claimer_claims_by_time_without_the_emulator() {
+        let instance = claimer_instance();
+        let template = template(DEADLINE + 1, 1);
+        let mut archive = ArchiveMock::new().unwrap();
+        let reaction = archive
+            .react(|a| Match::react(&instance, a, &None, &template))
+            .unwrap();
+
+        match reaction {
+            Reaction::Transaction(request) => assert_eq!(request.function, "claimVictoryByTime"),
+            _ => panic!("expected claimVictoryByTime"),
+        }
+        assert!(archive.calls().is_empty());
+    }
+
+    #[test]
+    fn self_audit_recovers_the_session_then_runs_it() {
+        use_test_dirs();
+        let instance = claimer_instance();
+        let template = template(DEADLINE - 1, 3);
+        let id =
+            MachineId::new(&template, Address::from(CLAIMER), MachinePurpose::Reveal).to_string();
+
+        let mut archive = ArchiveMock::new().unwrap();
+        archive.on_new_session(&id, Err("AlreadyExists: session exists".into()));
+        assert!(archive
+            .react(|a| Match::react(&instance, a, &None, &template))
+            .is_err());
+
+        let run = build_session_run_key(id.clone(), vec![0, 100]);
+        assert_eq!(
+            archive.call_sequence(),
+            vec![
+                format!("{} {}({})", EMULATOR_SERVICE_NAME, EMULATOR_METHOD_NEW, id),
+                format!("{} {}({})", EMULATOR_SERVICE_NAME, EMULATOR_METHOD_RUN, run),
+            ]
+        );
+        assert_eq!(archive.missing().len(), 1);
+        assert_eq!(archive.missing()[0].key, run);
+    }
+
+    #[test]
+    fn failed_self_audit_is_not_retried() {
+        let instance = claimer_instance();
+        let template = template(DEADLINE - 1, 2);
+
+        // the audit waits for the emulator before the deadline
+        let mut archive = ArchiveMock::new().unwrap();
+        assert!(archive
+            .react(|a| Match::react(&instance, a, &None, &template))
+            .is_err());
+        let call = archive.missing()[0].clone();
+
+        // which fails, leaving the claimer idle
+        let mut archive = ArchiveMock::new().unwrap();
+        archive.script(
+            &call.service,
+            &call.key,
+            &call.method,
+            Err("unavailable".into()),
+        );
+        let reaction = archive
+            .react(|a| Match::react(&instance, a, &None, &template))
+            .unwrap();
+        assert!(match reaction {
+            Reaction::Idle => true,
+            _ => false,
+        });
+
+        // and the audit is not run again
+        let mut archive = ArchiveMock::new().unwrap();
+        archive
+            .react(|a| Match::react(&instance, a, &None, &template))
+            .unwrap();
+        assert!(archive.calls().is_empty());
+    }
+
+    #[test]
+    fn opponent_log_is_linked_from_the_cache_without_a_drive_position() {
+        use_test_dirs();
+        let path = "log-cache-link/opponent.json.br.cpio";
+        fs::create_dir_all(log_file_path("log-cache-link")).unwrap();
+        fs::write(log_file_path(path), b"log").unwrap();
+
+        // linked again on every verification
+        for _ in 0..2 {
+            opponent_machine(&template(0, 11), U256::from(7), path).unwrap();
+            let legacy_path = log_file_path("11_7_opponent.json.br.cpio");
+            assert!(fs::symlink_metadata(&legacy_path)
+                .unwrap()
+                .file_type()
+                .is_symlink());
+            assert_eq!(fs::read(&legacy_path).unwrap(), b"log");
+        }
+    }
+
+    #[test]
+    fn concurrent_matches_link_their_own_opponent_log() {
+        use_test_dirs();
+        fs::create_dir_all(log_file_path("log-cache-matches")).unwrap();
+        let paths: Vec<String> = (0..2)
+            .map(|i| {
+                let path = format!("log-cache-matches/opponent-{}.json.br.cpio", i);
+                fs::write(log_file_path(&path), format!("log {}", i)).unwrap();
+                path
+            })
+            .collect();
+
+        opponent_machine(&template(0, 12), U256::from(0), &paths[0]).unwrap();
+        opponent_machine(&template(0, 12), U256::from(1), &paths[1]).unwrap();
+        assert_eq!(
+            fs::read(log_file_path("12_0_opponent.json.br.cpio")).unwrap(),
+            b"log 0"
+        );
+        assert_eq!(
+            fs::read(log_file_path("12_1_opponent.json.br.cpio")).unwrap(),
+            b"log 1"
+        );
+    }
+
+    #[test]
+    fn challenger_samples_the_opponent_machine_at_both_ends() {
+        use_test_dirs();
+        let path = log_file_path("challenger-opponent.json.br.cpio");
+        fs::write(&path, b"opponent log").unwrap();
+        let template = MachineTemplate {
+            page_log2_size: 10,
+            tree_log2_size: 10,
+            ..template(DEADLINE - 1, 13)
+        };
+        let (root, _) = LogCache::from_config()
+            .unwrap()
+            .insert_file(&path, 20)
+            .unwrap();
+        let instance = instance(CHALLENGER, &ctx(root));
+        let id = MachineId::new(
+            &template,
+            Address::from(CLAIMER),
+            MachinePurpose::Verification,
+        )
+        .to_string();
+
+        // the log is in the cache, the logger is never asked for it
+        let mut archive = ArchiveMock::new().unwrap();
+        archive.on_new_session(&id, Err("AlreadyExists: session exists".into()));
+        assert!(archive
+            .react(|a| Match::react(&instance, a, &None, &template))
+            .is_err());
+
+        let run = build_session_run_key(id.clone(), vec![0, 100]);
+        assert_eq!(
+            archive.call_sequence(),
+            vec![
+                format!("{} {}({})", EMULATOR_SERVICE_NAME, EMULATOR_METHOD_NEW, id),
+                format!("{} {}({})", EMULATOR_SERVICE_NAME, EMULATOR_METHOD_RUN, run),
+            ]
+        );
+    }
+
+    #[test]
+    fn unreproducible_opponent_log_is_not_challenged() {
+        let ctx = ctx(H256::from([4; 32]));
+        let instance = instance(CHALLENGER, &ctx);
+        let id = MachineId::new(
+            &template(0, 14),
+            Address::from(CLAIMER),
+            MachinePurpose::Verification,
+        );
+        let wrong = H256::from([0xee; 32]);
+
+        let reaction =
+            react_to_verification(&instance, &ctx, &AlwaysVerify, &id, &[wrong, wrong]).unwrap();
+        assert!(match reaction {
+            Reaction::Idle => true,
+            _ => false,
+        });
+
+        let reaction = react_to_verification(
+            &instance,
+            &ctx,
+            &AlwaysVerify,
+            &id,
+            &[ctx.initial_hash, wrong],
+        )
+        .unwrap();
+        match reaction {
+            Reaction::Transaction(request) => assert_eq!(request.function, "challengeHighestScore"),
+            _ => panic!("expected challengeHighestScore"),
+        }
+    }
+
+    #[test]
+    fn denied_challenge_sends_no_transaction() {
+        let ctx = ctx(H256::from([4; 32]));
+        let instance = instance(CHALLENGER, &ctx);
+        let id = MachineId::new(
+            &template(0, 15),
+            Address::from(CLAIMER),
+            MachinePurpose::Verification,
+        );
+        let hashes = [ctx.initial_hash, H256::from([0xee; 32])];
+
+        for policy in &[
+            PolicyConfig::NeverChallenge,
+            PolicyConfig::DryRun,
+            PolicyConfig::GasThreshold { max_gas: 0 },
+        ] {
+            let reaction =
+                react_to_verification(&instance, &ctx, &*policy.build(), &id, &hashes).unwrap();
+            assert!(
+                match reaction {
+                    Reaction::Idle => true,
+                    _ => false,
+                },
+                "{:?} challenged",
+                policy
+            );
+        }
+
+        let policy = PolicyConfig::GasThreshold {
+            max_gas: u64::max_value(),
+        };
+        let reaction =
+            react_to_verification(&instance, &ctx, &*policy.build(), &id, &hashes).unwrap();
+        match reaction {
+            Reaction::Transaction(request) => assert_eq!(request.function, "challengeHighestScore"),
+            _ => panic!("expected challengeHighestScore"),
+        }
+    }
+}
@@ -19,7 +19,7 @@
 // be used independently under the Apache v2 license. After this component is
 // rewritten, the entire component will be released under the Apache v2 license.
 
-use super::dispatcher::{AddressArray3, BoolField, String32Field, U256Array9};
+use super::dispatcher::{AddressArray3, BoolField, String32Field, U256Array4, U256Array9};
 use super::dispatcher::{Archive, DApp, Reaction};
 use super::error::Result;
 use super::error::*;
@@ -27,73 +27,71 @@ use super::ethabi::Token;
 use super::ethereum_types::{Address, U256};
 use super::transaction;
 use super::transaction::TransactionRequest;
+use super::session::{close_session, close_tournament_sessions};
+use super::settlement::settle;
+use super::machine_id::{MachineId, MachinePurpose};
 use super::{Match, Role};
-use r#match::{MachineTemplate, MatchCtx, MatchCtxParsed};
+use super::score::Contender;
+use r#match::{MachineTemplate, MatchCtx, MatchCtxParsed, MatchState};
 
-use std::time::{SystemTime, UNIX_EPOCH};
 
 pub struct MatchManager();
 
-// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-// these two structs and the From trait below shuld be
-// obtained from a simple derive
-// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-#[derive(Serialize, Deserialize)]
-pub struct MatchManagerCtxParsed(
-    U256Array9, // epochDuration
-    // roundDuration
-    // currentEpoch
-    // finalTime
-    // lastEpochStartTime
-    // numberOfMatchesOnEpoch
-    // lastMatchIndex
-    // parentInstance
-    // lastMatchEpoch
-    AddressArray3, // unmatchedplayer
-    // machine
-    // parentaddress
-    BoolField,     // registered
-    String32Field, // currentstate
-);
-
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, DAppContext)]
+#[get_state(
+    _uintValues(U256Array9),
+    _addressValues(AddressArray3),
+    _lastMatchScores(U256Array4),
+    registered(BoolField),
+    currentState(String32Field)
+)]
 pub struct MatchManagerCtx {
+    #[slot(_uintValues)]
     pub epoch_duration: U256,
+    #[slot(_uintValues)]
     pub round_duration: U256,
+    #[slot(_uintValues)]
     pub current_epoch: U256,
+    #[slot(_uintValues)]
     pub final_time: U256,
+    #[slot(_uintValues)]
     pub last_epoch_start_time: U256,
+    #[slot(_uintValues)]
     pub number_of_matches_on_last_epoch: U256,
-    pub unmatched_player: Address,
+    #[slot(_uintValues)]
     pub last_match_index: U256,
-    pub machine: Address,
-    pub parent_address: Address,
+    #[slot(_uintValues)]
     pub parent_instance: U256,
+    #[slot(_uintValues)]
     pub last_match_epoch: U256,
+
+    #[slot(_addressValues)]
+    pub unmatched_player: Address,
+    #[slot(_addressValues)]
+    pub machine: Address,
+    #[slot(_addressValues)]
+    pub parent_address: Address,
+
+    #[slot(_lastMatchScores)]
+    pub last_match_claimer_score: U256,
+    #[slot(_lastMatchScores)]
+    pub last_match_claimer_commit_time: U256,
+    #[slot(_lastMatchScores)]
+    pub last_match_challenger_score: U256,
+    #[slot(_lastMatchScores)]
+    pub last_match_challenger_commit_time: U256,
+
+    #[slot(registered)]
     pub registered: bool,
+    #[slot(currentState)]
     pub current_state: String,
 }
 
-impl From<MatchManagerCtxParsed> for MatchManagerCtx {
-    fn from(parsed: MatchManagerCtxParsed) -> MatchManagerCtx {
-        MatchManagerCtx {
-            epoch_duration: parsed.0.value[0],
-            round_duration: parsed.0.value[1],
-            current_epoch: parsed.0.value[2],
-            final_time: parsed.0.value[3],
-            last_epoch_start_time: parsed.0.value[4],
-            number_of_matches_on_last_epoch: parsed.0.value[5],
-            last_match_index: parsed.0.value[6],
-            parent_instance: parsed.0.value[7],
-            last_match_epoch: parsed.0.value[8],
-
-            unmatched_player: parsed.1.value[0],
-            machine: parsed.1.value[1],
-            parent_address: parsed.1.value[2],
-
-            registered: parsed.2.value,
-            current_state: parsed.3.value,
-        }
+contract_state! {
+    /// States of `MatchManagerInterface.sol`
+    pub enum MatchManagerState {
+        WaitingMatches,
+        MatchesOver,
     }
 }
 
@@ -125,19 +123,19 @@ impl DApp<MachineTemplate> for MatchManager {
             ctx
         );
 
-        match ctx.current_state.as_ref() {
+        let state: MatchManagerState = ctx.current_state.parse()?;
+
+        match state {
             // these states should not occur as they indicate an innactive instance,
             // but it is possible that the blockchain state changed between queries
-            "MatchesOver" => {
-                return Ok(Reaction::Idle);
+            MatchManagerState::MatchesOver => {
+                close_tournament_sessions(archive, machine_template)?;
+                settle(instance, &ctx, &*machine_template.clock)
             }
 
-            "WaitingMatches" => {
+            MatchManagerState::WaitingMatches => {
                 // we inspect the match contract
-                let current_time = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .chain_err(|| "System time before UNIX_EPOCH")?
-                    .as_secs();
+                let current_time = machine_template.clock.now()?;
 
                 let epoch_over = current_time
                     > ctx.last_epoch_start_time.as_u64()
@@ -211,6 +209,22 @@ impl DApp<MachineTemplate> for MatchManager {
                         )
                     })?;
                 let match_ctx: MatchCtx = match_parsed.into();
+                let match_state: MatchState = match_ctx.current_state.parse()?;
+                warn_on_unexpected_roles(instance, &ctx, &match_ctx, machine_template);
+
+                // the opponent's machine of a finished match is no longer
+                // needed, the player's own is until the tournament is over
+                if (match_state == MatchState::ClaimerWon
+                    || match_state == MatchState::ChallengerWon)
+                    && instance.concern.user_address != match_ctx.claimer
+                {
+                    let id = MachineId::new(
+                        machine_template,
+                        match_ctx.claimer,
+                        MachinePurpose::Verification,
+                    );
+                    close_session(archive, &id)?;
+                }
 
                 let role = match instance.concern.user_address {
                     cl if (cl == match_ctx.claimer) => Role::Claimer,
@@ -223,8 +237,8 @@ impl DApp<MachineTemplate> for MatchManager {
                 };
 
                 match role {
-                    Role::Claimer => match match_ctx.current_state.as_ref() {
-                        "ClaimerWon" => {
+                    Role::Claimer => match match_state {
+                        MatchState::ClaimerWon => {
                             if epoch_over
                                 || (ctx.last_match_epoch != ctx.current_epoch && !ctx.registered)
                             {
@@ -243,18 +257,18 @@ impl DApp<MachineTemplate> for MatchManager {
                         }
 
                         // you lost the previous game, so nothing else to do
-                        "ChallengerWon" => {
+                        MatchState::ChallengerWon => {
                             return Ok(Reaction::Idle);
                         }
-                        _ => {
+                        MatchState::WaitingChallenge | MatchState::ChallengeStarted => {
                             // match is still running,
                             // pass control to the match instance
                             return Match::react(match_instance, archive, &None, machine_template);
                         }
                     },
 
-                    Role::Challenger => match match_ctx.current_state.as_ref() {
-                        "ChallengerWon" => {
+                    Role::Challenger => match match_state {
+                        MatchState::ChallengerWon => {
                             if epoch_over
                                 || (ctx.last_match_epoch != ctx.current_epoch && !ctx.registered)
                             {
@@ -273,11 +287,11 @@ impl DApp<MachineTemplate> for MatchManager {
                         }
 
                         // you lost the previous game, so nothing else to do
-                        "ClaimerWon" => {
+                        MatchState::ClaimerWon => {
                             return Ok(Reaction::Idle);
                         }
 
-                        _ => {
+                        MatchState::WaitingChallenge | MatchState::ChallengeStarted => {
                             // match is still running,
                             // pass control to the match instance
                             return Match::react(match_instance, archive, &None, machine_template);
@@ -285,10 +299,6 @@ impl DApp<MachineTemplate> for MatchManager {
                     },
                 }
             }
-
-            _ => {
-                return Ok(Reaction::Idle);
-            }
         }
     }
 
@@ -330,3 +340,267 @@ impl DApp<MachineTemplate> for MatchManager {
         return Ok(pretty_instance);
     }
 }
+
+/// Warns when the roles assigned on chain are not the ones the score
+/// ordering of the tournament expects. The match is played anyway, it is
+/// the one the contract will settle.
+fn warn_on_unexpected_roles(
+    instance: &state::Instance,
+    ctx: &MatchManagerCtx,
+    match_ctx: &MatchCtx,
+    machine_template: &MachineTemplate,
+) {
+    let claimer = Contender {
+        address: match_ctx.claimer,
+        score: ctx.last_match_claimer_score,
+        commit_time: ctx.last_match_claimer_commit_time,
+    };
+    let challenger = Contender {
+        address: match_ctx.challenger,
+        score: ctx.last_match_challenger_score,
+        commit_time: ctx.last_match_challenger_commit_time,
+    };
+
+    let expected = machine_template
+        .score_ordering
+        .expected_claimer(&claimer, &challenger);
+    match expected {
+        Some(expected) if expected.address != match_ctx.claimer => {
+            warn!(
+                "Match of epoch {} (matchmanager index {}) has claimer {:?} with score {}, \
+                 but {:?} with score {} is the claimer under {:?}, playing it as created",
+                match_ctx.epoch_number,
+                instance.index,
+                claimer.address,
+                claimer.score,
+                expected.address,
+                expected.score,
+                machine_template.score_ordering
+            );
+        }
+        Some(_) => {}
+        None => {
+            trace!(
+                "Tie between {:?} and {:?} under {:?}, any claimer is expected",
+                claimer.address,
+                challenger.address,
+                machine_template.score_ordering
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use archivemock::ArchiveMock;
+    use clock::ManualClock;
+    use configuration::Concern;
+    use ethereum_types::H256;
+    use score::ScoreOrdering;
+    use session::{open_session, open_sessions, EMULATOR_METHOD_END};
+    use std::sync::Arc;
+    use storage::use_test_dirs;
+    use EMULATOR_SERVICE_NAME;
+
+    const PLAYER: [u8; 20] = [1; 20];
+
+    // the player claims a match it has the lower score of, and whose
+    // deadline has passed
+    fn manager_instance() -> state::Instance {
+        match_instance(
+            Address::from([2; 20]),
+            Address::from(PLAYER),
+            "WaitingChallenge",
+        )
+    }
+
+    fn match_instance(challenger: Address, claimer: Address, match_state: &str) -> state::Instance {
+        let ctx = MatchManagerCtx {
+            epoch_duration: U256::from(10_000),
+            round_duration: U256::from(50),
+            current_epoch: U256::from(1),
+            final_time: U256::from(100),
+            last_epoch_start_time: U256::from(0),
+            number_of_matches_on_last_epoch: U256::from(1),
+            last_match_index: U256::from(0),
+            parent_instance: U256::from(0),
+            last_match_epoch: U256::from(1),
+            unmatched_player: Address::from([4; 20]),
+            machine: Address::from([3; 20]),
+            parent_address: Address::from([5; 20]),
+            last_match_claimer_score: U256::from(5),
+            last_match_claimer_commit_time: U256::from(10),
+            last_match_challenger_score: U256::from(9),
+            last_match_challenger_commit_time: U256::from(20),
+            registered: true,
+            current_state: "WaitingMatches".to_string(),
+        };
+        let match_ctx = MatchCtx {
+            challenger,
+            claimer,
+            machine: Address::from([3; 20]),
+            epoch_number: U256::from(1),
+            deadline: U256::from(100),
+            final_time: U256::from(100),
+            log_hash: H256::from([6; 32]),
+            initial_hash: H256::from([7; 32]),
+            claimed_final_hash: H256::from([8; 32]),
+            current_state: match_state.to_string(),
+        };
+        let concern = |contract: u8| Concern {
+            contract_address: Address::from([contract; 20]),
+            user_address: Address::from(PLAYER),
+        };
+        state::Instance {
+            name: "MatchManager".to_string(),
+            concern: concern(9),
+            index: U256::from(0),
+            service_status: None,
+            json_data: ctx.to_json_data(),
+            sub_instances: vec![Box::new(state::Instance {
+                name: "Match".to_string(),
+                concern: concern(10),
+                index: U256::from(0),
+                service_status: None,
+                json_data: match_ctx.to_json_data(),
+                sub_instances: vec![],
+            })],
+        }
+    }
+
+    fn react(score_ordering: ScoreOrdering) -> Reaction {
+        let template = MachineTemplate {
+            clock: Arc::new(ManualClock::new(500)),
+            score_ordering,
+            ..Default::default()
+        };
+        let instance = manager_instance();
+        let mut archive = ArchiveMock::new().unwrap();
+        archive
+            .react(|a| MatchManager::react(&instance, a, &None, &template))
+            .unwrap()
+    }
+
+    #[test]
+    fn match_the_ordering_expects_is_played() {
+        match react(ScoreOrdering::LowestWins) {
+            Reaction::Transaction(request) => assert_eq!(request.function, "claimVictoryByTime"),
+            _ => panic!("expected claimVictoryByTime"),
+        }
+    }
+
+    #[test]
+    fn match_against_the_ordering_is_still_played() {
+        match react(ScoreOrdering::HighestWins) {
+            Reaction::Transaction(request) => assert_eq!(request.function, "claimVictoryByTime"),
+            _ => panic!("expected claimVictoryByTime"),
+        }
+    }
+
+    fn is_open(id: &MachineId) -> bool {
+        open_sessions()
+            .unwrap()
+            .iter()
+            .any(|record| record.id == id.to_string())
+    }
+
+    fn ended(archive: &ArchiveMock, id: &MachineId) -> bool {
+        archive
+            .calls()
+            .iter()
+            .any(|call| call.method == EMULATOR_METHOD_END && call.key == id.to_string())
+    }
+
+    #[test]
+    fn own_session_outlives_consecutive_matches_as_claimer() {
+        use_test_dirs();
+        let template = MachineTemplate {
+            tournament_index: U256::from(161),
+            clock: Arc::new(ManualClock::new(50)),
+            ..Default::default()
+        };
+        let player = Address::from(PLAYER);
+        let id = MachineId::new(&template, player, MachinePurpose::Reveal);
+
+        for opponent in &[[2; 20], [6; 20]] {
+            let opponent = Address::from(*opponent);
+
+            // the running match audits the player's machine
+            let mut archive = ArchiveMock::new().unwrap();
+            archive
+                .on_new_session(&id.to_string(), Err("AlreadyExists: session exists".into()))
+                .on_run(&id.to_string(), vec![0, 100], Err("unavailable".into()));
+            let instance = match_instance(opponent, player, "WaitingChallenge");
+            archive
+                .react(|a| MatchManager::react(&instance, a, &None, &template))
+                .unwrap();
+            assert!(is_open(&id));
+
+            // and its end leaves the machine to the next one
+            let mut archive = ArchiveMock::new().unwrap();
+            let instance = match_instance(opponent, player, "ClaimerWon");
+            archive
+                .react(|a| MatchManager::react(&instance, a, &None, &template))
+                .unwrap();
+            assert!(!ended(&archive, &id));
+            assert!(is_open(&id));
+        }
+
+        // which is ended with the tournament
+        let mut instance = match_instance(Address::from([6; 20]), player, "ClaimerWon");
+        let mut ctx: MatchManagerCtx =
+            serde_json::from_str::<MatchManagerCtxParsed>(&instance.json_data)
+                .unwrap()
+                .into();
+        ctx.current_state = "MatchesOver".to_string();
+        instance.json_data = ctx.to_json_data();
+        let mut archive = ArchiveMock::new().unwrap();
+        archive.script(
+            EMULATOR_SERVICE_NAME,
+            &id.to_string(),
+            EMULATOR_METHOD_END,
+            Ok(vec![]),
+        );
+        archive
+            .react(|a| MatchManager::react(&instance, a, &None, &template))
+            .unwrap();
+        assert!(ended(&archive, &id));
+        assert!(!is_open(&id));
+    }
+
+    #[test]
+    fn opponent_session_is_ended_with_its_match() {
+        use_test_dirs();
+        let template = MachineTemplate {
+            tournament_index: U256::from(162),
+            clock: Arc::new(ManualClock::new(50)),
+            ..Default::default()
+        };
+        let claimer = Address::from([2; 20]);
+        let id = MachineId::new(&template, claimer, MachinePurpose::Verification);
+        let mut archive = ArchiveMock::new().unwrap();
+        archive.on_new_session(&id.to_string(), Err("AlreadyExists: session exists".into()));
+        archive
+            .react(|a| {
+                open_session(a, &id, &template.opponent_machine, None)?;
+                Ok(Reaction::Idle)
+            })
+            .unwrap();
+        assert!(is_open(&id));
+
+        let mut archive = ArchiveMock::new().unwrap();
+        archive.script(
+            EMULATOR_SERVICE_NAME,
+            &id.to_string(),
+            EMULATOR_METHOD_END,
+            Ok(vec![]),
+        );
+        let instance = match_instance(Address::from(PLAYER), claimer, "ClaimerWon");
+        archive
+            .react(|a| MatchManager::react(&instance, a, &None, &template))
+            .unwrap();
+        assert!(ended(&archive, &id));
+        assert!(!is_open(&id));
+    }
+}
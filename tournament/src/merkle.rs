@@ -0,0 +1,224 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Off-chain mirror of the `Merkle` library used by the contracts, so that
+//! proofs can be checked before they are sent in a transaction.
+
+use super::crypto::digest::Digest;
+use super::crypto::sha3::Sha3;
+use super::error::*;
+use super::ethereum_types::H256;
+
+/// Size of a machine word, the leaves of the machine's Merkle tree
+pub const WORD_LOG2_SIZE: u64 = 3;
+/// Size of the whole machine address space
+pub const MACHINE_LOG2_SIZE: u64 = 64;
+
+/// Keccak256 of the concatenation of all `parts`
+pub fn keccak(parts: &[&[u8]]) -> H256 {
+    let mut hasher = Sha3::keccak256();
+    for part in parts {
+        hasher.input(part);
+    }
+    let mut output = [0u8; 32];
+    hasher.result(&mut output);
+    H256::from(output)
+}
+
+/// Hash of a drive of size `2^log2_size` filled with zeros,
+/// as in `Merkle.getPristineHash`
+pub fn get_pristine_hash(log2_size: u64) -> Result<H256> {
+    check_log2_size(log2_size)?;
+    let mut running_hash = keccak(&[&[0u8; 8]]);
+    for _ in WORD_LOG2_SIZE..log2_size {
+        running_hash = keccak(&[&running_hash[..], &running_hash[..]]);
+    }
+    Ok(running_hash)
+}
+
+/// Root of the machine's Merkle tree when the drive at `position`, of size
+/// `2^log2_size`, hashes to `drive`. `siblings` are ordered bottom-up, the
+/// same order `Merkle.getRootWithDrive` expects them on-chain.
+pub fn get_root_with_drive(
+    position: u64,
+    log2_size: u64,
+    drive: H256,
+    siblings: &[H256],
+) -> Result<H256> {
+    check_log2_size(log2_size)?;
+    let size: u128 = 1 << log2_size;
+    if (size - 1) & position as u128 != 0 {
+        return Err(Error::from(ErrorKind::InvalidContractState(format!(
+            "Drive position {:#x} is not aligned to its size 2^{}",
+            position, log2_size
+        ))));
+    }
+    if siblings.len() as u64 != MACHINE_LOG2_SIZE - log2_size {
+        return Err(Error::from(ErrorKind::InvalidContractState(format!(
+            "Drive of size 2^{} needs {} siblings, got {}",
+            log2_size,
+            MACHINE_LOG2_SIZE - log2_size,
+            siblings.len()
+        ))));
+    }
+
+    let mut root = drive;
+    for (i, sibling) in siblings.iter().enumerate() {
+        if position as u128 & (size << i) == 0 {
+            root = keccak(&[&root[..], &sibling[..]]);
+        } else {
+            root = keccak(&[&sibling[..], &root[..]]);
+        }
+    }
+    Ok(root)
+}
+
+/// Root of a drive of size `2^log2_size` holding `data`, padded with
+/// zeros. This is the root the logger service computes for a file split in
+/// `2^tree_log2_size` pages of `2^page_log2_size` bytes, with
+/// `log2_size = page_log2_size + tree_log2_size`.
+pub fn get_root_of_data(data: &[u8], log2_size: u64) -> Result<H256> {
+    check_log2_size(log2_size)?;
+    if log2_size < MACHINE_LOG2_SIZE && data.len() as u128 > 1u128 << log2_size {
+        return Err(Error::from(ErrorKind::InvalidContractState(format!(
+            "{} bytes do not fit in a drive of size 2^{}",
+            data.len(),
+            log2_size
+        ))));
+    }
+
+    // pristine_hashes[level] is the hash of 2^level zeros
+    let mut pristine_hashes = vec![H256::zero(); WORD_LOG2_SIZE as usize];
+    pristine_hashes.push(get_pristine_hash(WORD_LOG2_SIZE)?);
+    for _ in WORD_LOG2_SIZE..log2_size {
+        let below = pristine_hashes[pristine_hashes.len() - 1];
+        pristine_hashes.push(keccak(&[&below[..], &below[..]]));
+    }
+    Ok(root_of_data(data, log2_size, &pristine_hashes))
+}
+
+fn root_of_data(data: &[u8], log2_size: u64, pristine_hashes: &[H256]) -> H256 {
+    if data.iter().all(|b| *b == 0) {
+        // zeros all the way down, no need to hash them
+        return pristine_hashes[log2_size as usize];
+    }
+    if log2_size == WORD_LOG2_SIZE {
+        let mut word = [0u8; 8];
+        word[..data.len()].copy_from_slice(data);
+        return keccak(&[&word]);
+    }
+
+    let half = 1usize << (log2_size - 1);
+    let (left, right) = data.split_at(half.min(data.len()));
+    let left = root_of_data(left, log2_size - 1, pristine_hashes);
+    let right = root_of_data(right, log2_size - 1, pristine_hashes);
+    keccak(&[&left[..], &right[..]])
+}
+
+fn check_log2_size(log2_size: u64) -> Result<()> {
+    if !(WORD_LOG2_SIZE..=MACHINE_LOG2_SIZE).contains(&log2_size) {
+        return Err(Error::from(ErrorKind::InvalidContractState(format!(
+            "Drive log2 size {} must be between {} and {}",
+            log2_size, WORD_LOG2_SIZE, MACHINE_LOG2_SIZE
+        ))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(hex: &str) -> H256 {
+        hex.parse().unwrap()
+    }
+
+    fn siblings(count: u8, first: u8) -> Vec<H256> {
+        (first..first + count)
+            .map(|i| H256::from([i; 32]))
+            .collect()
+    }
+
+    // vectors computed independently, following Merkle.getPristineHash and
+    // Merkle.getRootWithDrive
+
+    #[test]
+    fn pristine_hashes_match_the_contract() {
+        assert_eq!(
+            get_pristine_hash(3).unwrap(),
+            hash("011b4d03dd8c01f1049143cf9c4c817e4b167f1d1b83e5c6f0f10d89ba1e7bce")
+        );
+        assert_eq!(
+            get_pristine_hash(12).unwrap(),
+            hash("d8b96e5b7f6f459e9cb6a2f41bf276c7b85c10cd4662c04cbbb365434726c0a0")
+        );
+        assert_eq!(
+            get_pristine_hash(64).unwrap(),
+            hash("7b3fbc4a995c19017816b74d2f89179f10b6681bcefd8cfec7d8e18d0f35dbc7")
+        );
+        assert!(get_pristine_hash(2).is_err());
+        assert!(get_pristine_hash(65).is_err());
+    }
+
+    #[test]
+    fn roots_with_drive_match_the_contract() {
+        // a page at 0xa000, left and right of its siblings along the way
+        assert_eq!(
+            get_root_with_drive(0xa000, 12, H256::from([0x11; 32]), &siblings(52, 0)).unwrap(),
+            hash("ff30af1a60595f120d2c6e0efd2e7c321c80261ef5f5905b563cb60fa80f84e4")
+        );
+        // a single word
+        let word = keccak(&[&[1, 2, 3, 4, 5, 6, 7, 8]]);
+        assert_eq!(
+            get_root_with_drive(0x18, 3, word, &siblings(61, 1)).unwrap(),
+            hash("ab90dd2293386de8fef5a4f820e8c3cda315cbfc5e963568f5742124bc5ceb44")
+        );
+        // the whole machine
+        assert_eq!(get_root_with_drive(0, 64, word, &[]).unwrap(), word);
+    }
+
+    #[test]
+    fn misplaced_drives_are_rejected() {
+        let drive = H256::from([0x11; 32]);
+        assert!(get_root_with_drive(0xa008, 12, drive, &siblings(52, 0)).is_err());
+        assert!(get_root_with_drive(0xa000, 12, drive, &siblings(51, 0)).is_err());
+        assert!(get_root_with_drive(0x18, 2, drive, &siblings(62, 0)).is_err());
+    }
+
+    #[test]
+    fn roots_of_data_match_the_drives_they_fill() {
+        let word = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(get_root_of_data(&word, 3).unwrap(), keccak(&[&word]));
+        assert_eq!(
+            get_root_of_data(b"tournament log", 5).unwrap(),
+            hash("857afc50fdbff2d0d16afb0b50ee7d48701e130f8e59f70b8a650af9b45570fb")
+        );
+        assert_eq!(
+            get_root_of_data(b"tournament log", 64).unwrap(),
+            hash("343f012627c234d4a5bbdc9182eee2aac23cea7eaf2ab4ee53c70039d62a0a43")
+        );
+        assert_eq!(
+            get_root_of_data(&[], 12).unwrap(),
+            get_pristine_hash(12).unwrap()
+        );
+        assert!(get_root_of_data(b"tournament log", 3).is_err());
+    }
+}
@@ -0,0 +1,527 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Embedding of the tournament in a game DApp.
+//!
+//! A game contract instantiates a `RevealInstantiator` and, once the logs
+//! are revealed, a `MatchManagerInstantiator` whose parent is that reveal
+//! instance. The game DApp implements `TournamentParent` to describe its
+//! own state and sub-instances, and runs `Tournament<Game>` as its DApp,
+//! which drives `RevealCommit` and `MatchManager` and hands the winner back
+//! to the game.
+
+use super::config::tournament_config;
+use super::dispatcher::{Archive, DApp, Reaction};
+use super::error::*;
+use super::ethereum_types::Address;
+use super::matchmanager::{
+    MatchManager, MatchManagerCtx, MatchManagerCtxParsed, MatchManagerState,
+};
+use super::reveal_commit::{RevealCommit, RevealCommitCtx, RevealCommitCtxParsed, RevealState};
+use r#match::MachineTemplate;
+use std::marker::PhantomData;
+
+pub trait TournamentParent {
+    /// Name of the parent DApp, as shown in its pretty instance
+    const NAME: &'static str;
+
+    /// Whether the tournament of the parent `instance` is running,
+    /// `react_not_running` reacts for the parent otherwise
+    fn is_running(instance: &state::Instance) -> Result<bool>;
+
+    /// The `RevealInstantiator` sub-instance, once instantiated
+    fn reveal_instance(instance: &state::Instance) -> Result<Option<&state::Instance>>;
+
+    /// The `MatchManagerInstantiator` sub-instance, once instantiated
+    fn match_manager_instance(instance: &state::Instance) -> Result<Option<&state::Instance>>;
+
+    /// Machines and clock the tournament is played with, usually on top of
+    /// `MachineTemplate::from_config`. The chain id is the configured one,
+    /// the reveal address and tournament index are taken from the reveal
+    /// sub-instance.
+    fn machine_template(instance: &state::Instance) -> Result<MachineTemplate>;
+
+    /// Reaction of the parent once the matches are over, e.g. paying or
+    /// recording `winner`. It is called until the parent stops running.
+    fn on_winner(instance: &state::Instance, winner: Address) -> Result<Reaction>;
+
+    /// Whether the tournament of the parent `instance` is over, as
+    /// `RevealMock` in `TournamentOver`. `on_tournament_over` reacts for the
+    /// parent then, whether it is running or not.
+    fn is_over(_instance: &state::Instance) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Reaction of the parent once its tournament is over, `winner` being
+    /// the winner of the match manager, e.g. `claimFinished`
+    fn on_tournament_over(_instance: &state::Instance, _winner: Address) -> Result<Reaction> {
+        Ok(Reaction::Idle)
+    }
+
+    /// Reaction of the parent while the tournament is not running
+    fn react_not_running(
+        _instance: &state::Instance,
+        _archive: &Archive,
+        _post_payload: &Option<String>,
+    ) -> Result<Reaction> {
+        Ok(Reaction::Idle)
+    }
+
+    /// Reaction of the parent once the reveal is over and before there is
+    /// a match manager, e.g. instantiating it
+    fn on_reveal_done(_instance: &state::Instance) -> Result<Reaction> {
+        Ok(Reaction::Idle)
+    }
+}
+
+/// The tournament as the DApp of a `TournamentParent`
+pub struct Tournament<P: TournamentParent>(PhantomData<P>);
+
+impl<P: TournamentParent> DApp<()> for Tournament<P> {
+    fn react(
+        instance: &state::Instance,
+        archive: &Archive,
+        post_payload: &Option<String>,
+        _: &(),
+    ) -> Result<Reaction> {
+        if P::is_over(instance)? {
+            let match_manager_instance = P::match_manager_instance(instance)?.ok_or(
+                Error::from(ErrorKind::InvalidContractState(format!(
+                    "{} (index {}) is over without a match manager instance",
+                    P::NAME,
+                    instance.index
+                ))),
+            )?;
+            let ctx = parse_match_manager_ctx(match_manager_instance)?;
+            return P::on_tournament_over(instance, ctx.unmatched_player);
+        }
+        if !P::is_running(instance)? {
+            return P::react_not_running(instance, archive, post_payload);
+        }
+
+        let reveal_instance = P::reveal_instance(instance)?;
+        let machine_template = tournament_template::<P>(instance, reveal_instance)?;
+
+        if let Some(match_manager_instance) = P::match_manager_instance(instance)? {
+            let ctx = parse_match_manager_ctx(match_manager_instance)?;
+            let state: MatchManagerState = ctx.current_state.parse()?;
+
+            let reaction =
+                MatchManager::react(match_manager_instance, archive, &None, &machine_template)?;
+            return match (state, reaction) {
+                // the prize is claimed before the parent learns the winner,
+                // the last unmatched player as in MatchManagerInstantiator.getWinner
+                (MatchManagerState::MatchesOver, Reaction::Idle) => {
+                    P::on_winner(instance, ctx.unmatched_player)
+                }
+                (_, reaction) => Ok(reaction),
+            };
+        }
+
+        let reveal_instance =
+            reveal_instance.ok_or(Error::from(ErrorKind::InvalidContractState(format!(
+                "{} (index {}) is running without a reveal instance",
+                P::NAME,
+                instance.index
+            ))))?;
+
+        let ctx = parse_reveal_ctx(reveal_instance)?;
+        let state: RevealState = ctx.current_state.parse()?;
+
+        match state {
+            RevealState::CommitRevealDone => P::on_reveal_done(instance),
+            RevealState::CommitPhase | RevealState::RevealPhase => {
+                RevealCommit::react(reveal_instance, archive, post_payload, &machine_template)
+            }
+        }
+    }
+
+    fn get_pretty_instance(
+        instance: &state::Instance,
+        archive: &Archive,
+        _: &(),
+    ) -> Result<state::Instance> {
+        let reveal_instance = P::reveal_instance(instance)?;
+        let machine_template = tournament_template::<P>(instance, reveal_instance)?;
+
+        // get context (state) of the sub instances
+
+        let mut pretty_sub_instances: Vec<Box<state::Instance>> = vec![];
+
+        if let Some(reveal_instance) = reveal_instance {
+            pretty_sub_instances.push(Box::new(RevealCommit::get_pretty_instance(
+                reveal_instance,
+                archive,
+                &machine_template,
+            )?));
+        }
+        if let Some(match_manager_instance) = P::match_manager_instance(instance)? {
+            pretty_sub_instances.push(Box::new(MatchManager::get_pretty_instance(
+                match_manager_instance,
+                archive,
+                &machine_template,
+            )?));
+        }
+
+        let pretty_instance = state::Instance {
+            name: P::NAME.to_string(),
+            concern: instance.concern,
+            index: instance.index,
+            service_status: archive.get_service(P::NAME.into()),
+            json_data: instance.json_data.clone(),
+            sub_instances: pretty_sub_instances,
+        };
+
+        Ok(pretty_instance)
+    }
+}
+
+fn tournament_template<P: TournamentParent>(
+    instance: &state::Instance,
+    reveal_instance: Option<&state::Instance>,
+) -> Result<MachineTemplate> {
+    let mut machine_template = P::machine_template(instance)?;
+    machine_template.chain_id = tournament_config()?.chain_id;
+    if let Some(reveal_instance) = reveal_instance {
+        machine_template = machine_template.in_reveal(reveal_instance);
+        if machine_template.log_drive_position.is_none() {
+            let ctx = parse_reveal_ctx(reveal_instance)?;
+            machine_template.log_drive_position = Some(ctx.log_drive_position.as_u64());
+        }
+    }
+    Ok(machine_template)
+}
+
+fn parse_reveal_ctx(reveal_instance: &state::Instance) -> Result<RevealCommitCtx> {
+    let parsed: RevealCommitCtxParsed = serde_json::from_str(&reveal_instance.json_data)
+        .chain_err(|| {
+            format!(
+                "Could not parse reveal instance json_data: {}",
+                &reveal_instance.json_data
+            )
+        })?;
+    Ok(parsed.into())
+}
+
+fn parse_match_manager_ctx(match_manager_instance: &state::Instance) -> Result<MatchManagerCtx> {
+    let parsed: MatchManagerCtxParsed = serde_json::from_str(&match_manager_instance.json_data)
+        .chain_err(|| {
+            format!(
+                "Could not parse matchmanager instance json_data: {}",
+                &match_manager_instance.json_data
+            )
+        })?;
+    Ok(parsed.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use archivemock::ArchiveMock;
+    use clock::ManualClock;
+    use configuration::Concern;
+    use ethabi::Token;
+    use ethereum_types::{H256, U256};
+    use settlement::{Settlement, SettlementStatus};
+    use std::cell::Cell;
+    use std::sync::Arc;
+    use storage::use_test_dirs;
+    use transaction::{self, TransactionRequest};
+
+    const PLAYER: [u8; 20] = [1; 20];
+    const UNMATCHED: [u8; 20] = [4; 20];
+
+    thread_local! {
+        static NOW: Cell<u64> = Cell::new(50);
+    }
+
+    /// A game whose state is its json_data: "Idle", "Running" or "Over"
+    struct Game;
+
+    fn request(instance: &state::Instance, function: &str, data: Vec<Token>) -> Reaction {
+        Reaction::Transaction(TransactionRequest {
+            concern: instance.concern,
+            value: U256::from(0),
+            function: function.into(),
+            data,
+            gas: None,
+            strategy: transaction::Strategy::Simplest,
+        })
+    }
+
+    fn sub_instance<'a>(instance: &'a state::Instance, name: &str) -> Option<&'a state::Instance> {
+        instance
+            .sub_instances
+            .iter()
+            .find(|sub_instance| sub_instance.name == name)
+            .map(|sub_instance| &**sub_instance)
+    }
+
+    impl TournamentParent for Game {
+        const NAME: &'static str = "Game";
+
+        fn is_running(instance: &state::Instance) -> Result<bool> {
+            Ok(instance.json_data == "Running")
+        }
+
+        fn is_over(instance: &state::Instance) -> Result<bool> {
+            Ok(instance.json_data == "Over")
+        }
+
+        fn reveal_instance(instance: &state::Instance) -> Result<Option<&state::Instance>> {
+            Ok(sub_instance(instance, "RevealCommit"))
+        }
+
+        fn match_manager_instance(instance: &state::Instance) -> Result<Option<&state::Instance>> {
+            Ok(sub_instance(instance, "MatchManager"))
+        }
+
+        fn machine_template(_: &state::Instance) -> Result<MachineTemplate> {
+            Ok(MachineTemplate {
+                clock: Arc::new(ManualClock::new(NOW.with(|now| now.get()))),
+                ..Default::default()
+            })
+        }
+
+        fn on_winner(instance: &state::Instance, winner: Address) -> Result<Reaction> {
+            Ok(request(
+                instance,
+                "recordWinner",
+                vec![Token::Address(winner)],
+            ))
+        }
+
+        fn on_reveal_done(instance: &state::Instance) -> Result<Reaction> {
+            Ok(request(instance, "instantiateMatchManager", vec![]))
+        }
+
+        fn on_tournament_over(instance: &state::Instance, winner: Address) -> Result<Reaction> {
+            Ok(request(
+                instance,
+                "claimFinished",
+                vec![Token::Address(winner)],
+            ))
+        }
+    }
+
+    fn concern(contract: u8) -> Concern {
+        Concern {
+            contract_address: Address::from([contract; 20]),
+            user_address: Address::from(PLAYER),
+        }
+    }
+
+    fn reveal(contract: u8, reveal_state: &str) -> state::Instance {
+        let ctx = RevealCommitCtx {
+            instantiated_at: U256::from(0),
+            commit_duration: U256::from(100),
+            reveal_duration: U256::from(100),
+            score_word_position: U256::from(0xa000_0000_0000_0000u64),
+            log_drive_position: U256::from(0x9000_0000_0000_0000u64),
+            log_drive_log_size: U256::from(20),
+            score_drive_log_size: U256::from(3),
+            template_hash: H256::from([6; 32]),
+            commit_hash: H256::zero(),
+            log_hash: H256::zero(),
+            has_revealed: false,
+            log_available: false,
+            current_state: reveal_state.to_string(),
+        };
+        state::Instance {
+            name: "RevealCommit".to_string(),
+            concern: concern(contract),
+            index: U256::from(0),
+            service_status: None,
+            json_data: ctx.to_json_data(),
+            sub_instances: vec![],
+        }
+    }
+
+    fn match_manager(contract: u8, unmatched: [u8; 20]) -> state::Instance {
+        let ctx = MatchManagerCtx {
+            epoch_duration: U256::from(10_000),
+            round_duration: U256::from(50),
+            current_epoch: U256::from(3),
+            final_time: U256::from(100),
+            last_epoch_start_time: U256::from(0),
+            number_of_matches_on_last_epoch: U256::from(0),
+            last_match_index: U256::from(0),
+            parent_instance: U256::from(0),
+            last_match_epoch: U256::from(2),
+            unmatched_player: Address::from(unmatched),
+            machine: Address::from([3; 20]),
+            parent_address: Address::from([5; 20]),
+            last_match_claimer_score: U256::from(5),
+            last_match_claimer_commit_time: U256::from(10),
+            last_match_challenger_score: U256::from(9),
+            last_match_challenger_commit_time: U256::from(20),
+            registered: true,
+            current_state: "MatchesOver".to_string(),
+        };
+        state::Instance {
+            name: "MatchManager".to_string(),
+            concern: concern(contract),
+            index: U256::from(0),
+            service_status: None,
+            json_data: ctx.to_json_data(),
+            sub_instances: vec![],
+        }
+    }
+
+    fn game(game_state: &str, sub_instances: Vec<state::Instance>) -> state::Instance {
+        state::Instance {
+            name: "Game".to_string(),
+            concern: concern(0x70),
+            index: U256::from(0),
+            service_status: None,
+            json_data: game_state.to_string(),
+            sub_instances: sub_instances.into_iter().map(Box::new).collect(),
+        }
+    }
+
+    fn react(instance: &state::Instance, post_payload: Option<&str>) -> Reaction {
+        let mut archive = ArchiveMock::new().unwrap();
+        let post_payload = post_payload.map(|p| p.to_string());
+        archive
+            .react(|a| Tournament::<Game>::react(instance, a, &post_payload, &()))
+            .unwrap()
+    }
+
+    fn function(reaction: &Reaction) -> Option<&str> {
+        match *reaction {
+            Reaction::Transaction(ref request) => Some(&request.function),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn idle_game_is_left_to_the_parent() {
+        let instance = game("Idle", vec![reveal(0x71, "CommitPhase")]);
+        assert_eq!(function(&react(&instance, None)), None);
+    }
+
+    #[test]
+    fn reveal_is_played_by_the_tournament() {
+        use_test_dirs();
+        let instance = game("Running", vec![reveal(0x72, "CommitPhase")]);
+
+        assert_eq!(function(&react(&instance, None)), None);
+
+        let hash = H256::from([7; 32]);
+        let post = format!(
+            r#"{{"action": "commit", "params": {{"hash": "{:?}"}}}}"#,
+            hash
+        );
+        match react(&instance, Some(&post)) {
+            Reaction::Transaction(request) => {
+                assert_eq!(request.function, "commit");
+                assert_eq!(request.concern, concern(0x72));
+            }
+            _ => panic!("expected the reveal to commit"),
+        }
+    }
+
+    #[test]
+    fn parent_instantiates_the_match_manager_once_the_reveal_is_done() {
+        let instance = game("Running", vec![reveal(0x73, "CommitRevealDone")]);
+        assert_eq!(
+            function(&react(&instance, None)),
+            Some("instantiateMatchManager")
+        );
+    }
+
+    #[test]
+    fn parent_is_told_the_winner_of_the_matches() {
+        use_test_dirs();
+        let instance = game(
+            "Running",
+            vec![
+                reveal(0x74, "CommitRevealDone"),
+                match_manager(0x74, UNMATCHED),
+            ],
+        );
+        match react(&instance, None) {
+            Reaction::Transaction(request) => {
+                assert_eq!(request.function, "recordWinner");
+                assert_eq!(request.data, vec![Token::Address(Address::from(UNMATCHED))]);
+            }
+            _ => panic!("expected the winner to be recorded"),
+        }
+    }
+
+    #[test]
+    fn parent_is_told_the_tournament_is_over() {
+        let instance = game(
+            "Over",
+            vec![
+                reveal(0x75, "CommitRevealDone"),
+                match_manager(0x75, UNMATCHED),
+            ],
+        );
+        match react(&instance, None) {
+            Reaction::Transaction(request) => {
+                assert_eq!(request.function, "claimFinished");
+                assert_eq!(request.data, vec![Token::Address(Address::from(UNMATCHED))]);
+            }
+            _ => panic!("expected the tournament to be finished"),
+        }
+
+        let instance = game("Over", vec![reveal(0x75, "CommitRevealDone")]);
+        let mut archive = ArchiveMock::new().unwrap();
+        assert!(archive
+            .react(|a| Tournament::<Game>::react(&instance, a, &None, &()))
+            .is_err());
+    }
+
+    #[test]
+    fn winner_is_told_once_the_prize_claim_is_settled() {
+        use_test_dirs();
+        let instance = game(
+            "Running",
+            vec![
+                reveal(0x76, "CommitRevealDone"),
+                match_manager(0x76, PLAYER),
+            ],
+        );
+        let settlement = Settlement {
+            player: Address::from(PLAYER),
+            winner: Address::from(PLAYER),
+            parent_address: Address::from([5; 20]),
+            parent_instance: U256::from(0),
+            status: SettlementStatus::ClaimSent {
+                function: "claimPrize".to_string(),
+                sent_at: 0,
+            },
+        };
+        settlement.save(&concern(0x76), U256::from(0)).unwrap();
+        NOW.with(|now| now.set(1_000));
+
+        // the unconfirmed claim is sent again before the parent hears of it
+        assert_eq!(function(&react(&instance, None)), Some("claimPrize"));
+        match react(&instance, None) {
+            Reaction::Transaction(request) => {
+                assert_eq!(request.function, "recordWinner");
+                assert_eq!(request.data, vec![Token::Address(Address::from(PLAYER))]);
+            }
+            _ => panic!("expected the winner to be recorded"),
+        }
+    }
+}
@@ -0,0 +1,207 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Policies deciding what the challenger does with the claimer's result.
+
+use super::r#match::MatchCtx;
+
+/// Rough gas paid by the challenger to start and finish a verification
+/// game: `challengeHighestScore`, the final step and `winByVG`
+pub const VG_BASE_GAS: u64 = 1_500_000;
+/// Rough gas paid by the challenger per partition round
+pub const VG_GAS_PER_ROUND: u64 = 200_000;
+
+/// What to do with a claim that didn't match our own computation
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChallengeDecision {
+    Challenge,
+    /// Let the claim stand, for the given reason
+    Pass(String),
+}
+
+pub trait ChallengePolicy {
+    /// Whether the claim should be recomputed at all. If not, the log
+    /// is not even downloaded and the claim stands.
+    fn verifies(&self) -> bool {
+        true
+    }
+
+    /// Decides on a claim whose final hash we could not reproduce
+    fn on_mismatch(&self, ctx: &MatchCtx) -> ChallengeDecision;
+}
+
+/// Verifies every claim and challenges every wrong one
+pub struct AlwaysVerify;
+
+impl ChallengePolicy for AlwaysVerify {
+    fn on_mismatch(&self, _ctx: &MatchCtx) -> ChallengeDecision {
+        ChallengeDecision::Challenge
+    }
+}
+
+/// Passive player, never looks at the claims
+pub struct NeverChallenge;
+
+impl ChallengePolicy for NeverChallenge {
+    fn verifies(&self) -> bool {
+        false
+    }
+
+    fn on_mismatch(&self, _ctx: &MatchCtx) -> ChallengeDecision {
+        ChallengeDecision::Pass("the policy never challenges".into())
+    }
+}
+
+/// Challenges only when the verification game is expected to cost
+/// at most `max_gas`
+pub struct GasThreshold {
+    pub max_gas: u64,
+}
+
+impl ChallengePolicy for GasThreshold {
+    fn on_mismatch(&self, ctx: &MatchCtx) -> ChallengeDecision {
+        let expected = expected_vg_gas(ctx.final_time.as_u64());
+        if expected <= self.max_gas {
+            ChallengeDecision::Challenge
+        } else {
+            ChallengeDecision::Pass(format!(
+                "expected verification game gas {} is over the threshold {}",
+                expected, self.max_gas
+            ))
+        }
+    }
+}
+
+/// Verifies every claim, but only logs the challenges it would raise
+pub struct DryRun;
+
+impl ChallengePolicy for DryRun {
+    fn on_mismatch(&self, _ctx: &MatchCtx) -> ChallengeDecision {
+        ChallengeDecision::Pass("dry-run, would have challenged".into())
+    }
+}
+
+/// Upper estimate of the challenger's gas in a verification game over
+/// `final_time` cycles, assuming one partition round per bit of it
+pub fn expected_vg_gas(final_time: u64) -> u64 {
+    let rounds = 64 - u64::from(final_time.leading_zeros());
+    VG_BASE_GAS + rounds * VG_GAS_PER_ROUND
+}
+
+/// Challenge policy as written in the tournament configuration,
+/// e.g. `{ "policy": "gas_threshold", "max_gas": 3000000 }`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum PolicyConfig {
+    AlwaysVerify,
+    NeverChallenge,
+    GasThreshold { max_gas: u64 },
+    DryRun,
+}
+
+impl Default for PolicyConfig {
+    fn default() -> PolicyConfig {
+        PolicyConfig::AlwaysVerify
+    }
+}
+
+impl PolicyConfig {
+    pub fn build(&self) -> Box<dyn ChallengePolicy> {
+        match *self {
+            PolicyConfig::AlwaysVerify => Box::new(AlwaysVerify),
+            PolicyConfig::NeverChallenge => Box::new(NeverChallenge),
+            PolicyConfig::GasThreshold { max_gas } => Box::new(GasThreshold { max_gas }),
+            PolicyConfig::DryRun => Box::new(DryRun),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::{Address, H256, U256};
+
+    fn ctx(final_time: u64) -> MatchCtx {
+        MatchCtx {
+            challenger: Address::from([2; 20]),
+            claimer: Address::from([1; 20]),
+            machine: Address::from([3; 20]),
+            epoch_number: U256::from(0),
+            deadline: U256::from(1_000),
+            final_time: U256::from(final_time),
+            log_hash: H256::from([4; 32]),
+            initial_hash: H256::from([5; 32]),
+            claimed_final_hash: H256::from([6; 32]),
+            current_state: "WaitingChallenge".to_string(),
+        }
+    }
+
+    fn challenges(policy: &PolicyConfig, final_time: u64) -> bool {
+        let policy = policy.build();
+        policy.verifies() && policy.on_mismatch(&ctx(final_time)) == ChallengeDecision::Challenge
+    }
+
+    #[test]
+    fn always_verify_challenges_every_mismatch() {
+        assert!(challenges(&PolicyConfig::AlwaysVerify, 100));
+        assert!(challenges(&PolicyConfig::AlwaysVerify, u64::max_value()));
+    }
+
+    #[test]
+    fn never_challenge_does_not_even_verify() {
+        assert!(!PolicyConfig::NeverChallenge.build().verifies());
+        assert!(!challenges(&PolicyConfig::NeverChallenge, 100));
+    }
+
+    #[test]
+    fn gas_threshold_challenges_only_cheap_games() {
+        // 100 cycles take 7 partition rounds
+        let max_gas = VG_BASE_GAS + 7 * VG_GAS_PER_ROUND;
+        assert_eq!(expected_vg_gas(100), max_gas);
+
+        let policy = PolicyConfig::GasThreshold { max_gas };
+        assert!(challenges(&policy, 100));
+        assert!(challenges(&policy, 127));
+        assert!(!challenges(&policy, 128));
+        assert!(!challenges(&PolicyConfig::GasThreshold { max_gas: 0 }, 1));
+    }
+
+    #[test]
+    fn dry_run_verifies_without_challenging() {
+        let policy = PolicyConfig::DryRun.build();
+        assert!(policy.verifies());
+        match policy.on_mismatch(&ctx(100)) {
+            ChallengeDecision::Pass(_) => {}
+            other => panic!("expected a pass, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn policies_are_read_by_name() {
+        let policy: PolicyConfig =
+            serde_json::from_str(r#"{ "policy": "gas_threshold", "max_gas": 3000000 }"#).unwrap();
+        assert_eq!(policy, PolicyConfig::GasThreshold { max_gas: 3_000_000 });
+        let policy: PolicyConfig = serde_json::from_str(r#"{ "policy": "dry_run" }"#).unwrap();
+        assert_eq!(policy, PolicyConfig::DryRun);
+        assert!(serde_json::from_str::<PolicyConfig>(r#"{ "policy": "sometimes" }"#).is_err());
+        assert!(serde_json::from_str::<PolicyConfig>(r#"{ "policy": "gas_threshold" }"#).is_err());
+    }
+}
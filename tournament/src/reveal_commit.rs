@@ -21,7 +21,7 @@
 
 use super::configuration::Concern;
 use super::dispatcher::{Archive, DApp, Reaction};
-use super::dispatcher::{BoolField, Bytes32Field, String32Field, U256Array6};
+use super::dispatcher::{BoolField, Bytes32Field, String32Field, U256Array6, U256Field};
 use super::error::Result;
 use super::error::*;
 use super::hex;
@@ -30,62 +30,100 @@ use super::ethereum_types::{H256, U256};
 use super::transaction;
 use super::transaction::TransactionRequest;
 use super::{
-    build_machine_id, build_session_proof_key, build_session_read_key, build_session_run_key,
+    build_session_proof_key, build_session_read_key, build_session_run_key,
 };
 use super::{
-    cartesi_base, NewSessionRequest, NewSessionResult, SessionGetProofRequest,
+    cartesi_base, SessionGetProofRequest,
     SessionGetProofResult, SessionReadMemoryRequest, SessionReadMemoryResult, SessionRunRequest,
-    SessionRunResult, SubmitFileRequest, SubmitFileResponse, EMULATOR_METHOD_NEW, EMULATOR_METHOD_PROOF,
+    SessionRunResult, SubmitFileRequest, SubmitFileResponse, EMULATOR_METHOD_PROOF,
     EMULATOR_METHOD_READ, EMULATOR_METHOD_RUN, EMULATOR_SERVICE_NAME, LOGGER_METHOD_SUBMIT,
-    LOGGER_SERVICE_NAME, get_logger_response
 };
 
-use super::crypto::digest::Digest;
-use super::crypto::sha3::Sha3;
+use super::commitment::Commitment;
+use super::config::tournament_config;
+use super::download::{logger_request, RetryBudget};
+use super::logcache::LogCache;
+use super::merkle::{get_pristine_hash, get_root_of_data, get_root_with_drive};
+use super::machine_id::{MachineId, MachinePurpose};
+use super::score::{score_token, SCORE_LENGTH, SCORE_LOG2_SIZE};
+use super::session::open_session;
+use super::storage::log_file_path;
 use r#match::MachineTemplate;
-use std::time::{SystemTime, UNIX_EPOCH};
 
 pub struct RevealCommit();
 
-// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-// these two structs and the From trait below shuld be
-// obtained from a simple derive
-// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-#[derive(Serialize, Deserialize)]
-pub struct RevealCommitCtxParsed(
-    pub U256Array6, // instantiatedAt
-    // commitDuration
-    // revealDuration
-    // scoreWordPosition
-    // logDrivePosition
-    // logDriveLogSize
-    pub Bytes32Field,  // logHash;
-    pub BoolField,     // hasRevealed
-    pub BoolField,     // logAvailable
-    pub String32Field, // currentState
-);
-
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, DAppContext)]
+#[get_state(
+    _uintValues(U256Array6),
+    scoreDriveLogSize(U256Field),
+    templateHash(Bytes32Field),
+    commitHash(Bytes32Field),
+    logHash(Bytes32Field),
+    revealed(BoolField),
+    logAvailable(BoolField),
+    currentState(String32Field)
+)]
 pub struct RevealCommitCtx {
+    #[slot(_uintValues)]
     pub instantiated_at: U256,
+    #[slot(_uintValues)]
     pub commit_duration: U256,
+    #[slot(_uintValues)]
     pub reveal_duration: U256,
+    #[slot(_uintValues)]
     pub score_word_position: U256,
+    #[slot(_uintValues)]
     pub log_drive_position: U256,
+    #[slot(_uintValues)]
     pub log_drive_log_size: U256,
-
+    #[slot(scoreDriveLogSize)]
+    pub score_drive_log_size: U256,
+    #[slot(templateHash)]
+    pub template_hash: H256,
+
+    #[slot(commitHash)]
+    pub commit_hash: H256,
+    /// Log opened by the reveal, zero before it
+    #[slot(logHash)]
     pub log_hash: H256,
 
+    #[slot(revealed)]
     pub has_revealed: bool,
+    /// Whether the revealed log is at the logger, false before the reveal
+    #[slot(logAvailable)]
     pub log_available: bool,
 
+    #[slot(currentState)]
     pub current_state: String,
 }
 
+contract_state! {
+    /// States of `RevealInterface.sol`
+    pub enum RevealState {
+        CommitPhase,
+        RevealPhase,
+        CommitRevealDone,
+    }
+}
+
+/// Actions accepted by the post endpoint, e.g.
+/// `{"action": "commit", "params": {"hash": "0x..."}}`
 #[derive(Serialize, Deserialize, Debug)]
-pub struct Payload {
-    pub action: String,
-    pub params: Params,
+#[serde(tag = "action", content = "params", rename_all = "snake_case")]
+pub enum Payload {
+    /// Commit to a log whose root is already known
+    Commit(Params),
+    /// Commit to the root of a local log file, by default the
+    /// `{tournament_index}.json.br.cpio` revealed later, e.g.
+    /// `{"action": "commit_log", "params": {}}`
+    CommitLog {
+        #[serde(default)]
+        path: Option<String>,
+    },
+    /// Reveal as soon as the reveal window opens
+    RevealNow,
+    /// Withdraw from the tournament, not supported by the contract yet
+    Withdraw,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -93,35 +131,6 @@ pub struct Params {
     pub hash: H256,
 }
 
-fn to_bytes(input: Vec<u8>) -> Option<[u8; 8]> {
-    if input.len() != 8 {
-        None
-    } else {
-        Some([
-            input[0], input[1], input[2], input[3], input[4], input[5], input[6], input[7],
-        ])
-    }
-}
-impl From<RevealCommitCtxParsed> for RevealCommitCtx {
-    fn from(parsed: RevealCommitCtxParsed) -> RevealCommitCtx {
-        RevealCommitCtx {
-            instantiated_at: parsed.0.value[0],
-            commit_duration: parsed.0.value[1],
-            reveal_duration: parsed.0.value[2],
-            score_word_position: parsed.0.value[3],
-            log_drive_position: parsed.0.value[4],
-            log_drive_log_size: parsed.0.value[5],
-
-            log_hash: parsed.1.value,
-
-            has_revealed: parsed.2.value,
-            log_available: parsed.3.value,
-
-            current_state: parsed.4.value,
-        }
-    }
-}
-
 impl DApp<(MachineTemplate)> for RevealCommit {
     /// React to the Reveal contract
     fn react(
@@ -145,47 +154,56 @@ impl DApp<(MachineTemplate)> for RevealCommit {
             ctx
         );
 
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .chain_err(|| "System time before UNIX_EPOCH")?
-            .as_secs();
+        let current_time = machine_template.clock.now()?;
 
-        match ctx.current_state.as_ref() {
-            "CommitRevealDone" => {
+        let state: RevealState = ctx.current_state.parse()?;
+
+        match state {
+            RevealState::CommitRevealDone => {
                 // if commit and reveal is done, nothing to do here.
                 return Ok(Reaction::Idle);
             }
 
-            "CommitPhase" => {
+            RevealState::CommitPhase => {
+                let phase_is_over = current_time
+                    > ctx.instantiated_at.as_u64() + ctx.commit_duration.as_u64();
+
                 match post_payload {
-                    // if post_payload is not empty, commit
-                    Some(s) => {
-                        let payload: Payload = serde_json::from_str(&s)
-                            .chain_err(|| format!("Could not parse post_payload: {}", &s))?;
-
-                        let request = TransactionRequest {
-                            concern: instance.concern.clone(),
-                            value: U256::from(0),
-                            function: "commit".into(),
-                            // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-                            // improve these types by letting the
-                            // dapp submit ethereum_types and convert
-                            // them inside the transaction manager
-                            // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-                            data: vec![
-                                Token::Uint(instance.index),
-                                Token::FixedBytes(payload.params.hash.to_vec()),
-                            ],
-                            gas: None,
-                            strategy: transaction::Strategy::Simplest,
-                        };
-                        return Ok(Reaction::Transaction(request));
-                    }
+                    Some(s) => match parse_payload(s)? {
+                        Payload::Commit(params) => {
+                            commit(instance, params.hash, None)
+                        }
+                        Payload::CommitLog { path } => {
+                            let path = path.unwrap_or(default_log_path(machine_template));
+                            let root = cache_log(&path, machine_template)?;
+                            commit(instance, root, Some(path))
+                        }
+                        Payload::RevealNow => {
+                            if !phase_is_over {
+                                return Err(format!(
+                                    "Cannot reveal before the commit phase ends at {}",
+                                    ctx.instantiated_at.as_u64() + ctx.commit_duration.as_u64()
+                                )
+                                .into());
+                            }
+                            if ctx.commit_hash.is_zero() {
+                                return Err("There is no commit to reveal".into());
+                            }
+                            complete_reveal_phase(
+                                &instance.concern,
+                                instance.index,
+                                archive,
+                                machine_template,
+                                &ctx,
+                            )
+                        }
+                        Payload::Withdraw => {
+                            warn!("Withdraw is not supported by the Reveal contract yet");
+                            Ok(Reaction::Idle)
+                        }
+                    },
                     None => {
-                        let phase_is_over = current_time
-                            > ctx.instantiated_at.as_u64() + ctx.commit_duration.as_u64();
-
-                        if phase_is_over && !ctx.log_hash.is_zero() {
+                        if phase_is_over && !ctx.commit_hash.is_zero() {
                             // if commit phase is over and player has log
                             // reveals log and forces the phase change
                             return complete_reveal_phase(
@@ -193,6 +211,7 @@ impl DApp<(MachineTemplate)> for RevealCommit {
                                 instance.index,
                                 archive,
                                 machine_template,
+                                &ctx,
                             );
                         }
                         // If there is no post and the phase is not over, idles
@@ -201,12 +220,36 @@ impl DApp<(MachineTemplate)> for RevealCommit {
                 }
             }
 
-            "RevealPhase" => {
+            RevealState::RevealPhase => {
                 let phase_is_over = current_time
                     > ctx.instantiated_at.as_u64()
                         + ctx.commit_duration.as_u64()
                         + ctx.reveal_duration.as_u64();
 
+                if let Some(s) = post_payload {
+                    match parse_payload(s)? {
+                        Payload::Commit(_) | Payload::CommitLog { .. } => {
+                            return Err("Cannot commit, the commit phase is over".into());
+                        }
+                        Payload::RevealNow => {
+                            if ctx.has_revealed || ctx.commit_hash.is_zero() {
+                                return Err("There is no pending commit to reveal".into());
+                            }
+                            return complete_reveal_phase(
+                                &instance.concern,
+                                instance.index,
+                                archive,
+                                machine_template,
+                                &ctx,
+                            );
+                        }
+                        Payload::Withdraw => {
+                            warn!("Withdraw is not supported by the Reveal contract yet");
+                            return Ok(Reaction::Idle);
+                        }
+                    }
+                }
+
                 if phase_is_over && ctx.has_revealed {
                     // TO-DO: Fix race condition / lack of incentive for calling it
                     let request = TransactionRequest {
@@ -223,7 +266,7 @@ impl DApp<(MachineTemplate)> for RevealCommit {
 
                 // if has player has revealed but phase is not over
                 // or if there is no commit to reveal
-                if ctx.has_revealed || ctx.log_hash.is_zero() {
+                if ctx.has_revealed || ctx.commit_hash.is_zero() {
                     return Ok(Reaction::Idle);
                 }
 
@@ -233,12 +276,9 @@ impl DApp<(MachineTemplate)> for RevealCommit {
                     instance.index,
                     archive,
                     machine_template,
+                    &ctx,
                 );
             }
-
-            _ => {
-                return Ok(Reaction::Idle);
-            }
         }
     }
 
@@ -273,77 +313,171 @@ impl DApp<(MachineTemplate)> for RevealCommit {
     }
 }
 
-pub fn complete_reveal_phase(
-    concern: &Concern,
-    index: U256,
+fn parse_payload(post_payload: &str) -> Result<Payload> {
+    serde_json::from_str(post_payload)
+        .chain_err(|| format!("Could not parse post_payload: {}", post_payload))
+}
+
+/// Commits to `log_hash`, salted and bound to the player's address
+fn commit(
+    instance: &state::Instance,
+    log_hash: H256,
+    log_path: Option<String>,
+) -> Result<Reaction> {
+    // never send the bare log hash, it could be copied from the mempool
+    let commitment =
+        Commitment::load_or_create(&instance.concern, instance.index, log_hash, log_path)?;
+
+    let request = TransactionRequest {
+        concern: instance.concern,
+        value: U256::from(0),
+        function: "commit".into(),
+        // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
+        // improve these types by letting the
+        // dapp submit ethereum_types and convert
+        // them inside the transaction manager
+        // !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
+        data: vec![
+            Token::Uint(instance.index),
+            Token::FixedBytes(commitment.hash(&instance.concern.user_address).to_vec()),
+        ],
+        gas: None,
+        strategy: transaction::Strategy::Simplest,
+    };
+    Ok(Reaction::Transaction(request))
+}
+
+fn default_log_path(machine_template: &MachineTemplate) -> String {
+    format!("{}.json.br.cpio", machine_template.tournament_index)
+}
+
+/// Stores the log at `path` in the log cache, returning the root the
+/// logger service will compute for it
+fn cache_log(path: &str, machine_template: &MachineTemplate) -> Result<H256> {
+    let (root, cached) = LogCache::from_config()?.insert_file(
+        &log_file_path(path),
+        machine_template.page_log2_size + machine_template.tree_log2_size,
+    )?;
+    trace!("Root of log {}: {:?}, cached as {}", path, root, cached);
+
+    Ok(root)
+}
+
+/// Path of the log of `root` as named to the logger, from the cache or
+/// else from `path`, where it was committed from
+fn resolve_log(root: &H256, path: &str, machine_template: &MachineTemplate) -> Result<String> {
+    let cache = LogCache::from_config()?;
+    let log2_size = machine_template.page_log2_size + machine_template.tree_log2_size;
+    if let Some(cached) = cache.get(root, log2_size)? {
+        return Ok(cached);
+    }
+
+    let (actual, cached) = cache.insert_file(&log_file_path(path), log2_size)?;
+    if actual != *root {
+        return Err(format!(
+            "Log {} has root {:?}, but the commitment is to {:?}",
+            path, actual, root
+        )
+        .into());
+    }
+    Ok(cached)
+}
+
+/// Submits the log at `path` to the logger, returning its root. Fails once
+/// the logger has not stored it before the reveal phase ends.
+fn submit_log(
     archive: &Archive,
+    path: &str,
     machine_template: &MachineTemplate,
-) -> Result<Reaction> {
-    // automatically submitting the log to the logger
-    let path = format!("{}.json.br.cpio", machine_template.tournament_index);
+    ctx: &RevealCommitCtx,
+) -> Result<H256> {
     trace!("Submitting file: {}...", path);
 
     let request = SubmitFileRequest {
-        path: path.clone(),
+        path: path.to_string(),
         page_log2_size: machine_template.page_log2_size,
         tree_log2_size: machine_template.tree_log2_size,
     };
 
-    let processed_response: SubmitFileResponse = get_logger_response(
-            archive,
-            "RevealCommit".into(),
-            LOGGER_SERVICE_NAME.to_string(),
-            path.clone(),
-            LOGGER_METHOD_SUBMIT.to_string(),
-            request.into(),
-        )?
-        .into();
+    let reveal_end = ctx.instantiated_at.as_u64()
+        + ctx.commit_duration.as_u64()
+        + ctx.reveal_duration.as_u64();
+    let budget = RetryBudget::new(
+        reveal_end.saturating_sub(tournament_config()?.download.reserve_seconds),
+    );
+    let response = logger_request(
+        archive,
+        "RevealCommit",
+        path.to_string(),
+        LOGGER_METHOD_SUBMIT,
+        request.into(),
+        &budget,
+        &*machine_template.clock,
+    )?
+    .ok_or(Error::from(format!(
+        "Cannot reveal in time: the logger did not store log {} by {}, \
+         the reveal phase ends at {}",
+        path, budget.deadline, reveal_end
+    )))?;
+    let processed_response: SubmitFileResponse = response.into();
     trace!("Submitted! Result: {:?}...", processed_response.root);
 
+    Ok(processed_response.root)
+}
+
+pub fn complete_reveal_phase(
+    concern: &Concern,
+    index: U256,
+    archive: &Archive,
+    machine_template: &MachineTemplate,
+    ctx: &RevealCommitCtx,
+) -> Result<Reaction> {
+    // the salt is needed to open the commitment, without it there is no reveal
+    let commitment = Commitment::load(concern, index)?.ok_or(Error::from(
+        ErrorKind::InvalidContractState(format!(
+            "There is no stored commitment to open for reveal {}",
+            index
+        )),
+    ))?;
+    if commitment.hash(&concern.user_address) != ctx.commit_hash {
+        return Err(format!(
+            "Stored commitment to log {:?} does not match the on-chain commit {:?}",
+            commitment.log_hash, ctx.commit_hash
+        )
+        .into());
+    }
+
+    // automatically submitting the committed log to the logger
+    let path = resolve_log(
+        &commitment.log_hash,
+        &commitment
+            .log_path
+            .clone()
+            .unwrap_or(default_log_path(machine_template)),
+        machine_template,
+    )?;
+    let root = submit_log(archive, &path, machine_template, ctx)?;
+
+    if root != commitment.log_hash {
+        return Err(format!(
+            "Log {} has root {:?}, but the commitment is to {:?}",
+            path, root, commitment.log_hash
+        )
+        .into());
+    }
+
     // build machine
-    let id = build_machine_id(machine_template.tournament_index, &concern.user_address);
+    let machine_id = MachineId::new(machine_template, concern.user_address, MachinePurpose::Reveal);
+    let id = machine_id.to_string();
 
-    // send newSession request to the emulator service
-    let request = NewSessionRequest {
-        session_id: id.clone(),
-        machine: machine_template.machine.clone(),
-    };
-    let id_clone = id.clone();
-    let duplicate_session_msg = format!(
-        "Trying to register a session with a session_id that already exists: {}",
-        id
-    );
-    let _processed_response: NewSessionResult = archive
-        .get_response(
-            EMULATOR_SERVICE_NAME.to_string(),
-            id.clone(),
-            EMULATOR_METHOD_NEW.to_string(),
-            request.into(),
-        )?
-        .map_err(move |e| {
-            if e == duplicate_session_msg {
-                Error::from(ErrorKind::ResponseNeedsDummy(
-                    EMULATOR_SERVICE_NAME.to_string(),
-                    id_clone,
-                    EMULATOR_METHOD_NEW.to_string(),
-                ))
-            } else {
-                Error::from(ErrorKind::ResponseInvalidError(
-                    EMULATOR_SERVICE_NAME.to_string(),
-                    id_clone,
-                    EMULATOR_METHOD_NEW.to_string(),
-                ))
-            }
-        })?
-        .into();
+    open_session(archive, &machine_id, &machine_template.machine, None)?;
 
     // get hash of log drive from emulator
-    // Log drive starts at address (1<<63)+(2<<61)
-    // Log size is 1 MB
+    // Log drive position and size are the ones declared in the instance
     // Siblings should be checked against template hash (time = 0)
     let time = 0;
-    let address = (1 << 63) + (2 << 61);
-    let log2_size = 20; // 1MB
+    let address = ctx.log_drive_position.as_u64();
+    let log2_size = ctx.log_drive_log_size.as_u64();
 
     let archive_key = build_session_proof_key(id.clone(), time, address, log2_size);
     let mut target = cartesi_base::GetProofRequest::new();
@@ -406,14 +540,21 @@ pub fn complete_reveal_phase(
 
     let final_hash = processed_response.hashes[1];
 
-    // Score is the first word (logsize = 3) of the output drive
-    // The output drive starts at address: (1<<63)+(3<<61)
+    // Score is the word at the position declared in the instance,
+    // proved against a drive of the declared log size
     // The score is there when the machine halts (final_time)
-    let address = (1 << 63) + (3 << 61);
-
-    // TO-DO: Verify if length is in bytes!
-    let length = 8;
-    let score_logsize_2 = 3;
+    let address = ctx.score_word_position.as_u64();
+
+    // the score word has to fill the drive declared on chain
+    let length = SCORE_LENGTH;
+    let score_logsize_2 = SCORE_LOG2_SIZE;
+    if score_logsize_2 != ctx.score_drive_log_size.as_u64() {
+        return Err(format!(
+            "Scores have 2^{} bytes, but the score drive of instance {} has 2^{}",
+            score_logsize_2, index, ctx.score_drive_log_size
+        )
+        .into());
+    }
 
     let archive_key = build_session_read_key(id.clone(), time, address, length);
     let mut position = cartesi_base::ReadMemoryRequest::new();
@@ -480,40 +621,44 @@ pub fn complete_reveal_phase(
 
     trace!("Get proof result: {:?}...", processed_response.proof);
 
-    // TO-DO: transform V<u8> to uint
     let score_siblings = processed_response.proof;
 
     // get actual siblings
-    let mut log_siblings: Vec<_> = log_siblings
-        .sibling_hashes
-        .into_iter()
-        .map(|hash| Token::FixedBytes(hash.0.to_vec()))
-        .collect();
+    let mut log_siblings = log_siblings.sibling_hashes;
     trace!("Size of siblings: {}", log_siblings.len());
     // !!!!! This should not be necessary, !!!!!!!
     // !!!!! the emulator should do it     !!!!!!!
     log_siblings.reverse();
 
     // get actual siblings
-    let mut score_siblings: Vec<_> = score_siblings
-        .sibling_hashes
-        .into_iter()
-        .map(|hash| Token::FixedBytes(hash.0.to_vec()))
-        .collect();
+    let mut score_siblings = score_siblings.sibling_hashes;
     trace!("Size of siblings: {}", score_siblings.len());
     // !!!!! This should not be necessary, !!!!!!!
     // !!!!! the emulator should do it     !!!!!!!
     score_siblings.reverse();
 
+    // run the same checks as RevealInstantiator.reveal before paying for them
+    check_reveal_proofs(&id, ctx, &log_siblings, &score, &score_siblings, final_hash)?;
+
+    let log_siblings: Vec<_> = log_siblings
+        .into_iter()
+        .map(|hash| Token::FixedBytes(hash.0.to_vec()))
+        .collect();
+    let score_siblings: Vec<_> = score_siblings
+        .into_iter()
+        .map(|hash| Token::FixedBytes(hash.0.to_vec()))
+        .collect();
+
     let request = TransactionRequest {
         concern: concern.clone(),
         value: U256::from(0),
         function: "reveal".into(),
         data: vec![
             Token::Uint(index),
-            Token::Uint(U256::from(u64::from_be_bytes(
-                to_bytes(score).expect("read value has the wrong size"),
-            ))),
+            Token::FixedBytes(commitment.log_hash.to_vec()),
+            Token::FixedBytes(commitment.salt.to_vec()),
+            score_token(&score)
+                .chain_err(|| format!("Malformed score read from machine {}", id))?,
             Token::FixedBytes(final_hash.0.to_vec()),
             Token::Array(log_siblings),
             Token::Array(score_siblings),
@@ -524,3 +669,326 @@ pub fn complete_reveal_phase(
 
     return Ok(Reaction::Transaction(request));
 }
+
+/// Checks the proofs of machine `id` as `RevealInstantiator.reveal` does:
+/// the log drive siblings against the template hash and the score against
+/// the final hash
+fn check_reveal_proofs(
+    id: &str,
+    ctx: &RevealCommitCtx,
+    log_siblings: &[H256],
+    score: &[u8],
+    score_siblings: &[H256],
+    final_hash: H256,
+) -> Result<()> {
+    let pristine_log_root = get_root_with_drive(
+        ctx.log_drive_position.as_u64(),
+        ctx.log_drive_log_size.as_u64(),
+        get_pristine_hash(ctx.log_drive_log_size.as_u64())?,
+        log_siblings,
+    )?;
+    if pristine_log_root != ctx.template_hash {
+        return Err(format!(
+            "Log drive siblings of machine {} give root {:?} for an empty drive, \
+             but the template hash is {:?}",
+            id, pristine_log_root, ctx.template_hash
+        )
+        .into());
+    }
+
+    let score_root = get_root_with_drive(
+        ctx.score_word_position.as_u64(),
+        ctx.score_drive_log_size.as_u64(),
+        get_root_of_data(score, ctx.score_drive_log_size.as_u64())?,
+        score_siblings,
+    )?;
+    if score_root != final_hash {
+        return Err(format!(
+            "Score drive siblings of machine {} give root {:?}, \
+             but the final hash is {:?}",
+            id, score_root, final_hash
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use archivemock::ArchiveMock;
+    use clock::ManualClock;
+    use ethereum_types::Address;
+    use merkle::keccak;
+    use std::fs;
+    use std::sync::Arc;
+    use storage::use_test_dirs;
+
+    const LOG_DRIVE_POSITION: u64 = 0x9000_0000_0000_0000;
+    const LOG_DRIVE_LOG_SIZE: u64 = 12;
+    const SCORE_WORD_POSITION: u64 = 0xa000_0000_0000_0008;
+
+    fn siblings(count: u64, first: u8) -> Vec<H256> {
+        (0..count as u8)
+            .map(|i| H256::from([first + i; 32]))
+            .collect()
+    }
+
+    fn ctx(template_hash: H256) -> RevealCommitCtx {
+        RevealCommitCtx {
+            instantiated_at: U256::from(0),
+            commit_duration: U256::from(100),
+            reveal_duration: U256::from(100),
+            score_word_position: U256::from(SCORE_WORD_POSITION),
+            log_drive_position: U256::from(LOG_DRIVE_POSITION),
+            log_drive_log_size: U256::from(LOG_DRIVE_LOG_SIZE),
+            score_drive_log_size: U256::from(SCORE_LOG2_SIZE),
+            template_hash,
+            commit_hash: H256::from([1; 32]),
+            log_hash: H256::zero(),
+            has_revealed: false,
+            log_available: false,
+            current_state: "RevealPhase".to_string(),
+        }
+    }
+
+    fn commit_phase(contract: u8) -> state::Instance {
+        let ctx = RevealCommitCtx {
+            commit_hash: H256::zero(),
+            current_state: "CommitPhase".to_string(),
+            ..ctx(H256::zero())
+        };
+        state::Instance {
+            name: "RevealCommit".to_string(),
+            concern: Concern {
+                contract_address: Address::from([contract; 20]),
+                user_address: Address::from([1; 20]),
+            },
+            index: U256::from(3),
+            service_status: None,
+            json_data: ctx.to_json_data(),
+            sub_instances: vec![],
+        }
+    }
+
+    fn template(tournament_index: u64) -> MachineTemplate {
+        MachineTemplate {
+            tournament_index: U256::from(tournament_index),
+            page_log2_size: 10,
+            tree_log2_size: 10,
+            clock: Arc::new(ManualClock::new(50)),
+            ..Default::default()
+        }
+    }
+
+    fn post(
+        instance: &state::Instance,
+        template: &MachineTemplate,
+        payload: &str,
+    ) -> (Result<Reaction>, ArchiveMock) {
+        let mut archive = ArchiveMock::new().unwrap();
+        let payload = Some(payload.to_string());
+        let result = archive.react(|a| RevealCommit::react(instance, a, &payload, template));
+        (result, archive)
+    }
+
+    fn committed_hash(reaction: Reaction) -> H256 {
+        match reaction {
+            Reaction::Transaction(request) => {
+                assert_eq!(request.function, "commit");
+                match request.data[1] {
+                    Token::FixedBytes(ref hash) => H256::from_slice(hash),
+                    _ => panic!("expected the commit hash"),
+                }
+            }
+            _ => panic!("expected a commit"),
+        }
+    }
+
+    #[test]
+    fn wrong_sibling_is_rejected_before_the_reveal() {
+        let log_siblings = siblings(64 - LOG_DRIVE_LOG_SIZE, 1);
+        let template_hash = get_root_with_drive(
+            LOG_DRIVE_POSITION,
+            LOG_DRIVE_LOG_SIZE,
+            get_pristine_hash(LOG_DRIVE_LOG_SIZE).unwrap(),
+            &log_siblings,
+        )
+        .unwrap();
+        let score = 42u64.to_be_bytes();
+        let score_siblings = siblings(64 - SCORE_LOG2_SIZE, 100);
+        let final_hash = get_root_with_drive(
+            SCORE_WORD_POSITION,
+            SCORE_LOG2_SIZE,
+            keccak(&[&score]),
+            &score_siblings,
+        )
+        .unwrap();
+        let ctx = ctx(template_hash);
+
+        check_reveal_proofs(
+            "0",
+            &ctx,
+            &log_siblings,
+            &score,
+            &score_siblings,
+            final_hash,
+        )
+        .unwrap();
+
+        let mut wrong = log_siblings.clone();
+        wrong[7] = H256::from([0xff; 32]);
+        assert!(
+            check_reveal_proofs("0", &ctx, &wrong, &score, &score_siblings, final_hash).is_err()
+        );
+
+        let mut wrong = score_siblings.clone();
+        wrong[60] = H256::from([0xff; 32]);
+        assert!(check_reveal_proofs("0", &ctx, &log_siblings, &score, &wrong, final_hash).is_err());
+
+        let other_score = 43u64.to_be_bytes();
+        assert!(check_reveal_proofs(
+            "0",
+            &ctx,
+            &log_siblings,
+            &other_score,
+            &score_siblings,
+            final_hash
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn payload_actions_are_parsed() {
+        let hash = H256::from([7; 32]);
+        match parse_payload(&format!(
+            r#"{{"action": "commit", "params": {{"hash": "{:?}"}}}}"#,
+            hash
+        ))
+        .unwrap()
+        {
+            Payload::Commit(params) => assert_eq!(params.hash, hash),
+            other => panic!("expected commit, got {:?}", other),
+        }
+        match parse_payload(r#"{"action": "commit_log", "params": {}}"#).unwrap() {
+            Payload::CommitLog { path } => assert_eq!(path, None),
+            other => panic!("expected commit_log, got {:?}", other),
+        }
+        match parse_payload(r#"{"action": "commit_log", "params": {"path": "game.log"}}"#).unwrap()
+        {
+            Payload::CommitLog { path } => assert_eq!(path, Some("game.log".to_string())),
+            other => panic!("expected commit_log, got {:?}", other),
+        }
+        match parse_payload(r#"{"action": "reveal_now"}"#).unwrap() {
+            Payload::RevealNow => {}
+            other => panic!("expected reveal_now, got {:?}", other),
+        }
+        match parse_payload(r#"{"action": "withdraw"}"#).unwrap() {
+            Payload::Withdraw => {}
+            other => panic!("expected withdraw, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn malformed_payloads_are_rejected() {
+        assert!(parse_payload(r#"{"action": "forfeit"}"#).is_err());
+        assert!(parse_payload(r#"{"action": "commit", "params": {}}"#).is_err());
+        assert!(parse_payload(r#"{"action": "commit", "params": {"hash": "0x12"}}"#).is_err());
+        assert!(parse_payload(r#"{"params": {"hash": "0x12"}}"#).is_err());
+        assert!(parse_payload("commit").is_err());
+    }
+
+    #[test]
+    fn posted_actions_are_played_in_the_commit_phase() {
+        use_test_dirs();
+        let instance = commit_phase(0x61);
+        let template = template(61);
+
+        let hash = H256::from([7; 32]);
+        let (result, archive) = post(
+            &instance,
+            &template,
+            &format!(
+                r#"{{"action": "commit", "params": {{"hash": "{:?}"}}}}"#,
+                hash
+            ),
+        );
+        let commitment = Commitment::load(&instance.concern, instance.index)
+            .unwrap()
+            .unwrap();
+        assert_eq!(commitment.log_hash, hash);
+        assert_eq!(
+            committed_hash(result.unwrap()),
+            commitment.hash(&instance.concern.user_address)
+        );
+        assert!(archive.calls().is_empty());
+
+        let (result, _) = post(&instance, &template, r#"{"action": "reveal_now"}"#);
+        assert!(result.is_err());
+
+        let (result, archive) = post(&instance, &template, r#"{"action": "withdraw"}"#);
+        assert!(match result.unwrap() {
+            Reaction::Idle => true,
+            _ => false,
+        });
+        assert!(archive.calls().is_empty());
+
+        let (result, _) = post(&instance, &template, r#"{"action": "forfeit"}"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn log_is_committed_by_path_with_its_logger_root() {
+        use_test_dirs();
+        let instance = commit_phase(0x62);
+        let template = template(62);
+        let log = b"game log".to_vec();
+        fs::write(log_file_path("62.json.br.cpio"), &log).unwrap();
+        fs::create_dir_all(log_file_path("commit-by-path")).unwrap();
+        fs::write(
+            log_file_path("commit-by-path/other.json.br.cpio"),
+            b"other log",
+        )
+        .unwrap();
+
+        // by default the log revealed later
+        let (result, archive) = post(
+            &instance,
+            &template,
+            r#"{"action": "commit_log", "params": {}}"#,
+        );
+        let commitment = Commitment::load(&instance.concern, instance.index)
+            .unwrap()
+            .unwrap();
+        assert_eq!(commitment.log_hash, get_root_of_data(&log, 20).unwrap());
+        assert_eq!(commitment.log_path, Some("62.json.br.cpio".to_string()));
+        assert_eq!(
+            committed_hash(result.unwrap()),
+            commitment.hash(&instance.concern.user_address)
+        );
+        // the logger is not needed to compute the root
+        assert!(archive.calls().is_empty());
+
+        let (result, _) = post(
+            &instance,
+            &template,
+            r#"{"action": "commit_log", "params": {"path": "commit-by-path/other.json.br.cpio"}}"#,
+        );
+        let other = Commitment::load(&instance.concern, instance.index)
+            .unwrap()
+            .unwrap();
+        assert_eq!(other.log_hash, get_root_of_data(b"other log", 20).unwrap());
+        assert_eq!(
+            committed_hash(result.unwrap()),
+            other.hash(&instance.concern.user_address)
+        );
+
+        let (result, _) = post(
+            &instance,
+            &template,
+            r#"{"action": "commit_log", "params": {"path": "commit-by-path/missing.json.br.cpio"}}"#,
+        );
+        assert!(result.is_err());
+    }
+}
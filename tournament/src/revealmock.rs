@@ -28,49 +28,44 @@ use super::ethereum_types::{Address, H256, U256};
 use super::transaction;
 use super::transaction::TransactionRequest;
 use matchmanager::MatchManager;
+use r#match::MachineTemplate;
 
 pub struct RevealMock();
 
-// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-// these two structs and the From trait below shuld be
-// obtained from a simple derive
-// !!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!
-#[derive(Serialize, Deserialize)]
-pub struct RevealMockCtxParsed(
-    pub U256Array5, // commitDuration;
-    // revealDuration;
-    // matchManagerEpochDuration;
-    // matchManagerMatchDuration;
-    // finalTime;
-    pub Bytes32Field,  // initialHash;
-    pub AddressField,  // machineAddress;
-    pub String32Field, // currentState
-);
-
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, DAppContext)]
+#[get_state(
+    _uintValues(U256Array5),
+    _initialHash(Bytes32Field),
+    _machineAddress(AddressField),
+    currentState(String32Field)
+)]
 pub struct RevealMockCtx {
+    #[slot(_uintValues)]
     pub commit_duration: U256,
+    #[slot(_uintValues)]
     pub reveal_duration: U256,
+    #[slot(_uintValues)]
     pub match_manager_epoch_duration: U256,
+    #[slot(_uintValues)]
     pub match_manager_match_duration: U256,
+    #[slot(_uintValues)]
     pub final_time: U256,
+
+    #[slot(_initialHash)]
     pub initial_hash: H256,
+    #[slot(_machineAddress)]
     pub machine_address: Address,
+    #[slot(currentState)]
     pub current_state: String,
 }
 
-impl From<RevealMockCtxParsed> for RevealMockCtx {
-    fn from(parsed: RevealMockCtxParsed) -> RevealMockCtx {
-        RevealMockCtx {
-            commit_duration: parsed.0.value[0],
-            reveal_duration: parsed.0.value[1],
-            match_manager_epoch_duration: parsed.0.value[2],
-            match_manager_match_duration: parsed.0.value[3],
-            final_time: parsed.0.value[4],
-            initial_hash: parsed.1.value,
-            machine_address: parsed.2.value,
-            current_state: parsed.3.value,
-        }
+contract_state! {
+    /// States of `RevealMockInterface.sol`
+    pub enum RevealMockState {
+        CommitPhase,
+        RevealPhase,
+        MatchManagerPhase,
+        TournamentOver,
     }
 }
 
@@ -98,13 +93,18 @@ impl DApp<()> for RevealMock {
             ctx
         );
 
-        match ctx.current_state.as_ref() {
-            // TO-DO: RevealMock should never be in these states. Add warning.
-            "CommitPhase" | "RevealPhase" => {
+        let state: RevealMockState = ctx.current_state.parse()?;
+
+        match state {
+            RevealMockState::CommitPhase | RevealMockState::RevealPhase => {
+                warn!(
+                    "RevealMock (index {}) should never be in state {}",
+                    instance.index, state
+                );
                 return Ok(Reaction::Idle);
             }
 
-            "MatchManagerPhase" => {
+            RevealMockState::MatchManagerPhase => {
                 let match_manager_instance = instance.sub_instances.get(0).ok_or(Error::from(
                     ErrorKind::InvalidContractState(format!(
                         "There is no match manager instance {}",
@@ -117,11 +117,11 @@ impl DApp<()> for RevealMock {
                     match_manager_instance,
                     archive,
                     &None,
-                    &Default::default(),
+                    &MachineTemplate::from_config()?.in_reveal(instance),
                 );
             }
 
-            "TournamentOver" => {
+            RevealMockState::TournamentOver => {
                 // claim Finished in dappmock test contract
                 let request = TransactionRequest {
                     concern: instance.concern.clone(),
@@ -133,9 +133,6 @@ impl DApp<()> for RevealMock {
                 };
                 return Ok(Reaction::Transaction(request));
             }
-            _ => {
-                return Ok(Reaction::Idle);
-            }
         }
     }
 
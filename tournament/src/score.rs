@@ -0,0 +1,148 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Decoding of the score word a machine leaves in its score drive.
+//!
+//! `RevealInstantiator.reveal` takes the score as a `uint64` and proves
+//! the hash of its big endian bytes against the final hash, so that is the
+//! only score word a tournament can reveal.
+
+use super::error::*;
+use super::ethabi::Token;
+use super::ethereum_types::{Address, U256};
+use std::cmp::Ordering;
+
+/// Number of bytes read at the score word position
+pub const SCORE_LENGTH: u64 = 8;
+
+/// Log2 size of the score word, the size its proof is requested for
+pub const SCORE_LOG2_SIZE: u64 = 3;
+
+/// Value of the big endian score word
+pub fn decode_score(word: &[u8]) -> Result<U256> {
+    if word.len() as u64 != SCORE_LENGTH {
+        return Err(format!(
+            "Score word has {} bytes, but scores have {}",
+            word.len(),
+            SCORE_LENGTH
+        )
+        .into());
+    }
+    Ok(U256::from_big_endian(word))
+}
+
+/// The score as the token of the reveal transaction
+pub fn score_token(word: &[u8]) -> Result<Token> {
+    decode_score(word).map(Token::Uint)
+}
+
+/// Which player of a match should be the claimer, the claimer being the
+/// one with the better score
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScoreOrdering {
+    /// What `MatchManagerInstantiator.createMatch` does
+    HighestWins,
+    LowestWins,
+    /// Ties go to the player who committed first
+    HighestWinsEarliestCommit,
+    LowestWinsEarliestCommit,
+}
+
+impl Default for ScoreOrdering {
+    fn default() -> ScoreOrdering {
+        ScoreOrdering::HighestWins
+    }
+}
+
+/// A player of a match, as known to its parent reveal instance
+#[derive(Debug, Clone)]
+pub struct Contender {
+    pub address: Address,
+    pub score: U256,
+    pub commit_time: U256,
+}
+
+impl ScoreOrdering {
+    /// Whether `a` ranks above `b`, `Equal` for a tie
+    pub fn compare(&self, a: &Contender, b: &Contender) -> Ordering {
+        let by_score = match *self {
+            ScoreOrdering::HighestWins | ScoreOrdering::HighestWinsEarliestCommit => {
+                a.score.cmp(&b.score)
+            }
+            ScoreOrdering::LowestWins | ScoreOrdering::LowestWinsEarliestCommit => {
+                b.score.cmp(&a.score)
+            }
+        };
+        match *self {
+            ScoreOrdering::HighestWinsEarliestCommit | ScoreOrdering::LowestWinsEarliestCommit => {
+                by_score.then(b.commit_time.cmp(&a.commit_time))
+            }
+            _ => by_score,
+        }
+    }
+
+    /// The contender expected to be the claimer, `None` on a tie
+    pub fn expected_claimer<'a>(
+        &self,
+        a: &'a Contender,
+        b: &'a Contender,
+    ) -> Option<&'a Contender> {
+        match self.compare(a, b) {
+            Ordering::Greater => Some(a),
+            Ordering::Less => Some(b),
+            Ordering::Equal => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::H256;
+    use merkle::{get_root_of_data, keccak};
+
+    // keccak256(abi.encodePacked(_score)) in RevealInstantiator.reveal
+    fn contract_hash(score: u64) -> H256 {
+        keccak(&[&score.to_be_bytes()])
+    }
+
+    #[test]
+    fn score_word_hashes_as_the_contract() {
+        let word = [0, 0, 0, 0, 0, 1, 0xe2, 0x40];
+        let score = match score_token(&word).unwrap() {
+            Token::Uint(score) => score,
+            _ => unreachable!(),
+        };
+        assert_eq!(score, U256::from(123_456));
+        assert_eq!(
+            contract_hash(score.as_u64()),
+            get_root_of_data(&word, SCORE_LOG2_SIZE).unwrap()
+        );
+    }
+
+    #[test]
+    fn malformed_score_word_is_an_error() {
+        assert!(decode_score(&[]).is_err());
+        assert!(decode_score(&[0; 7]).is_err());
+        assert!(decode_score(&[0; 32]).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Emulator sessions opened by the tournament.
+//!
+//! Every session opened through `open_session` is recorded in the
+//! tournament storage until `close_session` ends it, so the sessions of a
+//! finished match or tournament can be torn down even after the dispatcher
+//! restarted. A session that already exists in the machine manager, most
+//! likely opened before a restart, is recovered instead of being an error.
+
+use super::compute::manager_high::EndSessionRequest;
+use super::dispatcher::Archive;
+use super::dryrun::is_dry_run;
+use super::error::*;
+use super::ethereum_types::H256;
+use super::machine_id::MachineId;
+use super::protobuf::Message;
+use super::storage::storage_subdir;
+use super::{
+    cartesi_base, NewSessionRequest, NewSessionResult, EMULATOR_METHOD_NEW, EMULATOR_SERVICE_NAME,
+};
+use r#match::MachineTemplate;
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+/// Machine manager method ending a session
+pub const EMULATOR_METHOD_END: &str = "/CartesiManagerHigh.MachineManagerHigh/EndSession";
+
+const SESSIONS_DIR: &str = "sessions";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionRecord {
+    pub id: String,
+    /// Cached log the machine of the session reads, kept in the log cache
+    /// until the session is closed
+    #[serde(default)]
+    pub log_root: Option<H256>,
+}
+
+/// Opens session `id` of `machine`, recovering it if it already exists.
+/// `log_root` is the cached log the machine reads, if any.
+pub fn open_session(
+    archive: &Archive,
+    id: &MachineId,
+    machine: &cartesi_base::MachineRequest,
+    log_root: Option<H256>,
+) -> Result<()> {
+    let id = &id.to_string();
+    let request = NewSessionRequest {
+        session_id: id.to_string(),
+        machine: machine.clone(),
+    };
+
+    let response = archive.get_response(
+        EMULATOR_SERVICE_NAME.to_string(),
+        id.to_string(),
+        EMULATOR_METHOD_NEW.to_string(),
+        request.into(),
+    )?;
+    match response {
+        Ok(response) => {
+            let _processed_response: NewSessionResult = response.into();
+        }
+        Err(ref e) if has_status(e, ALREADY_EXISTS) => {
+            trace!("Recovering existing session {}", id);
+        }
+        Err(e) => {
+            error!("Could not open session {}: {}", id, e);
+            return Err(Error::from(ErrorKind::ResponseInvalidError(
+                EMULATOR_SERVICE_NAME.to_string(),
+                id.to_string(),
+                EMULATOR_METHOD_NEW.to_string(),
+            )));
+        }
+    }
+
+    let record = SessionRecord {
+        id: id.to_string(),
+        log_root,
+    };
+    if load_record(id)?.as_ref() != Some(&record) {
+        save_record(&record)?;
+    }
+    Ok(())
+}
+
+/// Ends session `id` if it was opened by the tournament
+pub fn close_session(archive: &Archive, id: &MachineId) -> Result<()> {
+    let id = &id.to_string();
+    if load_record(id)?.is_none() {
+        return Ok(());
+    }
+
+    let response = archive.get_response(
+        EMULATOR_SERVICE_NAME.to_string(),
+        id.to_string(),
+        EMULATOR_METHOD_END.to_string(),
+        end_session_request(id)?,
+    )?;
+    match response {
+        Ok(_) => info!("Ended session {}", id),
+        Err(ref e) if has_status(e, NOT_FOUND) => trace!("Session {} was already gone", id),
+        // keeping the record would retry a teardown that keeps failing
+        Err(e) => warn!("Could not end session {}, forgetting it: {}", id, e),
+    }
+
+    if is_dry_run() {
+        return Ok(());
+    }
+    let path = record_path(id)?;
+    fs::remove_file(&path)
+        .chain_err(|| format!("Could not remove session record {}", path.display()))?;
+    Ok(())
+}
+
+/// Ends every session opened for the tournament of `machine_template`
+pub fn close_tournament_sessions(
+    archive: &Archive,
+    machine_template: &MachineTemplate,
+) -> Result<()> {
+    for record in open_sessions()? {
+        match record.id.parse::<MachineId>() {
+            Ok(ref id) if id.in_tournament(machine_template) => close_session(archive, id)?,
+            Ok(_) => {}
+            Err(e) => warn!("Ignoring session record {}: {}", record.id, e),
+        }
+    }
+    Ok(())
+}
+
+/// Sessions opened and not yet closed, across dispatcher runs
+pub fn open_sessions() -> Result<Vec<SessionRecord>> {
+    let dir = storage_subdir(SESSIONS_DIR)?;
+    let entries =
+        fs::read_dir(&dir).chain_err(|| format!("Could not list sessions in {}", dir.display()))?;
+
+    let mut records = vec![];
+    for entry in entries {
+        let path = entry
+            .chain_err(|| format!("Could not list sessions in {}", dir.display()))?
+            .path();
+        if path.extension().map_or(false, |e| e == "json") {
+            records.push(read_record(&path)?);
+        }
+    }
+    Ok(records)
+}
+
+/// Roots of the cached logs read by the sessions still open
+pub fn pinned_logs() -> Result<HashSet<H256>> {
+    Ok(open_sessions()?
+        .into_iter()
+        .filter_map(|record| record.log_root)
+        .collect())
+}
+
+// gRPC status codes, as named in the failures the dispatcher forwards
+const ALREADY_EXISTS: (&str, &str) = ("AlreadyExists", "ALREADY_EXISTS");
+const NOT_FOUND: (&str, &str) = ("NotFound", "NOT_FOUND");
+const RPC_FAILURE: &str = "RpcFailure";
+
+/// Whether the failure `error` has `status`. The dispatcher forwards the
+/// failure as text, so only the status it starts with counts, e.g.
+/// `AlreadyExists: ...` or `RpcFailure: 6-ALREADY_EXISTS ...`, and not a
+/// status named in the details.
+fn has_status(error: &str, status: (&str, &str)) -> bool {
+    let mut error = error.trim_start();
+    if error.starts_with(RPC_FAILURE) {
+        error = error[RPC_FAILURE.len()..].trim_start_matches(|c: char| c == ':' || c == ' ');
+    }
+    // the numeric code may come before the name
+    let error = error
+        .trim_start_matches(|c: char| c.is_ascii_digit())
+        .trim_start_matches('-');
+    let name = error
+        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .next()
+        .unwrap_or("");
+    name == status.0 || name == status.1
+}
+
+fn end_session_request(id: &str) -> Result<Vec<u8>> {
+    let mut request = EndSessionRequest::new();
+    request.set_session_id(id.to_string());
+    request
+        .write_to_bytes()
+        .chain_err(|| format!("Could not encode the end of session {}", id))
+}
+
+fn load_record(id: &str) -> Result<Option<SessionRecord>> {
+    let path = record_path(id)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    read_record(&path).map(Some)
+}
+
+fn read_record(path: &PathBuf) -> Result<SessionRecord> {
+    let contents = fs::read_to_string(path)
+        .chain_err(|| format!("Could not read session record {}", path.display()))?;
+    serde_json::from_str(&contents)
+        .chain_err(|| format!("Could not parse session record {}", path.display()))
+}
+
+fn save_record(record: &SessionRecord) -> Result<()> {
+    if is_dry_run() {
+        return Ok(());
+    }
+    let path = record_path(&record.id)?;
+    let contents = serde_json::to_string(record).unwrap();
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents)
+        .chain_err(|| format!("Could not write session record {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, &path)
+        .chain_err(|| format!("Could not write session record {}", path.display()))?;
+    Ok(())
+}
+
+fn record_path(id: &str) -> Result<PathBuf> {
+    Ok(storage_subdir(SESSIONS_DIR)?.join(format!("{}.json", id.replace(':', "_"))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn already_exists_is_read_from_the_status() {
+        assert!(has_status("AlreadyExists: session 1", ALREADY_EXISTS));
+        assert!(has_status("ALREADY_EXISTS", ALREADY_EXISTS));
+        assert!(has_status(
+            "RpcFailure: 6-ALREADY_EXISTS session 1",
+            ALREADY_EXISTS
+        ));
+        assert!(!has_status("NotFound: session 1", ALREADY_EXISTS));
+    }
+
+    #[test]
+    fn not_found_is_read_from_the_status() {
+        assert!(has_status("NotFound: session 1", NOT_FOUND));
+        assert!(has_status("RpcFailure: 5-NOT_FOUND", NOT_FOUND));
+        assert!(!has_status("AlreadyExists: session 1", NOT_FOUND));
+    }
+
+    #[test]
+    fn status_named_in_the_details_is_not_the_status() {
+        let error = "Internal: session NotFound_AlreadyExists failed, ALREADY_EXISTS NOT_FOUND";
+        assert!(!has_status(error, ALREADY_EXISTS));
+        assert!(!has_status(error, NOT_FOUND));
+        assert!(!has_status("RpcFailure: 13-INTERNAL NotFound", NOT_FOUND));
+    }
+}
@@ -0,0 +1,573 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Settlement of a finished tournament.
+//!
+//! Once the matches are over, the winner claims its prize by calling the
+//! configured function of the parent contract, and every player records
+//! how the tournament ended for it. The claim is recorded as sent, and as
+//! claimed only once the parent contract confirms it through the
+//! configured view function, asked in the background. A claim left
+//! unconfirmed for `resend_seconds` is sent again.
+
+use super::clock::Clock;
+use super::config::tournament_config;
+use super::configuration::Concern;
+use super::dispatcher::Reaction;
+use super::dryrun::is_dry_run;
+use super::error::*;
+use super::ethabi::Token;
+use super::ethereum_types::{Address, U256};
+use super::hex;
+use super::http;
+use super::matchmanager::MatchManagerCtx;
+use super::merkle::keccak;
+use super::storage::storage_subdir;
+use super::transaction;
+use super::transaction::TransactionRequest;
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const SETTLEMENTS_DIR: &str = "settlements";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SettlementConfig {
+    /// Function of the parent contract paying the prize, called with the
+    /// parent instance index. No prize is claimed without one.
+    #[serde(default)]
+    pub prize_function: Option<String>,
+    #[serde(default)]
+    pub gas: Option<u64>,
+    /// View function of the parent contract, `(uint256) returns (bool)`,
+    /// telling whether the prize of a parent instance was paid. Without
+    /// one, the claim is sent again until the match manager is gone.
+    #[serde(default)]
+    pub claimed_function: Option<String>,
+    /// Node the claim is confirmed with
+    #[serde(default = "default_rpc_url")]
+    pub rpc_url: String,
+    /// Time after which an unconfirmed claim is sent again
+    #[serde(default = "default_resend_seconds")]
+    pub resend_seconds: u64,
+}
+
+fn default_rpc_url() -> String {
+    "http://localhost:8545".to_string()
+}
+
+fn default_resend_seconds() -> u64 {
+    600
+}
+
+impl Default for SettlementConfig {
+    fn default() -> SettlementConfig {
+        SettlementConfig {
+            prize_function: None,
+            gas: None,
+            claimed_function: None,
+            rpc_url: default_rpc_url(),
+            resend_seconds: default_resend_seconds(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SettlementStatus {
+    /// Won, and last sent the prize claim at `sent_at`
+    ClaimSent {
+        function: String,
+        sent_at: u64,
+    },
+    /// Won, and the parent contract confirmed the prize at `claimed_at`
+    PrizeClaimed {
+        function: String,
+        claimed_at: u64,
+    },
+    /// Won, with no prize function configured
+    Won,
+    Lost,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Settlement {
+    pub player: Address,
+    pub winner: Address,
+    pub parent_address: Address,
+    pub parent_instance: U256,
+    pub status: SettlementStatus,
+}
+
+impl Settlement {
+    /// Settlement of the player of `concern` in match manager `index`
+    pub fn load(concern: &Concern, index: U256) -> Result<Option<Settlement>> {
+        let path = settlement_path(concern, index)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(&path)
+            .chain_err(|| format!("Could not read settlement {}", path.display()))?;
+        let settlement = serde_json::from_str(&contents)
+            .chain_err(|| format!("Could not parse settlement {}", path.display()))?;
+        Ok(Some(settlement))
+    }
+
+    /// Stores the settlement, replacing the previous one
+    pub fn save(&self, concern: &Concern, index: U256) -> Result<()> {
+        if is_dry_run() {
+            return Ok(());
+        }
+        let path = settlement_path(concern, index)?;
+        let contents = serde_json::to_string(self).unwrap();
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, contents)
+            .chain_err(|| format!("Could not write settlement {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &path)
+            .chain_err(|| format!("Could not write settlement {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Reaction to the match manager `instance` being over, the winner
+/// being its last unmatched player as in `MatchManagerInstantiator.getWinner`
+pub fn settle(
+    instance: &state::Instance,
+    ctx: &MatchManagerCtx,
+    clock: &dyn Clock,
+) -> Result<Reaction> {
+    settle_with(instance, ctx, clock, &tournament_config()?.settlement)
+}
+
+fn settle_with(
+    instance: &state::Instance,
+    ctx: &MatchManagerCtx,
+    clock: &dyn Clock,
+    config: &SettlementConfig,
+) -> Result<Reaction> {
+    if let Some(settlement) = Settlement::load(&instance.concern, instance.index)? {
+        let (function, sent_at) = match settlement.status {
+            SettlementStatus::ClaimSent {
+                ref function,
+                sent_at,
+            } => (function.clone(), sent_at),
+            ref status => {
+                trace!(
+                    "Tournament (matchmanager index {}) already settled: {:?}",
+                    instance.index,
+                    status
+                );
+                return Ok(Reaction::Idle);
+            }
+        };
+
+        let now = clock.now()?;
+        check_prize_claimed(instance, ctx, config, now)?;
+        if now < sent_at + config.resend_seconds {
+            return Ok(Reaction::Idle);
+        }
+        warn!(
+            "Prize claim of tournament (matchmanager index {}) sent at {} is not confirmed, \
+             sending it again",
+            instance.index, sent_at
+        );
+        return claim_prize(instance, ctx, config, settlement, function, now);
+    }
+
+    let player = instance.concern.user_address;
+    let winner = ctx.unmatched_player;
+    let mut settlement = Settlement {
+        player,
+        winner,
+        parent_address: ctx.parent_address,
+        parent_instance: ctx.parent_instance,
+        status: SettlementStatus::Lost,
+    };
+
+    if winner != player {
+        info!(
+            "Tournament (matchmanager index {}) is over, {:?} won and {:?} lost",
+            instance.index, winner, player
+        );
+        settlement.save(&instance.concern, instance.index)?;
+        return Ok(Reaction::Idle);
+    }
+
+    let function = match config.prize_function {
+        Some(ref function) => function.clone(),
+        None => {
+            info!(
+                "Tournament (matchmanager index {}) won by {:?}, no prize function to call",
+                instance.index, player
+            );
+            settlement.status = SettlementStatus::Won;
+            settlement.save(&instance.concern, instance.index)?;
+            return Ok(Reaction::Idle);
+        }
+    };
+
+    info!(
+        "Tournament (matchmanager index {}) won by {:?}, claiming the prize with {} on {:?}",
+        instance.index, player, function, ctx.parent_address
+    );
+    claim_prize(instance, ctx, config, settlement, function, clock.now()?)
+}
+
+/// Records the claim as sent at `now` and sends it
+fn claim_prize(
+    instance: &state::Instance,
+    ctx: &MatchManagerCtx,
+    config: &SettlementConfig,
+    mut settlement: Settlement,
+    function: String,
+    now: u64,
+) -> Result<Reaction> {
+    settlement.status = SettlementStatus::ClaimSent {
+        function: function.clone(),
+        sent_at: now,
+    };
+    settlement.save(&instance.concern, instance.index)?;
+
+    let request = TransactionRequest {
+        concern: Concern {
+            contract_address: ctx.parent_address,
+            user_address: settlement.player,
+        },
+        value: U256::from(0),
+        function,
+        data: vec![Token::Uint(ctx.parent_instance)],
+        gas: config.gas.map(U256::from),
+        strategy: transaction::Strategy::Simplest,
+    };
+    Ok(Reaction::Transaction(request))
+}
+
+lazy_static! {
+    // settlements whose prize claim is being confirmed, each on a thread
+    // of its own, until the parent contract answers
+    static ref PRIZE_CHECKS: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+/// Asks the parent contract, on a thread of its own so a slow node never
+/// holds up `react`, whether the prize claim of `instance` was paid, and
+/// records it as claimed at `now` if it was. Nothing is asked without a
+/// function to ask with, in a dry run or while a check is running.
+fn check_prize_claimed(
+    instance: &state::Instance,
+    ctx: &MatchManagerCtx,
+    config: &SettlementConfig,
+    now: u64,
+) -> Result<()> {
+    if config.claimed_function.is_none() || is_dry_run() {
+        return Ok(());
+    }
+    let path = settlement_path(&instance.concern, instance.index)?;
+    if !PRIZE_CHECKS.lock().unwrap().insert(path.clone()) {
+        return Ok(());
+    }
+
+    let concern = instance.concern;
+    let index = instance.index;
+    let parent_address = ctx.parent_address;
+    let parent_instance = ctx.parent_instance;
+    let config = config.clone();
+    thread::spawn(move || {
+        match prize_claimed(&config, parent_address, parent_instance) {
+            Ok(true) => {
+                if let Err(e) = record_prize_claimed(&concern, index, now) {
+                    warn!(
+                        "Could not record the prize of tournament (matchmanager index {}) \
+                         as claimed: {}",
+                        index, e
+                    );
+                }
+            }
+            Ok(false) => trace!(
+                "Prize claim of tournament (matchmanager index {}) not confirmed yet",
+                index
+            ),
+            Err(e) => warn!(
+                "Could not confirm the prize claim of tournament (matchmanager index {}): {}",
+                index, e
+            ),
+        }
+        PRIZE_CHECKS.lock().unwrap().remove(&path);
+    });
+    Ok(())
+}
+
+fn record_prize_claimed(concern: &Concern, index: U256, now: u64) -> Result<()> {
+    let mut settlement = match Settlement::load(concern, index)? {
+        Some(settlement) => settlement,
+        None => return Ok(()),
+    };
+    if let SettlementStatus::ClaimSent { ref function, .. } = settlement.status.clone() {
+        info!(
+            "Prize of tournament (matchmanager index {}) claimed by {:?}",
+            index, settlement.player
+        );
+        settlement.status = SettlementStatus::PrizeClaimed {
+            function: function.clone(),
+            claimed_at: now,
+        };
+        settlement.save(concern, index)?;
+    }
+    Ok(())
+}
+
+/// Whether `parent_address` reports the prize of `parent_instance` paid,
+/// `false` without a function to ask it with
+fn prize_claimed(
+    config: &SettlementConfig,
+    parent_address: Address,
+    parent_instance: U256,
+) -> Result<bool> {
+    let function = match config.claimed_function {
+        Some(ref function) => function,
+        None => return Ok(false),
+    };
+
+    let mut data = keccak(&[format!("{}(uint256)", function).as_bytes()])[..4].to_vec();
+    let mut word = [0u8; 32];
+    parent_instance.to_big_endian(&mut word);
+    data.extend_from_slice(&word);
+
+    let result = http::json_rpc(
+        &config.rpc_url,
+        "eth_call",
+        serde_json::json!([
+            { "to": format!("{:?}", parent_address), "data": format!("0x{}", hex::encode(&data)) },
+            "latest"
+        ]),
+        Duration::from_secs(5),
+    )?;
+    let result = result.as_str().ok_or(Error::from(format!(
+        "{} of {:?} did not return a string",
+        function, parent_address
+    )))?;
+    let result = hex::decode(result.trim_start_matches("0x"))
+        .chain_err(|| format!("Could not decode {} of {:?}", function, parent_address))?;
+    if result.len() != 32 {
+        return Err(format!(
+            "{} of {:?} returned {} bytes, expected a bool",
+            function,
+            parent_address,
+            result.len()
+        )
+        .into());
+    }
+    Ok(result[31] != 0)
+}
+
+fn settlement_path(concern: &Concern, index: U256) -> Result<PathBuf> {
+    Ok(storage_subdir(SETTLEMENTS_DIR)?.join(format!(
+        "{:x}_{}_{:x}.json",
+        concern.contract_address, index, concern.user_address
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::ManualClock;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+    use storage::use_test_dirs;
+
+    const PLAYER: [u8; 20] = [1; 20];
+
+    fn ctx() -> MatchManagerCtx {
+        MatchManagerCtx {
+            epoch_duration: U256::from(10_000),
+            round_duration: U256::from(50),
+            current_epoch: U256::from(3),
+            final_time: U256::from(100),
+            last_epoch_start_time: U256::from(0),
+            number_of_matches_on_last_epoch: U256::from(0),
+            last_match_index: U256::from(0),
+            parent_instance: U256::from(7),
+            last_match_epoch: U256::from(2),
+            unmatched_player: Address::from(PLAYER),
+            machine: Address::from([3; 20]),
+            parent_address: Address::from([5; 20]),
+            last_match_claimer_score: U256::from(0),
+            last_match_claimer_commit_time: U256::from(0),
+            last_match_challenger_score: U256::from(0),
+            last_match_challenger_commit_time: U256::from(0),
+            registered: true,
+            current_state: "MatchesOver".to_string(),
+        }
+    }
+
+    // each test settles its own match manager, `contract`
+    fn instance(contract: u8) -> state::Instance {
+        state::Instance {
+            name: "MatchManager".to_string(),
+            concern: Concern {
+                contract_address: Address::from([contract; 20]),
+                user_address: Address::from(PLAYER),
+            },
+            index: U256::from(0),
+            service_status: None,
+            json_data: ctx().to_json_data(),
+            sub_instances: vec![],
+        }
+    }
+
+    // node answering `isPrizeClaimed` with `claimed`, after `delay`
+    fn config(claimed: Arc<AtomicBool>, delay: Duration) -> SettlementConfig {
+        let url = http::serve(move |_, body| {
+            thread::sleep(delay);
+            let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+            assert_eq!(request["method"], "eth_call");
+            let result = format!("0x{:064x}", claimed.load(Ordering::SeqCst) as u8);
+            let response =
+                serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "result": result });
+            (200, response.to_string().into_bytes())
+        });
+        SettlementConfig {
+            prize_function: Some("claimPrize".to_string()),
+            claimed_function: Some("isPrizeClaimed".to_string()),
+            rpc_url: url,
+            resend_seconds: 100,
+            ..Default::default()
+        }
+    }
+
+    fn settle_at(instance: &state::Instance, config: &SettlementConfig, now: u64) -> Reaction {
+        settle_with(instance, &ctx(), &ManualClock::new(now), config).unwrap()
+    }
+
+    fn status(instance: &state::Instance) -> SettlementStatus {
+        Settlement::load(&instance.concern, instance.index)
+            .unwrap()
+            .unwrap()
+            .status
+    }
+
+    // waits for the confirmation of the claim of `instance` to end
+    fn wait_for_check(instance: &state::Instance) {
+        let path = settlement_path(&instance.concern, instance.index).unwrap();
+        let started = Instant::now();
+        while PRIZE_CHECKS.lock().unwrap().contains(&path) {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "check never ended"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn is_claim(reaction: &Reaction) -> bool {
+        match reaction {
+            Reaction::Transaction(request) => {
+                request.function == "claimPrize" && request.data == vec![Token::Uint(U256::from(7))]
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn claim_is_sent_then_awaits_confirmation() {
+        use_test_dirs();
+        let instance = instance(20);
+        let config = config(Arc::new(AtomicBool::new(false)), Duration::from_secs(0));
+
+        assert!(is_claim(&settle_at(&instance, &config, 1_000)));
+        assert_eq!(
+            status(&instance),
+            SettlementStatus::ClaimSent {
+                function: "claimPrize".to_string(),
+                sent_at: 1_000
+            }
+        );
+        match settle_at(&instance, &config, 1_050) {
+            Reaction::Idle => {}
+            _ => panic!("claim sent again before resend_seconds"),
+        }
+    }
+
+    #[test]
+    fn unconfirmed_claim_is_sent_again() {
+        use_test_dirs();
+        let instance = instance(21);
+        let config = config(Arc::new(AtomicBool::new(false)), Duration::from_secs(0));
+
+        assert!(is_claim(&settle_at(&instance, &config, 1_000)));
+        assert!(is_claim(&settle_at(&instance, &config, 1_100)));
+        assert_eq!(
+            status(&instance),
+            SettlementStatus::ClaimSent {
+                function: "claimPrize".to_string(),
+                sent_at: 1_100
+            }
+        );
+    }
+
+    #[test]
+    fn confirmed_claim_is_recorded_as_claimed() {
+        use_test_dirs();
+        let instance = instance(22);
+        let claimed = Arc::new(AtomicBool::new(false));
+        let config = config(claimed.clone(), Duration::from_secs(0));
+
+        assert!(is_claim(&settle_at(&instance, &config, 1_000)));
+        claimed.store(true, Ordering::SeqCst);
+        match settle_at(&instance, &config, 1_050) {
+            Reaction::Idle => {}
+            _ => panic!("claim sent again before resend_seconds"),
+        }
+        wait_for_check(&instance);
+        assert_eq!(
+            status(&instance),
+            SettlementStatus::PrizeClaimed {
+                function: "claimPrize".to_string(),
+                claimed_at: 1_050
+            }
+        );
+        match settle_at(&instance, &config, 1_200) {
+            Reaction::Idle => {}
+            _ => panic!("confirmed claim sent again"),
+        }
+    }
+
+    #[test]
+    fn confirmation_does_not_wait_for_the_node() {
+        use_test_dirs();
+        let instance = instance(23);
+        let claimed = Arc::new(AtomicBool::new(true));
+        let config = config(claimed, Duration::from_secs(2));
+
+        assert!(is_claim(&settle_at(&instance, &config, 1_000)));
+        let started = Instant::now();
+        settle_at(&instance, &config, 1_050);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        wait_for_check(&instance);
+        match status(&instance) {
+            SettlementStatus::PrizeClaimed { .. } => {}
+            status => panic!("claim not confirmed: {:?}", status),
+        }
+    }
+}
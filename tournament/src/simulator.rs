@@ -0,0 +1,834 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! In-process simulation of a whole tournament.
+//!
+//! The simulator keeps a model of `DAppMock`, `RevealMock`,
+//! `MatchManagerInstantiator` and `MatchInstantiator`, renders it into the
+//! same `state::Instance` tree the dispatcher would build for each player,
+//! feeds that tree to `DAppMock::react` and applies the transactions that
+//! come back to the model, reverting them where the contract would.
+//!
+//! Time only moves when no player had anything to do, by `time_step`
+//! seconds of a `ManualClock`, so every run is deterministic.
+//!
+//! Two parts of the protocol are not modelled:
+//! - the verification game, a challenge is settled as soon as it is raised
+//!   in favour of the claimer if and only if the claimer is honest;
+//! - the emulator and the logger, whose responses come from a
+//!   `ServiceOracle` whenever a player's reaction needs one.
+
+use super::clock::{with_clock, ManualClock};
+use super::configuration::Concern;
+use super::dappmock::{DAppMock, DAppMockCtx, DAppMockState};
+use super::dispatcher::{Archive, DApp, Reaction};
+use super::error::*;
+use super::ethabi::Token;
+use super::ethereum_types::{Address, H256, U256};
+use super::matchmanager::{MatchManagerCtx, MatchManagerState};
+use super::merkle::keccak;
+use super::r#match::{MatchCtx, MatchState};
+use super::revealmock::{RevealMockCtx, RevealMockState};
+use super::transaction::TransactionRequest;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Reactions attempted per player and step before giving up on the
+/// services, each missing response costs one
+const MAX_SERVICE_ROUNDS: usize = 32;
+
+/// Raw response of a service, or the error message it replied with
+pub type ServiceResponse = std::result::Result<Vec<u8>, String>;
+
+/// A request made to a service through the `Archive`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceCall {
+    pub service: String,
+    pub key: String,
+    pub method: String,
+    pub request: Vec<u8>,
+}
+
+impl fmt::Display for ServiceCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}({})", self.service, self.method, self.key)
+    }
+}
+
+/// Answers the service requests of the players' reactions, in place of
+/// the emulator and the logger. `None` leaves the request unanswered,
+/// the player then idles for the step.
+pub trait ServiceOracle {
+    fn respond(
+        &mut self,
+        service: &str,
+        key: &str,
+        method: &str,
+        request: &[u8],
+    ) -> Option<std::result::Result<Vec<u8>, String>>;
+}
+
+impl<F> ServiceOracle for F
+where
+    F: FnMut(&str, &str, &str, &[u8]) -> Option<std::result::Result<Vec<u8>, String>>,
+{
+    fn respond(
+        &mut self,
+        service: &str,
+        key: &str,
+        method: &str,
+        request: &[u8],
+    ) -> Option<std::result::Result<Vec<u8>, String>> {
+        self(service, key, method, request)
+    }
+}
+
+/// Oracle of a world without services
+pub struct NoServices;
+
+impl ServiceOracle for NoServices {
+    fn respond(
+        &mut self,
+        _: &str,
+        _: &str,
+        _: &str,
+        _: &[u8],
+    ) -> Option<std::result::Result<Vec<u8>, String>> {
+        None
+    }
+}
+
+/// A player as added by `RevealMock.addFakePlayers`
+#[derive(Debug, Clone)]
+pub struct SimPlayer {
+    pub address: Address,
+    pub score: U256,
+    pub log_hash: H256,
+    pub initial_hash: H256,
+    pub final_hash: H256,
+    /// Whether `final_hash` is what the machine really computes,
+    /// decides the verification games the player is challenged in
+    pub honest: bool,
+}
+
+impl SimPlayer {
+    /// Honest player number `n`, with hashes derived from `n`
+    pub fn new(n: u64, score: u64) -> SimPlayer {
+        let seed = n.to_be_bytes();
+        SimPlayer {
+            address: sim_address(0x1000 + n),
+            score: U256::from(score),
+            log_hash: keccak(&[b"log", &seed]),
+            initial_hash: keccak(&[b"initial", &seed]),
+            final_hash: keccak(&[b"final", &seed]),
+            honest: true,
+        }
+    }
+
+    /// Same player, claiming a final hash its machine doesn't reach
+    pub fn dishonest(self) -> SimPlayer {
+        SimPlayer {
+            honest: false,
+            ..self
+        }
+    }
+}
+
+pub struct SimulatorConfig {
+    /// Time of the first step
+    pub start_time: u64,
+    /// Time advanced when no player acted in a step
+    pub time_step: u64,
+    pub round_duration: u64,
+    pub final_time: u64,
+    /// `MatchManagerInstantiator` derives it from the match and
+    /// verification game durations, here it is given
+    pub epoch_duration: u64,
+    /// Steps after which the run is declared stuck
+    pub max_steps: u64,
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> SimulatorConfig {
+        // same values as DAppMock.claimDAppRunning
+        SimulatorConfig {
+            start_time: 1_000_000,
+            time_step: 25,
+            round_duration: 25,
+            final_time: 13000,
+            epoch_duration: 6000,
+            max_steps: 100_000,
+        }
+    }
+}
+
+/// A transaction sent by a player and what the model did with it
+#[derive(Debug, Clone)]
+pub struct SimEvent {
+    pub step: u64,
+    pub time: u64,
+    pub player: Address,
+    pub contract: &'static str,
+    pub function: String,
+    pub index: U256,
+    /// The revert reason, if the contract would revert
+    pub reverted: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct SimOutcome {
+    /// Player left unmatched when the matches were over
+    pub winner: Address,
+    /// Matches created over the whole tournament
+    pub matches: usize,
+    pub steps: u64,
+    pub time: u64,
+    pub events: Vec<SimEvent>,
+}
+
+const DAPPMOCK: &str = "DAppMock";
+const REVEALMOCK: &str = "RevealMock";
+const MATCHMANAGER: &str = "MatchManager";
+const MATCH: &str = "Match";
+
+struct MatchModel {
+    challenger: Address,
+    claimer: Address,
+    epoch_number: U256,
+    time_of_last_move: u64,
+    state: MatchState,
+}
+
+struct ManagerModel {
+    current_epoch: u64,
+    last_epoch_start_time: u64,
+    matches_on_epoch: HashMap<u64, u64>,
+    unmatched_player: Address,
+    last_match_index: HashMap<Address, usize>,
+    registered: HashMap<(Address, u64), bool>,
+    state: MatchManagerState,
+}
+
+pub struct Simulator<O: ServiceOracle> {
+    config: SimulatorConfig,
+    players: Vec<SimPlayer>,
+    oracle: O,
+    archive: Archive,
+    clock: Arc<ManualClock>,
+    step: u64,
+    events: Vec<SimEvent>,
+
+    dapp_state: DAppMockState,
+    reveal_state: RevealMockState,
+    manager: ManagerModel,
+    matches: Vec<MatchModel>,
+}
+
+impl<O: ServiceOracle> Simulator<O> {
+    pub fn new(config: SimulatorConfig, players: Vec<SimPlayer>, oracle: O) -> Result<Simulator<O>> {
+        let clock = Arc::new(ManualClock::new(config.start_time));
+        Ok(Simulator {
+            players,
+            oracle,
+            archive: Archive::new()?,
+            clock,
+            step: 0,
+            events: vec![],
+
+            dapp_state: DAppMockState::Idle,
+            reveal_state: RevealMockState::MatchManagerPhase,
+            manager: ManagerModel {
+                current_epoch: 0,
+                last_epoch_start_time: config.start_time,
+                matches_on_epoch: HashMap::new(),
+                unmatched_player: Address::zero(),
+                last_match_index: HashMap::new(),
+                registered: HashMap::new(),
+                state: MatchManagerState::WaitingMatches,
+            },
+            matches: vec![],
+            config,
+        })
+    }
+
+    /// Runs until `RevealMock` reaches `TournamentOver`
+    pub fn run(mut self) -> Result<SimOutcome> {
+        while self.reveal_state != RevealMockState::TournamentOver {
+            if self.step >= self.config.max_steps {
+                return Err(format!(
+                    "Tournament stuck after {} steps: {}",
+                    self.step,
+                    self.describe()
+                )
+                .into());
+            }
+            self.run_step()?;
+        }
+
+        Ok(SimOutcome {
+            winner: self.manager.unmatched_player,
+            matches: self.matches.len(),
+            steps: self.step,
+            time: self.now(),
+            events: self.events,
+        })
+    }
+
+    /// Lets every player react once, in order, then moves the clock if
+    /// nobody sent a transaction the contracts accepted
+    pub fn run_step(&mut self) -> Result<()> {
+        let mut progress = false;
+        for i in 0..self.players.len() {
+            let player = self.players[i].address;
+            if let Some(request) = self.react(player)? {
+                progress |= self.apply(player, request);
+            }
+        }
+
+        if !progress {
+            self.clock.advance(self.config.time_step);
+        }
+        self.step += 1;
+        Ok(())
+    }
+
+    pub fn events(&self) -> &[SimEvent] {
+        &self.events
+    }
+
+    fn now(&self) -> u64 {
+        self.clock.get()
+    }
+
+    fn react(&mut self, player: Address) -> Result<Option<TransactionRequest>> {
+        let instance = self.dappmock_instance(player);
+
+        for _ in 0..MAX_SERVICE_ROUNDS {
+            let archive = &self.archive;
+            let reaction = with_clock(self.clock.clone(), || {
+                DAppMock::react(&instance, archive, &None, &())
+            });
+
+            match reaction {
+                Ok(Reaction::Transaction(request)) => return Ok(Some(request)),
+                Ok(_) => return Ok(None),
+                Err(e) => {
+                    let (service, key, method, request) = match *e.kind() {
+                        ErrorKind::ResponseMissError(ref service, ref key, ref method, ref request) => {
+                            (service.clone(), key.clone(), method.clone(), request.clone())
+                        }
+                        _ => {
+                            return Err(e).chain_err(|| {
+                                format!("Reaction of player {:?} failed at step {}", player, self.step)
+                            });
+                        }
+                    };
+                    match self.oracle.respond(&service, &key, &method, &request) {
+                        Some(response) => {
+                            self.archive.insert_response(service, key, method, response);
+                        }
+                        None => {
+                            trace!("Player {:?} waits for {} {} {}", player, service, method, key);
+                            return Ok(None);
+                        }
+                    }
+                }
+            }
+        }
+
+        Err(format!(
+            "Player {:?} still misses service responses after {} rounds",
+            player, MAX_SERVICE_ROUNDS
+        )
+        .into())
+    }
+
+    /// Applies the transaction to the model, returning whether it was accepted
+    fn apply(&mut self, player: Address, request: TransactionRequest) -> bool {
+        let index = match request.data.first() {
+            Some(Token::Uint(index)) => *index,
+            _ => U256::zero(),
+        };
+        let contract = contract_name(&request.concern.contract_address);
+        let result = match (contract, request.function.as_str()) {
+            (DAPPMOCK, "claimDAppRunning") => self.claim_dapp_running(),
+            (DAPPMOCK, "claimFinished") => self.claim_dapp_finished(),
+            (REVEALMOCK, "claimFinished") => self.claim_tournament_over(),
+            (MATCHMANAGER, "advanceEpoch") => {
+                self.advance_epoch();
+                Ok(())
+            }
+            (MATCHMANAGER, "playNextEpoch") => self.play_next_epoch(player),
+            (MATCHMANAGER, "claimWin") => self.claim_win(),
+            (MATCH, "challengeHighestScore") => self.challenge(player, index.as_usize()),
+            (MATCH, "claimVictoryByTime") => self.claim_victory_by_time(player, index.as_usize()),
+            (_, function) => Err(format!("{} is not modelled in {}", function, contract)),
+        };
+
+        let accepted = result.is_ok();
+        let event = SimEvent {
+            step: self.step,
+            time: self.now(),
+            player,
+            contract,
+            function: request.function,
+            index,
+            reverted: result.err(),
+        };
+        trace!("Simulated transaction {:?}", event);
+        self.events.push(event);
+        accepted
+    }
+
+    fn claim_dapp_running(&mut self) -> std::result::Result<(), String> {
+        if self.dapp_state != DAppMockState::Idle {
+            return Err("State has to be Idle".into());
+        }
+        self.dapp_state = DAppMockState::DAppRunning;
+        self.manager.last_epoch_start_time = self.now();
+        Ok(())
+    }
+
+    fn claim_dapp_finished(&mut self) -> std::result::Result<(), String> {
+        if self.dapp_state != DAppMockState::DAppRunning {
+            return Err("The state is already Finished".into());
+        }
+        if self.reveal_state != RevealMockState::TournamentOver {
+            return Err("The subinstance compute is still active".into());
+        }
+        self.dapp_state = DAppMockState::DAppFinished;
+        Ok(())
+    }
+
+    fn claim_tournament_over(&mut self) -> std::result::Result<(), String> {
+        if self.manager.state != MatchManagerState::MatchesOver {
+            return Err("All matches have to be over".into());
+        }
+        self.reveal_state = RevealMockState::TournamentOver;
+        Ok(())
+    }
+
+    fn epoch_over(&self) -> bool {
+        self.now() > self.manager.last_epoch_start_time + self.config.epoch_duration
+    }
+
+    fn advance_epoch(&mut self) {
+        if self.epoch_over() {
+            self.manager.current_epoch += 1;
+            self.manager.last_epoch_start_time = self.now();
+        }
+    }
+
+    fn play_next_epoch(&mut self, player: Address) -> std::result::Result<(), String> {
+        if self.manager.state != MatchManagerState::WaitingMatches {
+            return Err("State has to be WaitingMatches".into());
+        }
+        // the contract advances the epoch before any check
+        self.advance_epoch();
+        let epoch = self.manager.current_epoch;
+
+        if epoch == 0 {
+            if self.is_registered(player, 0) {
+                return Err("Player cannot register twice".into());
+            }
+            self.manager.registered.insert((player, 0), true);
+        } else {
+            if player == self.manager.unmatched_player {
+                return Err("Player is waiting for a opponent".into());
+            }
+            let last_match = self
+                .manager
+                .last_match_index
+                .get(&player)
+                .and_then(|i| self.matches.get(*i));
+            let won_last_epoch = match last_match {
+                Some(m) => {
+                    m.epoch_number.as_u64() + 1 == epoch
+                        && ((m.state == MatchState::ChallengerWon && m.challenger == player)
+                            || (m.state == MatchState::ClaimerWon && m.claimer == player))
+                }
+                None => false,
+            };
+            if !won_last_epoch {
+                return Err("Player must have won last match played".into());
+            }
+        }
+
+        if self.manager.unmatched_player.is_zero() {
+            self.manager.unmatched_player = player;
+        } else {
+            let opponent = self.manager.unmatched_player;
+            self.create_match(player, opponent);
+            self.manager.unmatched_player = Address::zero();
+        }
+        Ok(())
+    }
+
+    fn create_match(&mut self, player: Address, opponent: Address) {
+        // the highest score between both is the claimer
+        let (claimer, challenger) = if self.player(&player).score > self.player(&opponent).score {
+            (player, opponent)
+        } else {
+            (opponent, player)
+        };
+
+        let index = self.matches.len();
+        self.matches.push(MatchModel {
+            challenger,
+            claimer,
+            epoch_number: U256::from(self.manager.current_epoch),
+            time_of_last_move: self.now(),
+            state: MatchState::WaitingChallenge,
+        });
+        self.manager.last_match_index.insert(claimer, index);
+        self.manager.last_match_index.insert(challenger, index);
+        *self
+            .manager
+            .matches_on_epoch
+            .entry(self.manager.current_epoch)
+            .or_insert(0) += 1;
+    }
+
+    fn claim_win(&mut self) -> std::result::Result<(), String> {
+        if self.manager.state == MatchManagerState::MatchesOver {
+            return Err("PLayer cannot claim win multiple times".into());
+        }
+        let matches = self.matches_on_epoch(self.manager.current_epoch);
+        // the contract doesn't revert, it just does nothing
+        if self.epoch_over() && matches == 0 {
+            self.manager.state = MatchManagerState::MatchesOver;
+            // RevealMock reports the tournament over as soon as its
+            // match manager is
+            self.reveal_state = RevealMockState::TournamentOver;
+        }
+        Ok(())
+    }
+
+    fn challenge(&mut self, player: Address, index: usize) -> std::result::Result<(), String> {
+        let honest_claimer = match self.matches.get(index) {
+            Some(m) if m.challenger != player => return Err("Only the challenger".into()),
+            Some(m) if m.state != MatchState::WaitingChallenge => {
+                return Err("State has to be Waiting Challenge".into());
+            }
+            Some(m) => self.player(&m.claimer).honest,
+            None => return Err("Match is not instantiated".into()),
+        };
+
+        // the verification game is settled right away
+        let now = self.now();
+        let m = &mut self.matches[index];
+        m.time_of_last_move = now;
+        m.state = if honest_claimer {
+            MatchState::ClaimerWon
+        } else {
+            MatchState::ChallengerWon
+        };
+        Ok(())
+    }
+
+    fn claim_victory_by_time(&mut self, player: Address, index: usize) -> std::result::Result<(), String> {
+        let deadline = match self.matches.get(index) {
+            Some(m) => self.match_deadline(m),
+            None => return Err("Match is not instantiated".into()),
+        };
+        if self.now() <= deadline {
+            return Err("Deadline is not over for this specific state".into());
+        }
+        let m = &mut self.matches[index];
+        if m.claimer == player && m.state == MatchState::WaitingChallenge {
+            m.state = MatchState::ClaimerWon;
+            return Ok(());
+        }
+        Err("Fail to ClaimVictoryByTime in current condition".into())
+    }
+
+    /// `timeOfLastMove + getMaxStateDuration(...)` as in `MatchInstantiator.getState`
+    fn match_deadline(&self, m: &MatchModel) -> u64 {
+        match m.state {
+            MatchState::WaitingChallenge => {
+                m.time_of_last_move
+                    + 2400
+                    + 40
+                    + self.config.final_time * 500 / 1_000_000_000_000
+                    + self.config.round_duration
+            }
+            _ => m.time_of_last_move,
+        }
+    }
+
+    fn matches_on_epoch(&self, epoch: u64) -> u64 {
+        *self.manager.matches_on_epoch.get(&epoch).unwrap_or(&0)
+    }
+
+    fn is_registered(&self, player: Address, epoch: u64) -> bool {
+        *self.manager.registered.get(&(player, epoch)).unwrap_or(&false)
+    }
+
+    fn player(&self, address: &Address) -> &SimPlayer {
+        self.players
+            .iter()
+            .find(|p| p.address == *address)
+            .expect("matches are only created between known players")
+    }
+
+    fn dappmock_instance(&self, player: Address) -> state::Instance {
+        let ctx = DAppMockCtx {
+            reveal_index: U256::zero(),
+            current_state: self.dapp_state.to_string(),
+        };
+        let mut sub_instances = vec![];
+        if self.dapp_state == DAppMockState::DAppRunning {
+            sub_instances.push(self.revealmock_instance(player));
+        }
+        build_instance(DAPPMOCK, player, ctx.to_json_data(), sub_instances)
+    }
+
+    fn revealmock_instance(&self, player: Address) -> state::Instance {
+        let ctx = RevealMockCtx {
+            commit_duration: U256::from(200),
+            reveal_duration: U256::from(200),
+            match_manager_epoch_duration: U256::from(self.config.epoch_duration),
+            match_manager_match_duration: U256::from(50),
+            final_time: U256::from(self.config.final_time),
+            initial_hash: H256::zero(),
+            machine_address: Address::zero(),
+            current_state: self.reveal_state.to_string(),
+        };
+        let sub_instances = vec![self.matchmanager_instance(player)];
+        build_instance(REVEALMOCK, player, ctx.to_json_data(), sub_instances)
+    }
+
+    fn matchmanager_instance(&self, player: Address) -> state::Instance {
+        let epoch = self.manager.current_epoch;
+        let last_match_index = self.manager.last_match_index.get(&player).cloned();
+        let last_match_epoch = self
+            .matches
+            .get(last_match_index.unwrap_or(0))
+            .map(|m| m.epoch_number)
+            .unwrap_or(U256::zero());
+
+        // same rule as MatchManagerInstantiator.hasMatch
+        let waiting_first_match = epoch == 0
+            && (self.manager.unmatched_player == player || !self.is_registered(player, 0));
+        let last_match = match waiting_first_match {
+            true => None,
+            false => self.matches.get(last_match_index.unwrap_or(0)),
+        };
+        // RevealMock.addFakePlayers commits every player at once
+        let commit_time = U256::from(self.config.start_time);
+
+        let ctx = MatchManagerCtx {
+            epoch_duration: U256::from(self.config.epoch_duration),
+            round_duration: U256::from(self.config.round_duration),
+            current_epoch: U256::from(epoch),
+            final_time: U256::from(self.config.final_time),
+            last_epoch_start_time: U256::from(self.manager.last_epoch_start_time),
+            // numberOfMatchesOnEpoch[currentEpoch - 1], which wraps around on epoch 0
+            number_of_matches_on_last_epoch: match epoch {
+                0 => U256::zero(),
+                _ => U256::from(self.matches_on_epoch(epoch - 1)),
+            },
+            last_match_index: U256::from(last_match_index.unwrap_or(0)),
+            parent_instance: U256::zero(),
+            last_match_epoch,
+            unmatched_player: self.manager.unmatched_player,
+            machine: Address::zero(),
+            parent_address: sim_address(2),
+            last_match_claimer_score: last_match
+                .map(|m| self.player(&m.claimer).score)
+                .unwrap_or_default(),
+            last_match_claimer_commit_time: last_match.map(|_| commit_time).unwrap_or_default(),
+            last_match_challenger_score: last_match
+                .map(|m| self.player(&m.challenger).score)
+                .unwrap_or_default(),
+            last_match_challenger_commit_time: last_match.map(|_| commit_time).unwrap_or_default(),
+            registered: self.is_registered(player, epoch),
+            current_state: self.manager.state.to_string(),
+        };
+
+        // same rule as MatchManagerInstantiator.getSubInstances
+        let mut sub_instances = vec![];
+        if let Some(m) = last_match {
+            let index = last_match_index.unwrap_or(0);
+            sub_instances.push(self.match_instance(player, index, m));
+        }
+        build_instance(MATCHMANAGER, player, ctx.to_json_data(), sub_instances)
+    }
+
+    fn match_instance(&self, player: Address, index: usize, m: &MatchModel) -> state::Instance {
+        let claimer = self.player(&m.claimer);
+        let ctx = MatchCtx {
+            challenger: m.challenger,
+            claimer: m.claimer,
+            machine: Address::zero(),
+            epoch_number: m.epoch_number,
+            deadline: U256::from(self.match_deadline(m)),
+            final_time: U256::from(self.config.final_time),
+            log_hash: claimer.log_hash,
+            initial_hash: claimer.initial_hash,
+            claimed_final_hash: claimer.final_hash,
+            current_state: m.state.to_string(),
+        };
+        let mut instance = build_instance(MATCH, player, ctx.to_json_data(), vec![]);
+        instance.index = U256::from(index);
+        instance
+    }
+
+    /// Short description of the model, for stuck runs
+    fn describe(&self) -> String {
+        let pending: Vec<String> = self
+            .matches
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.state == MatchState::WaitingChallenge)
+            .map(|(i, m)| format!("match {} {:?} vs {:?}", i, m.claimer, m.challenger))
+            .collect();
+        format!(
+            "DAppMock {}, RevealMock {}, MatchManager {} at epoch {} with unmatched player {:?}, \
+             pending matches [{}]",
+            self.dapp_state,
+            self.reveal_state,
+            self.manager.state,
+            self.manager.current_epoch,
+            self.manager.unmatched_player,
+            pending.join(", ")
+        )
+    }
+}
+
+fn build_instance(
+    name: &str,
+    player: Address,
+    json_data: String,
+    sub_instances: Vec<state::Instance>,
+) -> state::Instance {
+    state::Instance {
+        name: name.to_string(),
+        concern: Concern {
+            contract_address: contract_address(name),
+            user_address: player,
+        },
+        index: U256::zero(),
+        service_status: None,
+        json_data,
+        sub_instances: sub_instances.into_iter().map(Box::new).collect(),
+    }
+}
+
+fn contract_address(name: &str) -> Address {
+    match name {
+        DAPPMOCK => sim_address(1),
+        REVEALMOCK => sim_address(2),
+        MATCHMANAGER => sim_address(3),
+        _ => sim_address(4),
+    }
+}
+
+fn contract_name(address: &Address) -> &'static str {
+    [DAPPMOCK, REVEALMOCK, MATCHMANAGER, MATCH]
+        .iter()
+        .find(|name| contract_address(name) == *address)
+        .cloned()
+        .unwrap_or("unknown contract")
+}
+
+fn sim_address(n: u64) -> Address {
+    let mut address = [0u8; 20];
+    address[12..].copy_from_slice(&n.to_be_bytes());
+    Address::from(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::use_test_dirs;
+
+    fn run(players: Vec<SimPlayer>) -> SimOutcome {
+        use_test_dirs();
+        let config = SimulatorConfig {
+            // a few steps per epoch keep large brackets fast
+            time_step: 3_000,
+            max_steps: 1_000,
+            ..Default::default()
+        };
+        Simulator::new(config, players, NoServices)
+            .unwrap()
+            .run()
+            .unwrap()
+    }
+
+    fn players(count: u64) -> Vec<SimPlayer> {
+        // scores in no particular order, all distinct
+        (1..=count)
+            .map(|n| SimPlayer::new(n, (n * 7919) % 10_007))
+            .collect()
+    }
+
+    fn best(players: &[SimPlayer]) -> Address {
+        players.iter().max_by_key(|p| p.score).unwrap().address
+    }
+
+    // every match knocks one player out
+    fn check_bracket(count: u64) {
+        let players = players(count);
+        let outcome = run(players.clone());
+        assert_eq!(outcome.winner, best(&players));
+        assert_eq!(outcome.matches, count as usize - 1);
+        assert!(outcome.events.iter().all(|e| e.reverted.is_none()));
+    }
+
+    #[test]
+    fn two_players() {
+        check_bracket(2);
+    }
+
+    #[test]
+    fn three_players() {
+        check_bracket(3);
+    }
+
+    #[test]
+    fn sixteen_players() {
+        check_bracket(16);
+    }
+
+    #[test]
+    fn thousand_players() {
+        check_bracket(1000);
+    }
+
+    #[test]
+    fn odd_player_out_gets_a_bye() {
+        let players = players(5);
+        let outcome = run(players.clone());
+
+        let first_epoch: Vec<&SimEvent> = outcome
+            .events
+            .iter()
+            .filter(|e| e.contract == MATCH && e.time < outcome.events[0].time + 6000)
+            .collect();
+        // two matches in the first epoch, the fifth player waits for the next
+        assert_eq!(first_epoch.len(), 2);
+        assert_eq!(outcome.matches, 4);
+        assert_eq!(outcome.winner, best(&players));
+    }
+}
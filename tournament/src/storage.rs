@@ -0,0 +1,70 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Files the tournament keeps between dispatcher runs.
+
+use super::error::*;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Environment variable pointing to the tournament's storage directory
+pub const STORAGE_DIR_VAR: &str = "TOURNAMENT_STORAGE_DIR";
+/// Storage directory used when `TOURNAMENT_STORAGE_DIR` is not set
+pub const DEFAULT_STORAGE_DIR: &str = "/opt/cartesi/srv/dispatcher/tournament";
+
+/// Environment variable pointing to the directory shared with the logger
+pub const LOGS_DIR_VAR: &str = "TOURNAMENT_LOGS_DIR";
+
+/// Root of the tournament's storage
+pub fn storage_dir() -> PathBuf {
+    env::var_os(STORAGE_DIR_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_STORAGE_DIR))
+}
+
+/// Directory `name` inside the storage, created if missing
+pub fn storage_subdir(name: &str) -> Result<PathBuf> {
+    let dir = storage_dir().join(name);
+    fs::create_dir_all(&dir)
+        .chain_err(|| format!("Could not create storage directory {}", dir.display()))?;
+    Ok(dir)
+}
+
+/// Local path of a log file as named to the logger service. Relative paths
+/// are resolved against `TOURNAMENT_LOGS_DIR`, the current directory if unset.
+pub fn log_file_path(path: &str) -> PathBuf {
+    match env::var_os(LOGS_DIR_VAR) {
+        Some(dir) => PathBuf::from(dir).join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Points the storage and the logs directories to a directory of this
+/// test process, so tests never touch the real ones
+#[cfg(test)]
+pub fn use_test_dirs() -> PathBuf {
+    let dir = env::temp_dir().join(format!("tournament-test-{}", ::std::process::id()));
+    env::set_var(STORAGE_DIR_VAR, dir.join("storage"));
+    env::set_var(LOGS_DIR_VAR, dir.join("logs"));
+    fs::create_dir_all(dir.join("logs")).unwrap();
+    dir
+}
@@ -0,0 +1,379 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Offline replay of the challenger's decision on a match.
+//!
+//! Given the match context, the opponent's log and the hashes of the
+//! opponent's machine built from it, tells whether the claim stands, as
+//! `Match` would decide it when playing the challenger. The context comes
+//! from a `json_data` dump or straight from a node through `eth_call`, and
+//! the hashes from an emulator command run on the log.
+
+use super::error::*;
+use super::ethereum_types::{Address, H256, U256};
+use super::hex;
+use super::http;
+use super::merkle::{get_root_of_data, keccak};
+use super::policy::{ChallengeDecision, ChallengePolicy};
+use super::r#match::{verify_claim, ClaimVerification, MatchCtx, MatchCtxParsed};
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+/// What the verifier needs to know of the machine template
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VerifierTemplate {
+    pub page_log2_size: u64,
+    pub tree_log2_size: u64,
+    /// Start of the log drive in the opponent's machine
+    #[serde(default)]
+    pub log_drive_position: Option<u64>,
+    /// Command printing the hashes of the opponent's machine
+    #[serde(default)]
+    pub emulator: Option<EmulatorCommand>,
+}
+
+/// Command running the opponent's machine, e.g. the `cartesi-machine`
+/// CLI. `{log}`, `{log_drive_position}` and `{final_time}` are replaced
+/// in the arguments, and every `cycle: hash` line of its output is read
+/// as the hash of the machine at that cycle.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmulatorCommand {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+impl EmulatorCommand {
+    /// Hashes of the machine reading `log` at cycles 0 and `final_time`
+    pub fn run(
+        &self,
+        log: &Path,
+        log_drive_position: Option<u64>,
+        final_time: u64,
+    ) -> Result<Vec<H256>> {
+        let args: Vec<String> = self
+            .args
+            .iter()
+            .map(|a| {
+                a.replace("{log}", &log.to_string_lossy())
+                    .replace(
+                        "{log_drive_position}",
+                        &format!("{:#x}", log_drive_position.unwrap_or(0)),
+                    )
+                    .replace("{final_time}", &final_time.to_string())
+            })
+            .collect();
+        let output = Command::new(&self.program)
+            .args(&args)
+            .output()
+            .chain_err(|| format!("Could not run {}", self.program))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} failed with {}: {}",
+                self.program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+
+        // the hashes may be printed to either stream
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        let mut initial = None;
+        let mut last = None;
+        for line in text.lines() {
+            let mut parts = line.splitn(2, ':');
+            let cycle = parts.next().and_then(|c| c.trim().parse::<u64>().ok());
+            let hash = parts.next().and_then(|h| parse_h256(h.trim()).ok());
+            match (cycle, hash) {
+                (Some(0), Some(hash)) => initial = Some(hash),
+                (Some(cycle), Some(hash)) if cycle == final_time => last = Some(hash),
+                _ => {}
+            }
+        }
+        match (initial, last) {
+            (Some(initial), Some(last)) => Ok(vec![initial, last]),
+            _ => Err(format!(
+                "{} did not print the hashes at cycles 0 and {}",
+                self.program, final_time
+            )
+            .into()),
+        }
+    }
+}
+
+/// The challenger's decision on a match
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// The log is not the one the claimer revealed
+    LogMismatch { expected: H256, actual: H256 },
+    /// The machine reading the log does not start from the on-chain
+    /// initial hash, a challenge would be lost
+    NotReproducible { expected: H256, actual: H256 },
+    /// The claim is right, there is nothing to challenge
+    Confirmed,
+    /// The claimed final hash is wrong, challenged unless the policy passes
+    Wrong {
+        claimed: H256,
+        actual: H256,
+        challenge: ChallengeDecision,
+    },
+}
+
+impl Verdict {
+    /// Process exit code for the verdict
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Verdict::Confirmed => 0,
+            Verdict::Wrong { .. } => 1,
+            Verdict::NotReproducible { .. } => 2,
+            Verdict::LogMismatch { .. } => 3,
+        }
+    }
+}
+
+impl ::std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            Verdict::LogMismatch { expected, actual } => write!(
+                f,
+                "the log has root {:?}, not the revealed {:?}: it is not the opponent's log",
+                actual, expected
+            ),
+            Verdict::NotReproducible { expected, actual } => write!(
+                f,
+                "the machine starts from {:?}, not the on-chain {:?}: \
+                 a challenge could not be won, the challenger does not challenge",
+                actual, expected
+            ),
+            Verdict::Confirmed => {
+                write!(f, "the claimed final hash is right, nothing to challenge")
+            }
+            Verdict::Wrong {
+                claimed,
+                actual,
+                challenge: ChallengeDecision::Challenge,
+            } => write!(
+                f,
+                "the machine ends in {:?}, not the claimed {:?}: the challenger challenges",
+                actual, claimed
+            ),
+            Verdict::Wrong {
+                claimed,
+                actual,
+                challenge: ChallengeDecision::Pass(ref reason),
+            } => write!(
+                f,
+                "the machine ends in {:?}, not the claimed {:?}, \
+                 but the challenger does not challenge: {}",
+                actual, claimed, reason
+            ),
+        }
+    }
+}
+
+/// Replays the challenger's decision on the match `ctx`, with the
+/// opponent's `log` and the `hashes` of its machine at 0 and `final_time`
+pub fn verify_match(
+    ctx: &MatchCtx,
+    log: &[u8],
+    template: &VerifierTemplate,
+    hashes: &[H256],
+    policy: &dyn ChallengePolicy,
+) -> Result<Verdict> {
+    let root = get_root_of_data(log, template.page_log2_size + template.tree_log2_size)?;
+    if root != ctx.log_hash {
+        return Ok(Verdict::LogMismatch {
+            expected: ctx.log_hash,
+            actual: root,
+        });
+    }
+
+    Ok(match verify_claim(ctx, hashes)? {
+        ClaimVerification::Confirmed => Verdict::Confirmed,
+        ClaimVerification::InitialHashMismatch(actual) => Verdict::NotReproducible {
+            expected: ctx.initial_hash,
+            actual,
+        },
+        ClaimVerification::FinalHashMismatch(actual) => Verdict::Wrong {
+            claimed: ctx.claimed_final_hash,
+            actual,
+            challenge: policy.on_mismatch(ctx),
+        },
+    })
+}
+
+/// Match context from the `json_data` of a match instance
+pub fn match_ctx_from_json(json_data: &str) -> Result<MatchCtx> {
+    let parsed: MatchCtxParsed = serde_json::from_str(json_data)
+        .chain_err(|| format!("Could not parse match json_data: {}", json_data))?;
+    Ok(parsed.into())
+}
+
+/// Match context of instance `index` of the Match contract at `address`,
+/// read through `eth_call` from the node at `rpc_url`
+pub fn fetch_match_ctx(rpc_url: &str, address: Address, index: U256) -> Result<MatchCtx> {
+    // getState(uint256 _index, address), the address is not used
+    let mut data = keccak(&[b"getState(uint256,address)"])[..4].to_vec();
+    let mut word = [0u8; 32];
+    index.to_big_endian(&mut word);
+    data.extend_from_slice(&word);
+    data.extend_from_slice(&[0u8; 32]);
+
+    let result = http::json_rpc(
+        rpc_url,
+        "eth_call",
+        serde_json::json!([
+            { "to": format!("{:?}", address), "data": format!("0x{}", hex::encode(&data)) },
+            "latest"
+        ]),
+        Duration::from_secs(30),
+    )?;
+    let result = result.as_str().ok_or(Error::from(format!(
+        "eth_call to {:?} did not return a string",
+        address
+    )))?;
+    let result = hex::decode(result.trim_start_matches("0x"))
+        .chain_err(|| format!("Could not decode the getState of {:?}", address))?;
+    decode_match_state(&result)
+        .chain_err(|| format!("Could not decode the getState of {:?}", address))
+}
+
+/// Match context of the return data of `MatchInstantiator.getState`:
+/// `address[3]`, `uint256[3]`, `bytes32[3]` and `bytes32`, ten static words
+fn decode_match_state(result: &[u8]) -> Result<MatchCtx> {
+    if result.len() != 10 * 32 {
+        return Err(format!("{} bytes returned, expected {}", result.len(), 10 * 32).into());
+    }
+    let words: Vec<&[u8]> = result.chunks(32).collect();
+    let current_state = String::from_utf8_lossy(words[9])
+        .trim_end_matches('\0')
+        .to_string();
+
+    Ok(MatchCtx {
+        challenger: Address::from_slice(&words[0][12..]),
+        claimer: Address::from_slice(&words[1][12..]),
+        machine: Address::from_slice(&words[2][12..]),
+        epoch_number: U256::from_big_endian(words[3]),
+        deadline: U256::from_big_endian(words[4]),
+        final_time: U256::from_big_endian(words[5]),
+        log_hash: H256::from_slice(words[6]),
+        initial_hash: H256::from_slice(words[7]),
+        claimed_final_hash: H256::from_slice(words[8]),
+        current_state,
+    })
+}
+
+/// Hash as printed by the emulator or given on the command line,
+/// with or without `0x`
+pub fn parse_h256(s: &str) -> Result<H256> {
+    let bytes =
+        hex::decode(s.trim_start_matches("0x")).chain_err(|| format!("Invalid hash {}", s))?;
+    if bytes.len() != 32 {
+        return Err(format!("Invalid hash {}, expected 32 bytes", s).into());
+    }
+    Ok(H256::from_slice(&bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // eth_call of getState on a match waiting for its challenge
+    const RECORDED_STATE: &str = concat!(
+        "0x",
+        "0000000000000000000000002ad38f50f38abc5cbcf175e1962293eecc7936de",
+        "0000000000000000000000009f7e0b1a1cdb8a2f6e3c5d4b8a9e0f1d2c3b4a59",
+        "000000000000000000000000e0b3bb0a4c5c6a7ee1ab2bf7b7ad4d9b2b9ba1f3",
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "000000000000000000000000000000000000000000000000000000005f5e1bea",
+        "0000000000000000000000000000000000000000000000000000000000989680",
+        "7ba2a0ef4a6b45e1d6ac0e3c3a9a5f6b3e39d5b8d1c5a0b7c2f3e4d5a6b7c8d9",
+        "c1cd6e0b4c5a9f0b3a7d8e9f0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d",
+        "5d4c3b2a19f8e7d6c5b4a39281706f5e4d3c2b1a0f9e8d7c6b5a493827160504",
+        "57616974696e674368616c6c656e676500000000000000000000000000000000"
+    );
+
+    fn assert_recorded(ctx: &MatchCtx) {
+        let address = |s: &str| s.parse::<Address>().unwrap();
+        let hash = |s: &str| s.parse::<H256>().unwrap();
+        assert_eq!(
+            ctx.challenger,
+            address("2ad38f50f38abc5cbcf175e1962293eecc7936de")
+        );
+        assert_eq!(
+            ctx.claimer,
+            address("9f7e0b1a1cdb8a2f6e3c5d4b8a9e0f1d2c3b4a59")
+        );
+        assert_eq!(
+            ctx.machine,
+            address("e0b3bb0a4c5c6a7ee1ab2bf7b7ad4d9b2b9ba1f3")
+        );
+        assert_eq!(ctx.epoch_number, U256::from(0));
+        assert_eq!(ctx.deadline, U256::from(1_600_003_050));
+        assert_eq!(ctx.final_time, U256::from(10_000_000));
+        assert_eq!(
+            ctx.log_hash,
+            hash("7ba2a0ef4a6b45e1d6ac0e3c3a9a5f6b3e39d5b8d1c5a0b7c2f3e4d5a6b7c8d9")
+        );
+        assert_eq!(
+            ctx.initial_hash,
+            hash("c1cd6e0b4c5a9f0b3a7d8e9f0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d")
+        );
+        assert_eq!(
+            ctx.claimed_final_hash,
+            hash("5d4c3b2a19f8e7d6c5b4a39281706f5e4d3c2b1a0f9e8d7c6b5a493827160504")
+        );
+        assert_eq!(ctx.current_state, "WaitingChallenge");
+    }
+
+    #[test]
+    fn recorded_get_state_is_decoded() {
+        let result = hex::decode(&RECORDED_STATE[2..]).unwrap();
+        assert_recorded(&decode_match_state(&result).unwrap());
+        assert!(decode_match_state(&result[..9 * 32]).is_err());
+    }
+
+    #[test]
+    fn match_ctx_is_fetched_with_get_state() {
+        let url = http::serve(|_, body| {
+            let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+            let data = request["params"][0]["data"].as_str().unwrap().to_string();
+            // selector, index 7 and the unused address
+            assert_eq!(data.len(), 2 + 2 * (4 + 32 + 32));
+            assert_eq!(
+                &data[2..10],
+                &hex::encode(&keccak(&[b"getState(uint256,address)"])[..4])[..]
+            );
+            assert_eq!(
+                U256::from(&hex::decode(&data[10..74]).unwrap()[..]),
+                U256::from(7)
+            );
+            let response = serde_json::json!({ "jsonrpc": "2.0", "id": request["id"], "result": RECORDED_STATE });
+            (200, response.to_string().into_bytes())
+        });
+
+        let ctx = fetch_match_ctx(&url, Address::from([9; 20]), U256::from(7)).unwrap();
+        assert_recorded(&ctx);
+    }
+}
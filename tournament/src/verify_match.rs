@@ -0,0 +1,172 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Replays the challenger's decision on a match, without the dispatcher:
+//!
+//! ```text
+//! verify_match --template template.json --log opponent.json.br.cpio
+//!     (--ctx json_data.json | --rpc http://localhost:8545 --address 0x.. --index 0)
+//!     [--hashes <initial> <final>] [--account 0x..]
+//! ```
+//!
+//! The template is a `VerifierTemplate`. Without `--hashes`, its emulator
+//! command computes them. The challenge policy is the one of `--account`,
+//! the match's challenger by default, in `TOURNAMENT_CONFIG_PATH`. Exits
+//! with 0 if the claim is right, 1 if it is wrong, 2 if the log does not
+//! reproduce the on-chain machine, 3 if it is not the opponent's log and
+//! 4 on errors.
+
+// error-chain recursion
+#![recursion_limit = "1024"]
+#![warn(unused_extern_crates)]
+#![allow(clippy::result_large_err)]
+
+extern crate error;
+extern crate ethereum_types;
+extern crate tournament;
+extern crate utils;
+
+use error::*;
+use ethereum_types::{Address, U256};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use tournament::config::tournament_config;
+use tournament::verifier::{
+    fetch_match_ctx, match_ctx_from_json, parse_h256, verify_match, Verdict, VerifierTemplate,
+};
+use utils::print_error;
+
+const ERROR_EXIT_CODE: i32 = 4;
+
+const USAGE: &str = "usage: verify_match --template <file> --log <file> \
+                     (--ctx <file> | --rpc <url> --address <address> --index <index>) \
+                     [--hashes <initial> <final>] [--account <address>]";
+
+fn main() {
+    env_logger::init();
+
+    match run() {
+        Ok(verdict) => process::exit(verdict.exit_code()),
+        Err(ref e) => {
+            print_error(e);
+            process::exit(ERROR_EXIT_CODE);
+        }
+    }
+}
+
+fn run() -> Result<Verdict> {
+    let args = parse_args(env::args().skip(1).collect())?;
+    let arg = |name: &str| -> Result<&Vec<String>> {
+        args.get(name)
+            .ok_or(Error::from(format!("Missing --{}\n{}", name, USAGE)))
+    };
+
+    let template_path = &arg("template")?[0];
+    let template: VerifierTemplate = serde_json::from_str(
+        &fs::read_to_string(template_path)
+            .chain_err(|| format!("Could not read template {}", template_path))?,
+    )
+    .chain_err(|| format!("Could not parse template {}", template_path))?;
+
+    let ctx = match args.get("ctx") {
+        Some(path) => match_ctx_from_json(
+            &fs::read_to_string(&path[0])
+                .chain_err(|| format!("Could not read match context {}", path[0]))?,
+        )?,
+        None => {
+            let address: Address = parse_address(&arg("address")?[0])?;
+            let index = U256::from_dec_str(&arg("index")?[0])
+                .map_err(|_| Error::from(format!("Invalid index {}", arg("index").unwrap()[0])))?;
+            fetch_match_ctx(&arg("rpc")?[0], address, index)?
+        }
+    };
+    println!("match state:        {}", ctx.current_state);
+    println!("claimer:            {:?}", ctx.claimer);
+    println!("challenger:         {:?}", ctx.challenger);
+    println!("final time:         {}", ctx.final_time);
+    println!("log hash:           {:?}", ctx.log_hash);
+    println!("initial hash:       {:?}", ctx.initial_hash);
+    println!("claimed final hash: {:?}", ctx.claimed_final_hash);
+
+    let log_path = Path::new(&arg("log")?[0]);
+    let log =
+        fs::read(log_path).chain_err(|| format!("Could not read log {}", log_path.display()))?;
+
+    let hashes = match args.get("hashes") {
+        Some(hashes) => vec![parse_h256(&hashes[0])?, parse_h256(&hashes[1])?],
+        None => template
+            .emulator
+            .as_ref()
+            .ok_or(Error::from(
+                "Either --hashes or an emulator command in the template is needed",
+            ))?
+            .run(
+                log_path,
+                template.log_drive_position,
+                ctx.final_time.as_u64(),
+            )?,
+    };
+    println!(
+        "machine hashes:     {:?} (0), {:?} ({})",
+        hashes[0], hashes[1], ctx.final_time
+    );
+
+    let account = match args.get("account") {
+        Some(account) => parse_address(&account[0])?,
+        None => ctx.challenger,
+    };
+    let policy = tournament_config()?.challenge_policy(&account).build();
+    if !policy.verifies() {
+        println!("note: the policy of {:?} does not verify claims", account);
+    }
+
+    let verdict = verify_match(&ctx, &log, &template, &hashes, &*policy)?;
+    println!("verdict:            {}", verdict);
+    Ok(verdict)
+}
+
+/// `--name value...` pairs, `--hashes` takes two values
+fn parse_args(args: Vec<String>) -> Result<HashMap<String, Vec<String>>> {
+    let mut parsed = HashMap::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            return Err(format!("Unexpected argument {}\n{}", arg, USAGE).into());
+        }
+        let name = arg[2..].to_string();
+        let count = if name == "hashes" { 2 } else { 1 };
+        let values: Vec<String> = args.by_ref().take(count).collect();
+        if values.len() != count {
+            return Err(format!("Missing value of {}\n{}", arg, USAGE).into());
+        }
+        parsed.insert(name, values);
+    }
+    Ok(parsed)
+}
+
+fn parse_address(s: &str) -> Result<Address> {
+    s.trim_start_matches("0x")
+        .parse()
+        .map_err(|_| Error::from(format!("Invalid address {}", s)))
+}
@@ -0,0 +1,533 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+//! Read-only view of a tournament's bracket.
+//!
+//! `MatchManagerInstantiator.getState` only tells a player about its own
+//! last match, so the bracket is rebuilt from the instances of as many
+//! players as available, optionally completed with the `MatchInstantiator`
+//! events. The more players are added, the more complete the view.
+
+use super::error::*;
+use super::ethereum_types::{Address, H256, U256};
+use super::hex;
+use super::matchmanager::{MatchManagerCtx, MatchManagerCtxParsed, MatchManagerState};
+use super::merkle::keccak;
+use r#match::{MatchCtx, MatchCtxParsed, MatchState};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+
+const MATCH_CREATED: &str = "MatchCreated(uint256,address,address,uint256,uint256,address,\
+                             bytes32,bytes32,bytes32,uint256,uint256)";
+const CHALLENGE_STARTED: &str = "ChallengeStarted(uint256)";
+const MATCH_FINISHED: &str = "MatchFinished(uint256,uint8)";
+
+/// A `MatchInstantiator` event, decoded. `MatchInstantiator` is shared by
+/// every match manager, only the events of the tournament's matches
+/// should be added.
+#[derive(Debug, Clone)]
+pub enum MatchEvent {
+    MatchCreated {
+        index: U256,
+        challenger: Address,
+        claimer: Address,
+        epoch_number: U256,
+    },
+    ChallengeStarted {
+        index: U256,
+    },
+    /// `_state` as the `uint8` of the `state` enum
+    MatchFinished {
+        index: U256,
+        state: u8,
+    },
+}
+
+/// A log as returned by `eth_getLogs`, with the hex encoded `data`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EventLog {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    pub data: String,
+}
+
+impl EventLog {
+    /// Reads a JSON list of logs
+    pub fn load(path: &str) -> Result<Vec<EventLog>> {
+        let contents =
+            fs::read_to_string(path).chain_err(|| format!("Could not read event logs {}", path))?;
+        serde_json::from_str(&contents).chain_err(|| format!("Could not parse event logs {}", path))
+    }
+}
+
+impl MatchEvent {
+    /// Decodes a `MatchInstantiator` log, `None` for the other events
+    pub fn from_log(log: &EventLog) -> Result<Option<MatchEvent>> {
+        let topic = match log.topics.first() {
+            Some(topic) => *topic,
+            None => return Ok(None),
+        };
+        let data = hex::decode(log.data.trim_start_matches("0x"))
+            .chain_err(|| format!("Invalid data of log {:?}", topic))?;
+        // every argument is a word, none of them is indexed
+        let words = data.len() / 32;
+        let word = |i: usize| &data[32 * i..32 * (i + 1)];
+        let uint = |i: usize| U256::from_big_endian(word(i));
+        let address = |i: usize| Address::from_slice(&word(i)[12..]);
+        let expect = |count: usize| -> Result<()> {
+            match words >= count {
+                true => Ok(()),
+                false => Err(format!(
+                    "Log {:?} has {} words of data, {} expected",
+                    topic, words, count
+                )
+                .into()),
+            }
+        };
+
+        if topic == keccak(&[MATCH_CREATED.as_bytes()]) {
+            expect(11)?;
+            Ok(Some(MatchEvent::MatchCreated {
+                index: uint(0),
+                challenger: address(1),
+                claimer: address(2),
+                epoch_number: uint(3),
+            }))
+        } else if topic == keccak(&[CHALLENGE_STARTED.as_bytes()]) {
+            expect(1)?;
+            Ok(Some(MatchEvent::ChallengeStarted { index: uint(0) }))
+        } else if topic == keccak(&[MATCH_FINISHED.as_bytes()]) {
+            expect(2)?;
+            Ok(Some(MatchEvent::MatchFinished {
+                index: uint(0),
+                state: word(1)[31],
+            }))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MatchView {
+    pub index: U256,
+    pub epoch: U256,
+    pub claimer: Address,
+    pub challenger: Address,
+    pub state: MatchState,
+    pub winner: Option<Address>,
+    /// Deadline of the current state, unknown when only seen in events
+    pub deadline: Option<U256>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerStatus {
+    /// Registered, but not matched yet
+    Registered,
+    /// Playing a match
+    Playing,
+    /// Won its last match, not registered for the next epoch yet
+    Advancing,
+    /// Registered for the next epoch, waiting for an opponent
+    WaitingOpponent,
+    Eliminated,
+    Winner,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PlayerStanding {
+    pub player: Address,
+    pub status: PlayerStatus,
+    pub matches_won: u64,
+    /// Last match the player is known to have played
+    pub last_match: Option<U256>,
+    pub last_epoch: Option<U256>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct EpochView {
+    pub epoch: U256,
+    pub matches: Vec<MatchView>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Standings {
+    pub current_epoch: Option<U256>,
+    pub state: Option<MatchManagerState>,
+    pub unmatched_player: Option<Address>,
+    pub epochs: Vec<EpochView>,
+    pub players: Vec<PlayerStanding>,
+}
+
+#[derive(Default)]
+pub struct TournamentView {
+    current_epoch: Option<U256>,
+    state: Option<MatchManagerState>,
+    unmatched_player: Option<Address>,
+    players: BTreeSet<Address>,
+    matches: BTreeMap<U256, MatchView>,
+}
+
+impl TournamentView {
+    pub fn new() -> TournamentView {
+        Default::default()
+    }
+
+    /// Adds the `MatchManager` instance of a player, with its `Match`
+    /// sub-instance if it has one
+    pub fn add_match_manager(&mut self, instance: &state::Instance) -> Result<()> {
+        let parsed: MatchManagerCtxParsed =
+            serde_json::from_str(&instance.json_data).chain_err(|| {
+                format!(
+                    "Could not parse matchmanager instance json_data: {}",
+                    &instance.json_data
+                )
+            })?;
+        let ctx: MatchManagerCtx = parsed.into();
+        let state: MatchManagerState = ctx.current_state.parse()?;
+
+        // the latest snapshot of the manager wins
+        if self
+            .current_epoch
+            .map_or(true, |epoch| ctx.current_epoch >= epoch)
+        {
+            self.current_epoch = Some(ctx.current_epoch);
+            if self.state != Some(MatchManagerState::MatchesOver) {
+                self.state = Some(state);
+            }
+            self.unmatched_player = match ctx.unmatched_player.is_zero() {
+                true => None,
+                false => Some(ctx.unmatched_player),
+            };
+        }
+
+        if ctx.registered || ctx.unmatched_player == instance.concern.user_address {
+            self.players.insert(instance.concern.user_address);
+        }
+        if let Some(unmatched_player) = self.unmatched_player {
+            self.players.insert(unmatched_player);
+        }
+
+        if let Some(match_instance) = instance.sub_instances.first() {
+            self.add_match(ctx.last_match_index, match_instance)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the `Match` instance of index `index`
+    pub fn add_match(&mut self, index: U256, instance: &state::Instance) -> Result<()> {
+        let parsed: MatchCtxParsed = serde_json::from_str(&instance.json_data).chain_err(|| {
+            format!(
+                "Could not parse match instance json_data: {}",
+                &instance.json_data
+            )
+        })?;
+        let ctx: MatchCtx = parsed.into();
+        let state: MatchState = ctx.current_state.parse()?;
+
+        self.merge_match(MatchView {
+            index,
+            epoch: ctx.epoch_number,
+            claimer: ctx.claimer,
+            challenger: ctx.challenger,
+            state,
+            winner: winner(state, &ctx.claimer, &ctx.challenger),
+            deadline: Some(ctx.deadline),
+        });
+        Ok(())
+    }
+
+    pub fn add_event(&mut self, event: &MatchEvent) -> Result<()> {
+        match *event {
+            MatchEvent::MatchCreated {
+                index,
+                challenger,
+                claimer,
+                epoch_number,
+            } => {
+                self.merge_match(MatchView {
+                    index,
+                    epoch: epoch_number,
+                    claimer,
+                    challenger,
+                    state: MatchState::WaitingChallenge,
+                    winner: None,
+                    deadline: None,
+                });
+            }
+            MatchEvent::ChallengeStarted { index } => {
+                self.update_match_state(index, MatchState::ChallengeStarted)?;
+            }
+            MatchEvent::MatchFinished { index, state } => {
+                let state = match state {
+                    2 => MatchState::ChallengerWon,
+                    3 => MatchState::ClaimerWon,
+                    _ => {
+                        return Err(Error::from(ErrorKind::InvalidContractState(format!(
+                            "Match {} finished in state {}",
+                            index, state
+                        ))));
+                    }
+                };
+                self.update_match_state(index, state)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the event of a `MatchInstantiator` log, other logs are ignored
+    pub fn add_log(&mut self, log: &EventLog) -> Result<()> {
+        match MatchEvent::from_log(log)? {
+            Some(event) => self.add_event(&event),
+            None => Ok(()),
+        }
+    }
+
+    /// Where `player` stands, `None` if it was never seen
+    pub fn locate(&self, player: &Address) -> Option<PlayerStanding> {
+        if !self.players.contains(player) {
+            return None;
+        }
+
+        let matches: Vec<&MatchView> = self
+            .matches
+            .values()
+            .filter(|m| m.claimer == *player || m.challenger == *player)
+            .collect();
+        let matches_won = matches.iter().filter(|m| m.winner == Some(*player)).count() as u64;
+        let last = matches.last();
+        let lost = matches
+            .iter()
+            .any(|m| m.winner.map_or(false, |winner| winner != *player));
+
+        let status = if lost {
+            PlayerStatus::Eliminated
+        } else if self.unmatched_player == Some(*player) {
+            match self.state {
+                Some(MatchManagerState::MatchesOver) => PlayerStatus::Winner,
+                _ => PlayerStatus::WaitingOpponent,
+            }
+        } else {
+            match last {
+                Some(m) if m.winner.is_none() => PlayerStatus::Playing,
+                Some(_) => PlayerStatus::Advancing,
+                None => PlayerStatus::Registered,
+            }
+        };
+
+        Some(PlayerStanding {
+            player: *player,
+            status,
+            matches_won,
+            last_match: last.map(|m| m.index),
+            last_epoch: last.map(|m| m.epoch),
+        })
+    }
+
+    pub fn standings(&self) -> Standings {
+        let mut epochs: BTreeMap<U256, Vec<MatchView>> = BTreeMap::new();
+        for m in self.matches.values() {
+            epochs
+                .entry(m.epoch)
+                .or_default()
+                .push(m.clone());
+        }
+
+        Standings {
+            current_epoch: self.current_epoch,
+            state: self.state,
+            unmatched_player: self.unmatched_player,
+            epochs: epochs
+                .into_iter()
+                .map(|(epoch, matches)| EpochView {
+                    epoch,
+                    matches,
+                })
+                .collect(),
+            players: self.players.iter().filter_map(|p| self.locate(p)).collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.standings()).unwrap()
+    }
+
+    fn merge_match(&mut self, m: MatchView) {
+        self.players.insert(m.claimer);
+        self.players.insert(m.challenger);
+
+        // states only move forward, an older snapshot never replaces a newer one
+        let keep_existing = match self.matches.get(&m.index) {
+            Some(existing) => progress(existing.state) > progress(m.state),
+            None => false,
+        };
+        if keep_existing {
+            return;
+        }
+        let deadline = match self.matches.get(&m.index) {
+            Some(existing) if m.deadline.is_none() && existing.state == m.state => {
+                existing.deadline
+            }
+            _ => m.deadline,
+        };
+        self.matches.insert(
+            m.index,
+            MatchView {
+                deadline,
+                ..m
+            },
+        );
+    }
+
+    fn update_match_state(&mut self, index: U256, state: MatchState) -> Result<()> {
+        let m = self.matches.get_mut(&index).ok_or_else(|| {
+            Error::from(format!(
+                "Match {} changed state before being created, add its MatchCreated event first",
+                index
+            ))
+        })?;
+        if progress(state) > progress(m.state) {
+            m.state = state;
+            m.winner = winner(state, &m.claimer, &m.challenger);
+            m.deadline = None;
+        }
+        Ok(())
+    }
+}
+
+fn winner(state: MatchState, claimer: &Address, challenger: &Address) -> Option<Address> {
+    match state {
+        MatchState::ClaimerWon => Some(*claimer),
+        MatchState::ChallengerWon => Some(*challenger),
+        MatchState::WaitingChallenge | MatchState::ChallengeStarted => None,
+    }
+}
+
+fn progress(state: MatchState) -> u8 {
+    match state {
+        MatchState::WaitingChallenge => 0,
+        MatchState::ChallengeStarted => 1,
+        MatchState::ChallengerWon | MatchState::ClaimerWon => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(value: u64) -> Vec<u8> {
+        let mut word = vec![0; 32];
+        U256::from(value).to_big_endian(&mut word);
+        word
+    }
+
+    fn address_word(n: u8) -> Vec<u8> {
+        let mut word = vec![0; 12];
+        word.extend_from_slice(&[n; 20]);
+        word
+    }
+
+    fn log(signature: &str, words: Vec<Vec<u8>>) -> EventLog {
+        EventLog {
+            address: Address::from([9; 20]),
+            topics: vec![keccak(&[signature.as_bytes()])],
+            data: format!("0x{}", hex::encode(words.concat())),
+        }
+    }
+
+    fn created(index: u64, challenger: u8, claimer: u8, epoch: u64) -> EventLog {
+        let mut words = vec![
+            word(index),
+            address_word(challenger),
+            address_word(claimer),
+            word(epoch),
+        ];
+        // round duration to time of last move, not part of the view
+        words.extend((0..7).map(word));
+        log(MATCH_CREATED, words)
+    }
+
+    fn finished(index: u64, state: u64) -> EventLog {
+        log(MATCH_FINISHED, vec![word(index), word(state)])
+    }
+
+    #[test]
+    fn match_instantiator_logs_are_decoded() {
+        match MatchEvent::from_log(&created(4, 1, 2, 3)).unwrap() {
+            Some(MatchEvent::MatchCreated {
+                index,
+                challenger,
+                claimer,
+                epoch_number,
+            }) => {
+                assert_eq!(index, U256::from(4));
+                assert_eq!(challenger, Address::from([1; 20]));
+                assert_eq!(claimer, Address::from([2; 20]));
+                assert_eq!(epoch_number, U256::from(3));
+            }
+            _ => panic!("expected MatchCreated"),
+        }
+        match MatchEvent::from_log(&finished(4, 3)).unwrap() {
+            Some(MatchEvent::MatchFinished { index, state }) => {
+                assert_eq!(index, U256::from(4));
+                assert_eq!(state, 3);
+            }
+            _ => panic!("expected MatchFinished"),
+        }
+        let started = log(CHALLENGE_STARTED, vec![word(4)]);
+        assert!(MatchEvent::from_log(&started).unwrap().is_some());
+
+        let other = log("VGCreated(uint256)", vec![word(4)]);
+        assert!(MatchEvent::from_log(&other).unwrap().is_none());
+        let short = log(MATCH_FINISHED, vec![word(4)]);
+        assert!(MatchEvent::from_log(&short).is_err());
+    }
+
+    #[test]
+    fn bracket_is_rebuilt_from_logs() {
+        let mut view = TournamentView::new();
+        let logs = vec![
+            created(0, 2, 1, 0),
+            created(1, 4, 3, 0),
+            finished(0, 3),
+            finished(1, 2),
+            created(2, 1, 4, 1),
+        ];
+        for log in &logs {
+            view.add_log(log).unwrap();
+        }
+
+        let status = |n: u8| view.locate(&Address::from([n; 20])).unwrap().status;
+        assert_eq!(status(1), PlayerStatus::Playing);
+        assert_eq!(status(2), PlayerStatus::Eliminated);
+        assert_eq!(status(3), PlayerStatus::Eliminated);
+        assert_eq!(status(4), PlayerStatus::Playing);
+        assert!(view.locate(&Address::from([5; 20])).is_none());
+
+        let standings = view.standings();
+        assert_eq!(standings.epochs.len(), 2);
+        assert_eq!(
+            standings.epochs[1].matches[0].claimer,
+            Address::from([4; 20])
+        );
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright (C) 2020 Cartesi Pte. Ltd.
+
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+// Note: This component currently has dependencies that are licensed under the GNU
+// GPL, version 3, and so you should treat this component as a whole as being under
+// the GPL version 3. But all Cartesi-written code in this component is licensed
+// under the Apache License, version 2, or a compatible permissive license, and can
+// be used independently under the Apache v2 license. After this component is
+// rewritten, the entire component will be released under the Apache v2 license.
+
+extern crate dispatcher;
+extern crate ethereum_types;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+#[macro_use]
+extern crate tournament_derive;
+
+use dispatcher::{
+    AddressArray3, AddressField, BoolField, Bytes32Array3, Bytes32Field, String32Field, U256Array3,
+    U256Field,
+};
+use ethereum_types::{Address, H256, U256};
+
+#[derive(Serialize, Debug, Clone, PartialEq, DAppContext)]
+#[get_state(
+    _uintValues(U256Array3),
+    _addressValues(AddressArray3),
+    _bytesValues(Bytes32Array3),
+    index(U256Field),
+    owner(AddressField),
+    root(Bytes32Field),
+    registered(BoolField),
+    currentState(String32Field)
+)]
+struct EveryKindCtx {
+    #[slot(_uintValues)]
+    first: U256,
+    #[slot(_uintValues)]
+    second: U256,
+    #[slot(_uintValues)]
+    third: U256,
+    #[slot(_addressValues)]
+    challenger: Address,
+    #[slot(_addressValues)]
+    claimer: Address,
+    #[slot(_addressValues)]
+    machine: Address,
+    #[slot(_bytesValues)]
+    log_hash: H256,
+    #[slot(_bytesValues)]
+    initial_hash: H256,
+    #[slot(_bytesValues)]
+    final_hash: H256,
+    #[slot(index)]
+    index: U256,
+    #[slot(owner)]
+    owner: Address,
+    #[slot(root)]
+    root: H256,
+    #[slot(registered)]
+    registered: bool,
+    #[slot(currentState)]
+    current_state: String,
+}
+
+fn sample() -> EveryKindCtx {
+    EveryKindCtx {
+        first: U256::from(1),
+        second: U256::from(1_000_000_000_000u64),
+        third: U256::max_value(),
+        challenger: Address::from([1; 20]),
+        claimer: Address::from([2; 20]),
+        machine: Address::from([3; 20]),
+        log_hash: H256::from([4; 32]),
+        initial_hash: H256::from([5; 32]),
+        final_hash: H256::from([6; 32]),
+        index: U256::from(7),
+        owner: Address::from([8; 20]),
+        root: H256::from([9; 32]),
+        registered: true,
+        current_state: "WaitingChallenge".to_string(),
+    }
+}
+
+#[test]
+fn to_json_data_round_trips_through_parsed() {
+    let ctx = sample();
+    let parsed: EveryKindCtxParsed = serde_json::from_str(&ctx.to_json_data()).unwrap();
+    let back: EveryKindCtx = parsed.into();
+    assert_eq!(back, ctx);
+}
+
+#[test]
+fn to_json_data_follows_the_get_state_layout() {
+    let json: serde_json::Value = serde_json::from_str(&sample().to_json_data()).unwrap();
+    let layout: Vec<(&str, &str)> = json
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| (v["name"].as_str().unwrap(), v["type"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        layout,
+        vec![
+            ("_uintValues", "uint256[3]"),
+            ("_addressValues", "address[3]"),
+            ("_bytesValues", "bytes32[3]"),
+            ("index", "uint256"),
+            ("owner", "address"),
+            ("root", "bytes32"),
+            ("registered", "bool"),
+            ("currentState", "bytes32"),
+        ]
+    );
+    assert_eq!(json[2]["value"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn string32_is_a_zero_padded_bytes32() {
+    let json: serde_json::Value = serde_json::from_str(&sample().to_json_data()).unwrap();
+    let state = json[7]["value"].as_str().unwrap();
+    assert_eq!(state.len(), 2 + 64);
+    assert!(state.starts_with(&format!("0x{}", "57616974696e674368616c6c656e6765")));
+    assert!(state.ends_with(&"0".repeat(32)));
+}